@@ -0,0 +1,1221 @@
+use std::cell::{OnceCell, RefCell};
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::error::{nargs_check, Exception, Kind};
+use crate::ns;
+use crate::stack;
+use crate::symbol::{self, Symbol};
+use crate::types::Type;
+use crate::vm::Chunk;
+use crate::{def_name, expand_once, quasiquote, syntax_quote};
+
+/// Analyzed code. Special forms are parsed once, when the code is
+/// analyzed, and references to local variables are replaced by their
+/// lexical address.
+#[derive(Debug)]
+pub enum Node {
+    Constant(Type),
+    /// Symbol looked up by name: globals and symbols of unknown scope
+    Global(Symbol),
+    /// Local variable by its lexical address
+    Local {
+        symbol: Symbol,
+        depth: usize,
+        slot: usize,
+    },
+    Vector(Vec<Rc<Node>>),
+    HashMap(Vec<(String, Rc<Node>)>),
+    Def {
+        name: Symbol,
+        dynamic: bool,
+        value: Rc<Node>,
+    },
+    DefMacro {
+        name: Symbol,
+        value: Rc<Node>,
+    },
+    /// `(declare name...)`, defines the names not defined yet to nil
+    Declare(Vec<Symbol>),
+    Let {
+        bindings: Vec<(Symbol, Rc<Node>)>,
+        body: Rc<Node>,
+    },
+    /// `loop`, a `let*` whose body can be run again by `recur`
+    Loop {
+        bindings: Vec<(Symbol, Rc<Node>)>,
+        body: Rc<Node>,
+    },
+    /// `recur` in tail position of a loop or closure body. It runs the
+    /// body again with its locals, the ones of the scope depth levels
+    /// out, bound to the values of the arguments.
+    Recur {
+        args: Vec<Rc<Node>>,
+        depth: usize,
+        slots: Rc<[Symbol]>,
+        target: Target,
+    },
+    Do(Vec<Rc<Node>>),
+    If {
+        cond: Rc<Node>,
+        then: Rc<Node>,
+        otherwise: Option<Rc<Node>>,
+    },
+    Fn(Rc<Lambda>),
+    Eval(Rc<Node>),
+    Macroexpand(Type),
+    /// `(macroexpand-1 form)`, expands a macro call once
+    Macroexpand1(Type),
+    /// `(macroexpand-all form)`, expands the macro calls nested in the
+    /// form too
+    MacroexpandAll(Type),
+    Try {
+        body: Rc<Node>,
+        /// `catch*` clauses, tried in order
+        catches: Vec<Catch>,
+        finally: Option<Rc<Node>>,
+    },
+    Ns {
+        name: Symbol,
+        clauses: Vec<Type>,
+    },
+    LoadFile(Rc<Node>),
+    SaveImage(Rc<Node>),
+    Require(Vec<Rc<Node>>),
+    Binding {
+        vars: Vec<(Symbol, Rc<Node>)>,
+        body: Rc<Node>,
+    },
+    /// Function call, the function comes first
+    Call(Vec<Rc<Node>>),
+    /// Call of a macro, or of a symbol not bound when the code was
+    /// analyzed (it could be a macro defined later). It's macroexpanded
+    /// the first time it's evaluated and the analyzed expansion is
+    /// cached until one of the macros it was expanded with is
+    /// redefined.
+    MacroCall {
+        form: Type,
+        /// Target of the `recur` forms of the expansion
+        recur: Option<Recur>,
+        expansion: RefCell<Option<Rc<Expansion>>>,
+    },
+    /// `(yield value)`, suspends the generator being run
+    Yield(Rc<Node>),
+    /// `(reset body)`, delimits the continuations captured by `shift`
+    Reset {
+        body: Rc<Node>,
+        /// Bytecode of the body, it's always run by the VM
+        compiled: OnceCell<Rc<Chunk>>,
+    },
+    /// `(shift k body)`, evaluates body in place of the innermost
+    /// `reset` with the continuation up to it bound to var
+    Shift {
+        var: Symbol,
+        body: Rc<Node>,
+        compiled: OnceCell<Rc<Chunk>>,
+    },
+    /// `(break)`, stops in the debugger
+    Break,
+    /// `(trace sym...)`, traces the calls of the closures bound to the
+    /// symbols
+    Trace(Vec<Rc<Node>>),
+    /// `(untrace sym...)`, stops tracing them, or every closure without
+    /// symbols
+    Untrace(Vec<Rc<Node>>),
+    /// Malformed special form, it fails when evaluated
+    Error(Exception),
+}
+
+/// `catch*` clause of a `try*`, its handler is evaluated with the
+/// exception bound to var
+#[derive(Debug)]
+pub struct Catch {
+    pub selector: Selector,
+    pub var: Symbol,
+    pub handler: Rc<Node>,
+}
+
+/// Exceptions caught by a `catch*` clause
+#[derive(Debug)]
+pub enum Selector {
+    /// Every exception, without selector or with `:default`
+    All,
+    /// Builtin exceptions of a kind
+    Kind(Kind),
+    /// Exceptions whose value the function satisfies
+    Predicate(Rc<Node>),
+}
+
+/// Symbols bound by a `let*` or a `loop` and their values
+type Bindings = Vec<(Symbol, Rc<Node>)>;
+
+/// What `recur` runs again
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Loop,
+    /// Body of the closure being called
+    Fn,
+}
+
+/// Analyzed expansion of a macro call
+#[derive(Debug)]
+pub struct Expansion {
+    /// Symbols at the head of the forms expanded, with the macros they
+    /// were bound to. The head of the final form isn't bound to one.
+    macros: Vec<(Symbol, Option<Macro>)>,
+    pub node: Rc<Node>,
+    /// Bytecode of the expansion, compiled the first time the VM runs
+    /// it
+    pub compiled: OnceCell<Rc<Chunk>>,
+}
+
+/// Environment and lambda of a macro, they tell it apart from the
+/// macros defined again with the same name
+type Macro = (Rc<Env>, Rc<Lambda>);
+
+impl Expansion {
+    /// Return true if the symbols are still bound to the macros the
+    /// call was expanded with
+    fn is_current(&self, env: &Rc<Env>) -> bool {
+        self.macros
+            .iter()
+            .all(|(symbol, old)| match (old, bound_macro(*symbol, env)) {
+                (Some((old_env, old_lambda)), Some((env, lambda))) => {
+                    Rc::ptr_eq(old_env, &env) && Rc::ptr_eq(old_lambda, &lambda)
+                }
+                (old, new) => old.is_none() && new.is_none(),
+            })
+    }
+}
+
+/// Return the macro symbol is bound to in env, if it's bound to one
+fn bound_macro(symbol: Symbol, env: &Rc<Env>) -> Option<Macro> {
+    match env.get(symbol) {
+        Ok(Type::Closure {
+            env,
+            lambda,
+            is_macro: true,
+        }) => Some((env, lambda)),
+        _ => None,
+    }
+}
+
+/// Loop or closure body the code being analyzed can `recur` to
+#[derive(Debug, Clone)]
+pub struct Recur {
+    target: Target,
+    /// Locals bound again, in slot order
+    slots: Rc<[Symbol]>,
+    /// Index of the scope of the locals in the static scopes. It's
+    /// negative in macro expansions, whose static scopes start inside
+    /// the scope of the macro call.
+    scope: isize,
+    /// The code is in tail position of the body
+    tail: bool,
+}
+
+/// Parameters and body of a `fn*` form
+#[derive(Debug)]
+pub struct Lambda {
+    pub params: Type,
+    pub body: Type,
+    /// Symbols bound by the parameters, `&` included
+    pub binds: Vec<Symbol>,
+    node: OnceCell<Rc<Node>>,
+    /// Bytecode of the body, compiled the first time the VM calls it
+    pub compiled: OnceCell<Rc<Chunk>>,
+    /// Name the closure was first defined with, for stack traces
+    name: OnceCell<Symbol>,
+    /// Calls are printed by the tracer
+    traced: bool,
+}
+
+impl Lambda {
+    /// Create a lambda whose body is analyzed the first time it's
+    /// called, like the ones restored from images
+    pub fn new(params: Type, body: Type) -> Lambda {
+        let binds = match params {
+            Type::List(ref params) | Type::Vector(ref params) => params
+                .iter()
+                .filter_map(|param| match param {
+                    Type::Symbol(symbol) => Some(*symbol),
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
+        };
+        Lambda {
+            params,
+            body,
+            binds,
+            node: OnceCell::new(),
+            compiled: OnceCell::new(),
+            name: OnceCell::new(),
+            traced: false,
+        }
+    }
+
+    /// Return a copy of the lambda whose calls are traced
+    pub fn traced_copy(&self) -> Lambda {
+        let lambda = Lambda {
+            traced: true,
+            ..Lambda::new(self.params.clone(), self.body.clone())
+        };
+        if let Some(name) = self.name() {
+            lambda.set_name(name);
+        }
+        lambda
+    }
+
+    /// Return true if the calls of the closure are traced
+    pub fn is_traced(&self) -> bool {
+        self.traced
+    }
+
+    /// Fail unless the closure takes nargs arguments
+    pub fn check_nargs(&self, nargs: usize) -> Result<(), Exception> {
+        let fun_name = || match self.name() {
+            Some(name) => name.to_string(),
+            None => String::from("fn*"),
+        };
+        match self
+            .binds
+            .iter()
+            .position(|bind| *bind == symbol::AMPERSAND)
+        {
+            Some(required) if nargs < required => Err(Exception::variadic_nargs_error(
+                &fun_name(),
+                required,
+                nargs,
+            )),
+            Some(_) => Ok(()),
+            None => nargs_check(&fun_name(), self.binds.len(), nargs),
+        }
+    }
+
+    /// Return the name the closure was first defined with
+    pub fn name(&self) -> Option<Symbol> {
+        self.name.get().copied()
+    }
+
+    /// Name an anonymous closure, named ones keep their name
+    pub fn set_name(&self, name: Symbol) {
+        let _ = self.name.set(name);
+    }
+
+    /// Return the analyzed body of a closure over env
+    pub fn body_node(&self, env: &Rc<Env>) -> Rc<Node> {
+        self.node
+            .get_or_init(|| {
+                let slots = param_slots(&self.binds);
+                let mut analyzer = Analyzer {
+                    recur: Some(Recur {
+                        target: Target::Fn,
+                        slots: slots.clone().into(),
+                        scope: 0,
+                        tail: true,
+                    }),
+                    scopes: vec![slots],
+                    env,
+                    misplaced_recur: None,
+                };
+                let body = analyzer.analyze_tail(&self.body);
+                analyzer.checked(body)
+            })
+            .clone()
+    }
+}
+
+/// Analyze a form evaluated in env
+pub fn analyze(ast: &Type, env: &Rc<Env>) -> Rc<Node> {
+    let mut analyzer = Analyzer {
+        scopes: vec![],
+        env,
+        recur: None,
+        misplaced_recur: None,
+    };
+    let node = analyzer.analyze(ast);
+    analyzer.checked(node)
+}
+
+/// Return the analyzed expansion of a macro call node evaluated in
+/// env, expanding and analyzing it the first time and after its
+/// macros are redefined
+pub fn expansion(node: &Node, env: &Rc<Env>) -> Result<Rc<Expansion>, Exception> {
+    let (form, recur, expansion) = match node {
+        Node::MacroCall {
+            form,
+            recur,
+            expansion,
+        } => (form, recur, expansion),
+        _ => unreachable!("only macro calls are expanded"),
+    };
+    if let Some(expanded) = &*expansion.borrow() {
+        if expanded.is_current(env) {
+            return Ok(expanded.clone());
+        }
+    }
+
+    // expanded like macroexpand does, recording the macros
+    let mut expanded = form.clone();
+    let mut macros = vec![];
+    while let Type::List(list) = &expanded {
+        let head = match list.first() {
+            Some(Type::Symbol(symbol)) => *symbol,
+            _ => break,
+        };
+        let bound = bound_macro(head, env);
+        let is_macro = bound.is_some();
+        macros.push((head, bound));
+        if !is_macro {
+            break;
+        }
+        expanded = expand_once(expanded, env)?;
+    }
+    let mut analyzer = Analyzer {
+        scopes: vec![],
+        env,
+        recur: recur.clone(),
+        misplaced_recur: None,
+    };
+    let expanded = match expanded {
+        // the expansion is final: a call is compiled as such even if
+        // the function isn't bound yet
+        Type::List(ref list) if !list.is_empty() => analyzer.list(&expanded, list, true),
+        _ => analyzer.analyze_tail(&expanded),
+    };
+    let expanded = Rc::new(Expansion {
+        macros,
+        node: analyzer.checked(expanded),
+        compiled: OnceCell::new(),
+    });
+    *expansion.borrow_mut() = Some(expanded.clone());
+    Ok(expanded)
+}
+
+/// Return true if symbol names a special form, or `&`. Syntax quote
+/// leaves them unqualified.
+pub fn is_special(symbol: Symbol) -> bool {
+    matches!(
+        symbol,
+        symbol::DEF
+            | symbol::LET
+            | symbol::LETFN
+            | symbol::LOOP
+            | symbol::RECUR
+            | symbol::DO
+            | symbol::IF
+            | symbol::FN
+            | symbol::EVAL
+            | symbol::QUOTE
+            | symbol::QUASIQUOTE
+            | symbol::QUASIQUOTEEXPAND
+            | symbol::SYNTAX_QUOTE
+            | symbol::UNQUOTE
+            | symbol::SPLICE_UNQUOTE
+            | symbol::DEFMACRO
+            | symbol::DECLARE
+            | symbol::MACROEXPAND
+            | symbol::MACROEXPAND_1
+            | symbol::MACROEXPAND_ALL
+            | symbol::NS
+            | symbol::LOAD_FILE
+            | symbol::SAVE_IMAGE
+            | symbol::REQUIRE
+            | symbol::BINDING
+            | symbol::TRY
+            | symbol::CATCH
+            | symbol::FINALLY
+            | symbol::AMPERSAND
+            | symbol::YIELD
+            | symbol::RESET
+            | symbol::SHIFT
+            | symbol::BREAK
+            | symbol::TRACE
+            | symbol::UNTRACE
+    )
+}
+
+/// Return the symbols bound by a parameter list, in slot order
+pub fn param_slots(params: &[Symbol]) -> Vec<Symbol> {
+    let mut slots = vec![];
+    for param in params {
+        if *param != symbol::AMPERSAND && !slots.contains(param) {
+            slots.push(*param);
+        }
+    }
+    slots
+}
+
+/// Analyzer of the code evaluated in env. The static scopes are the
+/// local scopes created by the code itself, innermost last. Each scope
+/// lists its symbols in slot order.
+struct Analyzer<'a> {
+    scopes: Vec<Vec<Symbol>>,
+    env: &'a Rc<Env>,
+    recur: Option<Recur>,
+    /// Error of the first misplaced `recur`, the whole code is
+    /// rejected
+    misplaced_recur: Option<Exception>,
+}
+
+impl Analyzer<'_> {
+    /// Return the analyzed code, or an error if it has a misplaced
+    /// `recur`
+    fn checked(&mut self, node: Rc<Node>) -> Rc<Node> {
+        match self.misplaced_recur.take() {
+            Some(exc) => Rc::new(Node::Error(exc)),
+            None => node,
+        }
+    }
+
+    /// Return the `::name` keyword name qualified with the namespace
+    /// where the code is analyzed
+    fn qualified(&self, name: &str) -> String {
+        format!("{}/{}", ns::name_of(self.env), name)
+    }
+
+    /// Return a hash map key, qualified if it's a `::name` keyword
+    fn qualified_key(&self, key: &str) -> String {
+        match key.strip_prefix("::") {
+            Some(name) => format!(":{}", self.qualified(name)),
+            None => key.to_string(),
+        }
+    }
+
+    /// Return quoted data with its `::name` keywords qualified, in
+    /// nested collections and hash map keys too. The data is walked
+    /// without recursion, like the reader builds it.
+    fn qualify_keywords(&self, form: &Type) -> Type {
+        enum Step<'a> {
+            Visit(&'a Type),
+            List(usize),
+            Vector(usize),
+            HashMap(Vec<String>),
+        }
+
+        let mut steps = vec![Step::Visit(form)];
+        let mut done: Vec<Type> = vec![];
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Visit(Type::Keyword(keyword)) if keyword.starts_with(':') => {
+                    done.push(Type::Keyword(self.qualified(&keyword[1..])))
+                }
+                Step::Visit(Type::List(items)) => {
+                    steps.push(Step::List(items.len()));
+                    steps.extend(items.iter().rev().map(Step::Visit));
+                }
+                Step::Visit(Type::Vector(items)) => {
+                    steps.push(Step::Vector(items.len()));
+                    steps.extend(items.iter().rev().map(Step::Visit));
+                }
+                Step::Visit(Type::HashMap(hash_map)) => {
+                    let (keys, values): (Vec<&String>, Vec<&Box<Type>>) = hash_map.iter().unzip();
+                    steps.push(Step::HashMap(
+                        keys.into_iter()
+                            .map(|key| self.qualified_key(key))
+                            .collect(),
+                    ));
+                    steps.extend(values.into_iter().rev().map(|value| Step::Visit(value)));
+                }
+                Step::Visit(value) => done.push(value.clone()),
+                Step::List(len) => {
+                    let items = done.split_off(done.len() - len);
+                    done.push(Type::List(items));
+                }
+                Step::Vector(len) => {
+                    let items = done.split_off(done.len() - len);
+                    done.push(Type::Vector(items));
+                }
+                Step::HashMap(keys) => {
+                    let values = done.split_off(done.len() - keys.len());
+                    done.push(Type::HashMap(
+                        keys.into_iter()
+                            .zip(values.into_iter().map(Box::new))
+                            .collect(),
+                    ));
+                }
+            }
+        }
+        done.pop().unwrap()
+    }
+
+    /// Bind a symbol in the innermost scope the same way Env::bind
+    /// does: a new slot unless it's already bound there
+    fn bind(&mut self, symbol: Symbol) {
+        let scope = self.scopes.last_mut().unwrap();
+        if !scope.contains(&symbol) {
+            scope.push(symbol);
+        }
+    }
+
+    /// Return the lexical address of a symbol. The local environments
+    /// of env (up to its namespace) are looked up after the static
+    /// scopes.
+    fn lookup(&self, symbol: Symbol) -> Option<(usize, usize)> {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(slot) = scope.iter().position(|name| *name == symbol) {
+                return Some((depth, slot));
+            }
+        }
+
+        let mut depth = self.scopes.len();
+        let mut env = self.env;
+        while !env.is_namespace() {
+            if let Some(slot) = env.slot_of(symbol) {
+                return Some((depth, slot));
+            }
+            env = env.outer()?;
+            depth += 1;
+        }
+
+        None
+    }
+
+    /// Analyze a form that isn't in tail position, `recur` can't be
+    /// used in it
+    fn analyze(&mut self, ast: &Type) -> Rc<Node> {
+        let recur = self.recur.clone();
+        if let Some(ref mut recur) = self.recur {
+            recur.tail = false;
+        }
+        let node = self.analyze_tail(ast);
+        self.recur = recur;
+        node
+    }
+
+    /// Analyze a form in tail position of the body `recur` runs again
+    /// when the analyzer has a recur target
+    fn analyze_tail(&mut self, ast: &Type) -> Rc<Node> {
+        let _depth = match stack::enter() {
+            Ok(depth) => depth,
+            Err(exc) => return Rc::new(Node::Error(exc)),
+        };
+        let node = match ast {
+            Type::Symbol(symbol) => match self.lookup(*symbol) {
+                Some((depth, slot)) => Node::Local {
+                    symbol: *symbol,
+                    depth,
+                    slot,
+                },
+                None => Node::Global(*symbol),
+            },
+            Type::Keyword(keyword) if keyword.starts_with(':') => {
+                Node::Constant(Type::Keyword(self.qualified(&keyword[1..])))
+            }
+            Type::List(list) if !list.is_empty() => return self.list(ast, list, false),
+            // a caught form given to eval
+            Type::Vector(vector) => Node::Vector(self.analyze_all(vector)),
+            Type::HashMap(hash_map) => Node::HashMap(
+                hash_map
+                    .iter()
+                    .map(|(key, value)| (self.qualified_key(key), self.analyze(value)))
+                    .collect(),
+            ),
+            _ => Node::Constant(ast.clone()),
+        };
+        Rc::new(node)
+    }
+
+    fn analyze_all(&mut self, forms: &[Type]) -> Vec<Rc<Node>> {
+        forms.iter().map(|form| self.analyze(form)).collect()
+    }
+
+    /// Analyze a non empty list. Malformed special forms are analyzed
+    /// to an error node. If expanded is true, the list is the final
+    /// expansion of a macro call and it's never deferred.
+    fn list(&mut self, ast: &Type, list: &[Type], expanded: bool) -> Rc<Node> {
+        let head = match list[0] {
+            Type::Symbol(head) => head,
+            _ => return Rc::new(Node::Call(self.analyze_all(list))),
+        };
+        let nargs = list.len() - 1;
+
+        let node = match head {
+            symbol::DEF => self.def(list),
+            symbol::LET => self.let_form(list),
+            symbol::LETFN => self.letfn(list),
+            symbol::LOOP => self.loop_form(list),
+            symbol::RECUR => self.recur(list),
+            symbol::DO if nargs == 0 => Ok(Node::Constant(Type::Nil)),
+            symbol::DO => {
+                let mut forms = self.analyze_all(&list[1..nargs]);
+                forms.push(self.analyze_tail(&list[nargs]));
+                Ok(Node::Do(forms))
+            }
+            symbol::IF => self.if_form(list),
+            symbol::FN => self.fn_form(list),
+            symbol::EVAL => {
+                nargs_check("eval", 1, nargs).map(|_| Node::Eval(self.analyze(&list[1])))
+            }
+            symbol::QUOTE => nargs_check("quote", 1, nargs)
+                .map(|_| Node::Constant(self.qualify_keywords(&list[1]))),
+            symbol::QUASIQUOTE if nargs > 0 => match quasiquote(list[1].clone()) {
+                Ok(expanded) => return self.analyze_tail(&expanded),
+                Err(exc) => Err(exc),
+            },
+            symbol::QUASIQUOTEEXPAND if nargs > 0 => {
+                quasiquote(list[1].clone()).map(Node::Constant)
+            }
+            symbol::SYNTAX_QUOTE if nargs > 0 => match syntax_quote(list[1].clone(), self.env) {
+                Ok(expanded) => return self.analyze_tail(&expanded),
+                Err(exc) => Err(exc),
+            },
+            symbol::QUASIQUOTE | symbol::QUASIQUOTEEXPAND | symbol::SYNTAX_QUOTE => {
+                Err(Exception::nargs_error(&head.name(), 1, nargs))
+            }
+            symbol::DEFMACRO => self.defmacro(list),
+            symbol::DECLARE => self.declare(list),
+            symbol::MACROEXPAND => {
+                nargs_check("macroexpand", 1, nargs).map(|_| Node::Macroexpand(list[1].clone()))
+            }
+            symbol::MACROEXPAND_1 => {
+                nargs_check("macroexpand-1", 1, nargs).map(|_| Node::Macroexpand1(list[1].clone()))
+            }
+            symbol::MACROEXPAND_ALL => nargs_check("macroexpand-all", 1, nargs)
+                .map(|_| Node::MacroexpandAll(list[1].clone())),
+            symbol::NS => self.ns(list),
+            symbol::LOAD_FILE => {
+                nargs_check("load-file", 1, nargs).map(|_| Node::LoadFile(self.analyze(&list[1])))
+            }
+            symbol::SAVE_IMAGE => {
+                nargs_check("save-image", 1, nargs).map(|_| Node::SaveImage(self.analyze(&list[1])))
+            }
+            symbol::REQUIRE => Ok(Node::Require(self.analyze_all(&list[1..]))),
+            symbol::BINDING => self.binding(list),
+            symbol::TRY => self.try_form(list),
+            symbol::RESET => nargs_check("reset", 1, nargs).map(|_| Node::Reset {
+                body: self.analyze(&list[1]),
+                compiled: OnceCell::new(),
+            }),
+            symbol::SHIFT => self.shift(list),
+            symbol::YIELD => {
+                nargs_check("yield", 1, nargs).map(|_| Node::Yield(self.analyze(&list[1])))
+            }
+            symbol::BREAK => nargs_check("break", 0, nargs).map(|_| Node::Break),
+            symbol::TRACE => Ok(Node::Trace(self.analyze_all(&list[1..]))),
+            symbol::UNTRACE => Ok(Node::Untrace(self.analyze_all(&list[1..]))),
+
+            _ if expanded || self.lookup(head).is_some() => Ok(Node::Call(self.analyze_all(list))),
+            _ => match self.env.get(head) {
+                Ok(value) if !value.is_macro() => Ok(Node::Call(self.analyze_all(list))),
+                _ => Ok(Node::MacroCall {
+                    form: ast.clone(),
+                    recur: self.recur.clone().map(|recur| Recur {
+                        scope: recur.scope - self.scopes.len() as isize,
+                        ..recur
+                    }),
+                    expansion: RefCell::new(None),
+                }),
+            },
+        };
+
+        Rc::new(node.unwrap_or_else(Node::Error))
+    }
+
+    fn misplaced(&mut self, message: &str) -> Exception {
+        let exc = Exception::builtin(message);
+        self.misplaced_recur.get_or_insert_with(|| exc.clone());
+        exc
+    }
+
+    fn def(&mut self, list: &[Type]) -> Result<Node, Exception> {
+        nargs_check("def!", 2, list.len() - 1)?;
+
+        let (name, dynamic) = def_name(&list[1])?;
+        Ok(Node::Def {
+            name,
+            dynamic,
+            value: self.analyze(&list[2]),
+        })
+    }
+
+    fn let_form(&mut self, list: &[Type]) -> Result<Node, Exception> {
+        let (bindings, body) = self.bindings_and_body("let*", list, None)?;
+        Ok(Node::Let { bindings, body })
+    }
+
+    /// Analyze `letfn` as a `let*` of closures whose names are all
+    /// bound before their bodies are analyzed
+    fn letfn(&mut self, list: &[Type]) -> Result<Node, Exception> {
+        // (letfn [(name params body) ...] body)
+        nargs_check("letfn", 2, list.len() - 1)?;
+
+        let fns = match list[1] {
+            Type::List(ref seq) | Type::Vector(ref seq) => seq,
+            _ => return Err(Exception::type_error("first letfn argument must be a list")),
+        };
+        let mut names = Vec::with_capacity(fns.len());
+        for function in fns.iter() {
+            match function {
+                Type::List(function) if function.len() == 3 => match function[0] {
+                    Type::Symbol(name) => names.push(name),
+                    _ => return Err(Exception::builtin("letfn function names must be symbols")),
+                },
+                _ => {
+                    return Err(Exception::builtin(
+                        "letfn functions must be (name params body) lists",
+                    ))
+                }
+            }
+        }
+
+        self.scopes.push(vec![]);
+        for name in &names {
+            self.bind(*name);
+        }
+        let mut bindings = Vec::with_capacity(fns.len());
+        for (name, function) in names.iter().zip(fns.iter()) {
+            let function = match function {
+                Type::List(function) => function,
+                _ => unreachable!("letfn functions are lists"),
+            };
+            let form = [
+                Type::Symbol(symbol::FN),
+                function[1].clone(),
+                function[2].clone(),
+            ];
+            let value = match self.fn_form(&form) {
+                Ok(Node::Fn(lambda)) => {
+                    lambda.set_name(*name);
+                    Node::Fn(lambda)
+                }
+                Ok(_) => unreachable!("fn* forms are analyzed to closures"),
+                Err(exc) => {
+                    self.scopes.pop();
+                    return Err(exc);
+                }
+            };
+            bindings.push((*name, Rc::new(value)));
+        }
+        let body = self.analyze_tail(&list[2]);
+        self.scopes.pop();
+
+        Ok(Node::Let { bindings, body })
+    }
+
+    fn loop_form(&mut self, list: &[Type]) -> Result<Node, Exception> {
+        let (bindings, body) = self.bindings_and_body("loop", list, Some(Target::Loop))?;
+        Ok(Node::Loop { bindings, body })
+    }
+
+    /// Analyze the bindings and the body of a `let*` or a `loop`. The
+    /// body of a loop is the target of its `recur` forms.
+    fn bindings_and_body(
+        &mut self,
+        name: &str,
+        list: &[Type],
+        target: Option<Target>,
+    ) -> Result<(Bindings, Rc<Node>), Exception> {
+        nargs_check(name, 2, list.len() - 1)?;
+
+        let binding_list = match list[1] {
+            Type::List(ref seq) | Type::Vector(ref seq) => seq,
+            _ => {
+                return Err(Exception::type_error(&format!(
+                    "first {} argument must be a list",
+                    name
+                )))
+            }
+        };
+        if binding_list.len() % 2 != 0 {
+            return Err(Exception::builtin(&format!(
+                "{} binding list must be composed of pairs",
+                name
+            )));
+        }
+
+        self.scopes.push(vec![]);
+        let mut bindings = Vec::with_capacity(binding_list.len() / 2);
+        for binding in binding_list.chunks(2) {
+            let symbol = match binding[0] {
+                Type::Symbol(symbol) => symbol,
+                _ => {
+                    self.scopes.pop();
+                    return Err(Exception::builtin(&format!(
+                        "{} variable names must be symbols",
+                        name
+                    )));
+                }
+            };
+            bindings.push((symbol, self.analyze(&binding[1])));
+            self.bind(symbol);
+        }
+
+        let body = match target {
+            Some(target) => {
+                let recur = Recur {
+                    target,
+                    slots: bindings.iter().map(|(symbol, _)| *symbol).collect(),
+                    scope: self.scopes.len() as isize - 1,
+                    tail: true,
+                };
+                let outer = self.recur.replace(recur);
+                let body = self.analyze_tail(&list[2]);
+                self.recur = outer;
+                body
+            }
+            None => self.analyze_tail(&list[2]),
+        };
+        self.scopes.pop();
+
+        Ok((bindings, body))
+    }
+
+    fn recur(&mut self, list: &[Type]) -> Result<Node, Exception> {
+        let recur = match self.recur {
+            Some(ref recur) if recur.tail => recur.clone(),
+            Some(_) => return Err(self.misplaced("recur must be in tail position")),
+            None => return Err(self.misplaced("recur must be in a loop or fn* body")),
+        };
+        nargs_check("recur", recur.slots.len(), list.len() - 1)?;
+
+        Ok(Node::Recur {
+            args: self.analyze_all(&list[1..]),
+            depth: (self.scopes.len() as isize - 1 - recur.scope) as usize,
+            slots: recur.slots,
+            target: recur.target,
+        })
+    }
+
+    fn if_form(&mut self, list: &[Type]) -> Result<Node, Exception> {
+        if list.len() < 3 || list.len() > 4 {
+            return Err(Exception::builtin("Malformed if expression"));
+        }
+
+        Ok(Node::If {
+            cond: self.analyze(&list[1]),
+            then: self.analyze_tail(&list[2]),
+            otherwise: list.get(3).map(|otherwise| self.analyze_tail(otherwise)),
+        })
+    }
+
+    fn fn_form(&mut self, list: &[Type]) -> Result<Node, Exception> {
+        nargs_check("fn*", 2, list.len() - 1)?;
+
+        if !list[1].is_sequence() {
+            return Err(Exception::builtin(
+                "fn* must be defined with a sequence as parameter",
+            ));
+        }
+
+        // the body is analyzed now, with the scopes it closes over
+        let lambda = Lambda::new(list[1].clone(), list[2].clone());
+        if let Some(rest) = lambda
+            .binds
+            .iter()
+            .position(|bind| *bind == symbol::AMPERSAND)
+        {
+            if lambda.binds.len() != rest + 2 {
+                return Err(Exception::builtin(
+                    "fn* must have exactly one parameter name after an &",
+                ));
+            }
+        }
+        let slots = param_slots(&lambda.binds);
+        let recur = Recur {
+            target: Target::Fn,
+            slots: slots.clone().into(),
+            scope: self.scopes.len() as isize,
+            tail: true,
+        };
+        self.scopes.push(slots);
+        let outer = self.recur.replace(recur);
+        let body = self.analyze_tail(&lambda.body);
+        self.recur = outer;
+        self.scopes.pop();
+        let _ = lambda.node.set(body);
+
+        Ok(Node::Fn(Rc::new(lambda)))
+    }
+
+    fn defmacro(&mut self, list: &[Type]) -> Result<Node, Exception> {
+        nargs_check("defmacro!", 2, list.len() - 1)?;
+
+        match list[1] {
+            Type::Symbol(name) => Ok(Node::DefMacro {
+                name,
+                value: self.analyze(&list[2]),
+            }),
+            _ => Err(Exception::type_error(
+                "First defmacro! argument must be a symbol",
+            )),
+        }
+    }
+
+    fn declare(&mut self, list: &[Type]) -> Result<Node, Exception> {
+        list[1..]
+            .iter()
+            .map(|name| match name {
+                Type::Symbol(name) => Ok(*name),
+                _ => Err(Exception::type_error("'declare' arguments must be symbols")),
+            })
+            .collect::<Result<_, _>>()
+            .map(Node::Declare)
+    }
+
+    fn shift(&mut self, list: &[Type]) -> Result<Node, Exception> {
+        nargs_check("shift", 2, list.len() - 1)?;
+        let var = match list[1] {
+            Type::Symbol(var) => var,
+            _ => {
+                return Err(Exception::type_error(
+                    "shift continuation name must be a symbol",
+                ))
+            }
+        };
+        self.scopes.push(vec![var]);
+        let body = self.analyze(&list[2]);
+        self.scopes.pop();
+        Ok(Node::Shift {
+            var,
+            body,
+            compiled: OnceCell::new(),
+        })
+    }
+
+    fn ns(&mut self, list: &[Type]) -> Result<Node, Exception> {
+        // (ns name (:require [other :as o :refer [a b]]))
+        if list.len() < 2 {
+            return Err(Exception::type_error("'ns' takes at least one argument"));
+        }
+
+        match list[1] {
+            Type::Symbol(name) => Ok(Node::Ns {
+                name,
+                clauses: list[2..].to_vec(),
+            }),
+            _ => Err(Exception::type_error("first ns argument must be a symbol")),
+        }
+    }
+
+    fn binding(&mut self, list: &[Type]) -> Result<Node, Exception> {
+        // (binding [var value ...] body ...)
+        if list.len() < 3 {
+            return Err(Exception::type_error(
+                "'binding' takes a binding vector and a body",
+            ));
+        }
+
+        let bindings = match list[1] {
+            Type::List(ref seq) | Type::Vector(ref seq) if seq.len() % 2 == 0 => seq,
+            Type::List(_) | Type::Vector(_) => {
+                return Err(Exception::type_error(
+                    "binding vector must have an even number of elements",
+                ))
+            }
+            _ => return Err(Exception::type_error("binding vector must be a vector")),
+        };
+
+        // the vars are looked up by name when evaluated
+        let mut vars = Vec::with_capacity(bindings.len() / 2);
+        for binding in bindings.chunks(2) {
+            match binding[0] {
+                Type::Symbol(var) => vars.push((var, self.analyze(&binding[1]))),
+                _ => return Err(Exception::type_error("binding names must be symbols")),
+            }
+        }
+
+        // the body is an implicit do, not in tail position since the
+        // vars are restored after it
+        let body = match list.len() {
+            3 => self.analyze(&list[2]),
+            _ => Rc::new(Node::Do(self.analyze_all(&list[2..]))),
+        };
+        Ok(Node::Binding { vars, body })
+    }
+
+    fn try_form(&mut self, list: &[Type]) -> Result<Node, Exception> {
+        // (try* A (catch* :kind B C) ... (finally D))
+        if list.len() < 2 {
+            return Err(Exception::type_error("'try*' takes at least one argument"));
+        }
+
+        let body = self.analyze(&list[1]);
+        let mut catches = vec![];
+        let mut finally = None;
+        for (index, clause) in list[2..].iter().enumerate() {
+            let clause = match clause {
+                Type::List(ref seq) | Type::Vector(ref seq) if !seq.is_empty() => seq,
+                _ => return Err(Exception::type_error("catch* block must be a list")),
+            };
+            match clause[0] {
+                Type::Symbol(symbol::FINALLY) if index == list.len() - 3 => {
+                    nargs_check("finally", 1, clause.len() - 1)?;
+                    finally = Some(self.analyze(&clause[1]));
+                }
+                Type::Symbol(symbol::FINALLY) => {
+                    return Err(Exception::builtin("finally must be the last try* clause"))
+                }
+                Type::Symbol(symbol::CATCH) => catches.push(self.catch(clause)?),
+                _ => {
+                    return Err(Exception::builtin(
+                        "try* clauses must be catch* or finally blocks",
+                    ))
+                }
+            }
+        }
+
+        Ok(Node::Try {
+            body,
+            catches,
+            finally,
+        })
+    }
+
+    fn catch(&mut self, clause: &[Type]) -> Result<Catch, Exception> {
+        // (catch* B C) or (catch* selector B C)
+        let selector = match clause.len() - 1 {
+            2 => Selector::All,
+            3 => match clause[1] {
+                Type::Keyword(ref name) if name == "default" => Selector::All,
+                Type::Keyword(ref name) => match Kind::from_name(name) {
+                    Some(kind) => Selector::Kind(kind),
+                    None => {
+                        return Err(Exception::builtin(&format!(
+                            "unknown exception kind :{}",
+                            name
+                        )))
+                    }
+                },
+                ref predicate => Selector::Predicate(self.analyze(predicate)),
+            },
+            given => return Err(Exception::nargs_error("catch*", 2, given)),
+        };
+
+        let var = match clause[clause.len() - 2] {
+            Type::Symbol(var) => var,
+            _ => {
+                return Err(Exception::type_error(
+                    "catch* exception name must be a symbol",
+                ))
+            }
+        };
+        self.scopes.push(vec![var]);
+        let handler = self.analyze(&clause[clause.len() - 1]);
+        self.scopes.pop();
+
+        Ok(Catch {
+            selector,
+            var,
+            handler,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::read_str;
+
+    fn analyze_str(code: &str) -> Rc<Node> {
+        let env = Rc::new(Env::new_default());
+        analyze(&read_str(code).unwrap().unwrap(), &env)
+    }
+
+    fn lambda_body(code: &str) -> Rc<Node> {
+        match &*analyze_str(code) {
+            Node::Fn(lambda) => lambda.node.get().unwrap().clone(),
+            node => panic!("not a lambda: {:?}", node),
+        }
+    }
+
+    fn local(node: &Node) -> (String, usize, usize) {
+        match node {
+            Node::Local {
+                symbol,
+                depth,
+                slot,
+            } => (symbol.name().to_string(), *depth, *slot),
+            _ => panic!("not a local: {:?}", node),
+        }
+    }
+
+    #[test]
+    fn test_analyze_params_and_let() {
+        let body = lambda_body("(fn* (a & rest) (let* (b a) (+ b (count rest))))");
+        let (bindings, body) = match &*body {
+            Node::Let { bindings, body } => (bindings, body),
+            node => panic!("not a let*: {:?}", node),
+        };
+        assert_eq!(bindings[0].0, Symbol::new("b"));
+        assert_eq!(local(&bindings[0].1), ("a".to_string(), 1, 0));
+
+        let call = match &**body {
+            Node::Call(call) => call,
+            node => panic!("not a call: {:?}", node),
+        };
+        assert!(matches!(*call[0], Node::Global(symbol) if &*symbol.name() == "+"));
+        assert_eq!(local(&call[1]), ("b".to_string(), 0, 0));
+        match &*call[2] {
+            Node::Call(count) => assert_eq!(local(&count[1]), ("rest".to_string(), 1, 1)),
+            node => panic!("not a call: {:?}", node),
+        }
+    }
+
+    #[test]
+    fn test_quoted_and_unknown_forms() {
+        let body = lambda_body("(fn* (a) (quote a))");
+        assert!(matches!(*body, Node::Constant(Type::Symbol(_))));
+
+        let body = lambda_body("(fn* (a) (undefined-macro a))");
+        assert!(matches!(*body, Node::MacroCall { .. }));
+
+        let body = lambda_body("(fn* (a) (fn* (b) a))");
+        match &*body {
+            Node::Fn(lambda) => {
+                assert_eq!(local(lambda.node.get().unwrap()), ("a".to_string(), 1, 0))
+            }
+            node => panic!("not a lambda: {:?}", node),
+        }
+    }
+
+    #[test]
+    fn test_malformed_special_forms() {
+        for (code, error) in [
+            ("(if)", "Malformed if expression"),
+            (
+                "(let* (a) a)",
+                "let* binding list must be composed of pairs",
+            ),
+            (
+                "(def! 1 2)",
+                "Type error: first def! argument must be a symbol",
+            ),
+        ] {
+            match &*analyze_str(code) {
+                Node::Error(exc) => assert_eq!(exc.to_string(), error),
+                node => panic!("not an error: {:?}", node),
+            }
+        }
+    }
+
+    #[test]
+    fn test_recur_addresses_its_target() {
+        let body = lambda_body("(fn* (n) (loop (i n) (let* (j i) (recur j))))");
+        let body = match &*body {
+            Node::Loop { body, .. } => body,
+            node => panic!("not a loop: {:?}", node),
+        };
+        match &**body {
+            Node::Let { body, .. } => match &**body {
+                Node::Recur {
+                    depth,
+                    slots,
+                    target,
+                    ..
+                } => {
+                    assert_eq!((*depth, *target), (1, Target::Loop));
+                    assert_eq!(**slots, [Symbol::new("i")]);
+                }
+                node => panic!("not a recur: {:?}", node),
+            },
+            node => panic!("not a let*: {:?}", node),
+        }
+
+        // the whole form is rejected
+        for code in ["(fn* (n) (+ 1 (recur n)))", "(do (recur))"] {
+            assert!(matches!(*analyze_str(code), Node::Error(_)));
+        }
+    }
+}
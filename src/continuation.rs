@@ -0,0 +1,87 @@
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+use crate::error::{self, Exception, Payload};
+use crate::types::{Args, Ret, Type};
+use crate::vm::{self, Snapshot};
+
+/// Continuation captured by `call/cc` or `shift`
+pub struct Continuation {
+    /// Evaluation that captured it, alive while it's running
+    pub(crate) extent: Weak<()>,
+    /// State of the VM to run again, None if the continuation was
+    /// captured out of the VM and can only escape
+    pub(crate) snapshot: Option<Snapshot>,
+    /// The VM that captured it was running top level code
+    pub(crate) root: bool,
+    /// Captured by `shift`, up to the innermost `reset`. Calling it
+    /// returns the value of the `reset`.
+    pub(crate) delimited: bool,
+}
+
+impl fmt::Debug for Continuation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Continuation")
+    }
+}
+
+impl Continuation {
+    /// Return true if the evaluation that captured it is extent
+    pub fn captured_by(&self, extent: &Rc<()>) -> bool {
+        self.extent.as_ptr() == Rc::as_ptr(extent)
+    }
+
+    /// Call the continuation out of the VM. Delimited continuations
+    /// return, the others unwind to the evaluation that captured them.
+    pub fn invoke(self: &Rc<Continuation>, args: Args) -> Ret {
+        let value = argument(args)?;
+        match self.snapshot {
+            Some(ref snapshot) if self.delimited => vm::compose(snapshot, value),
+            _ => self.escape(value),
+        }
+    }
+
+    /// Unwind to the evaluation that captured the continuation, making
+    /// value its result. Out of the VM continuations aren't
+    /// re-entrant, they fail once that evaluation returned.
+    pub fn escape(self: &Rc<Continuation>, value: Type) -> Ret {
+        if self.extent.strong_count() == 0 {
+            return Err(Exception::builtin(
+                "continuation called after its evaluation returned",
+            ));
+        }
+        Err(Exception::escape(self.clone(), value))
+    }
+}
+
+/// Return the value a continuation is called with, nil by default
+pub fn argument(mut args: Args) -> Ret {
+    match args.len() {
+        0 => Ok(Type::Nil),
+        1 => Ok(args.pop().unwrap()),
+        _ => Err(Exception::type_error(
+            "a continuation takes zero or one argument",
+        )),
+    }
+}
+
+/// Call a function with the continuation of the call. The VM captures
+/// the whole continuation itself, this one only escapes.
+pub fn call_cc(args: Args) -> Ret {
+    error::nargs_check("call/cc", 1, args.len())?;
+
+    let extent = Rc::new(());
+    let continuation = Rc::new(Continuation {
+        extent: Rc::downgrade(&extent),
+        snapshot: None,
+        root: false,
+        delimited: false,
+    });
+    match args[0].apply(vec![Type::Continuation(continuation)]) {
+        Err(Exception {
+            payload: Payload::Escape(continuation, value),
+            ..
+        }) if continuation.captured_by(&extent) => Ok(value),
+        result => result,
+    }
+}
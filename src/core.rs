@@ -2,14 +2,22 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{Read, Write};
+use std::path::Path;
 use std::rc::Rc;
+use std::time::Duration;
 
-use crate::env::Env;
+use crate::continuation;
+use crate::debug;
 use crate::error;
 use crate::error::Exception;
-use crate::eval;
+use crate::event::{self, Promise};
+use crate::generator::Generator;
+use crate::module;
+use crate::ns;
 use crate::printer::pr_str;
 use crate::reader::read_str;
+use crate::sandbox;
+use crate::symbol::Symbol;
 use crate::types::{Args, Function, Ret, Type};
 
 pub struct Namespace {
@@ -56,6 +64,13 @@ impl Namespace {
         ns.data.insert(String::from("first"), first);
         ns.data.insert(String::from("rest"), rest);
         ns.data.insert(String::from("throw"), throw);
+        ns.data.insert(String::from("ex-stack"), ex_stack);
+        ns.data.insert(String::from("ex-info"), ex_info);
+        ns.data.insert(String::from("ex-message"), ex_message);
+        ns.data.insert(String::from("ex-data"), ex_data);
+        ns.data.insert(String::from("ex-cause"), ex_cause);
+        ns.data
+            .insert(String::from("debug-on-exception!"), debug_on_exception);
         ns.data.insert(String::from("apply"), apply);
         ns.data.insert(String::from("map"), map);
         ns.data.insert(String::from("nil?"), nilp);
@@ -63,6 +78,7 @@ impl Namespace {
         ns.data.insert(String::from("false?"), falsep);
         ns.data.insert(String::from("symbol?"), symbolp);
         ns.data.insert(String::from("symbol"), symbol);
+        ns.data.insert(String::from("gensym"), gensym);
         ns.data.insert(String::from("keyword?"), keywordp);
         ns.data.insert(String::from("keyword"), keyword);
         ns.data.insert(String::from("vector?"), vectorp);
@@ -84,6 +100,30 @@ impl Namespace {
         ns.data.insert(String::from("fn?"), fnp);
         ns.data.insert(String::from("macro?"), macrop);
         ns.data.insert(String::from("seq"), seq);
+        ns.data.insert(String::from("generator"), generator);
+        ns.data.insert(String::from("generator?"), generatorp);
+        ns.data.insert(String::from("next!"), next);
+        ns.data.insert(String::from("set-timeout"), set_timeout);
+        ns.data.insert(String::from("set-interval"), set_interval);
+        ns.data.insert(String::from("clear-timer"), clear_timer);
+        ns.data.insert(String::from("promise"), promise);
+        ns.data.insert(String::from("promise?"), promisep);
+        ns.data.insert(String::from("deliver"), deliver);
+        ns.data.insert(String::from("realized?"), realizedp);
+        ns.data.insert(String::from("future"), future);
+        ns.data.insert(String::from("then"), then);
+        ns.data.insert(String::from("ns-publics"), ns_publics);
+        ns.data.insert(String::from("resolve"), resolve);
+        ns.data.insert(String::from("bound?"), boundp);
+        ns.data.insert(String::from("ns-unmap"), ns_unmap);
+        ns.data.insert(String::from("load-file"), load_file);
+        ns.data
+            .insert(String::from("call/cc"), continuation::call_cc);
+        if !sandbox::io_allowed() {
+            for name in sandbox::IO_FUNCTIONS {
+                ns.data.remove(*name);
+            }
+        }
         ns
     }
 }
@@ -193,8 +233,8 @@ fn read_string(args: Args) -> Ret {
     match &args[0] {
         Type::String(input) => match read_str(input) {
             Ok(Some(s)) => Ok(s),
-            Ok(None) => Err(Exception::builtin("")),
-            Err(e) => Err(Exception::builtin(&e)),
+            Ok(None) => Err(Exception::reader_error("")),
+            Err(e) => Err(Exception::reader_error(&e)),
         },
         _ => Err(Exception::string_fun("read-string")),
     }
@@ -202,23 +242,17 @@ fn read_string(args: Args) -> Ret {
 
 fn slurp(args: Args) -> Ret {
     error::nargs_check("slurp", 1, args.len())?;
+    // also left out of the environments created in the sandbox
+    sandbox::check_io("slurp")?;
 
     match &args[0] {
         Type::String(ref filename) => {
             let mut file = File::open(filename).map_err(|exc| {
-                format!(
-                    "IOError trying to open \"{}\": {}",
-                    filename,
-                    exc.to_string()
-                )
+                Exception::io_error(&format!("IOError trying to open \"{}\": {}", filename, exc))
             })?;
             let mut contents = String::new();
             file.read_to_string(&mut contents).map_err(|exc| {
-                format!(
-                    "IOError trying to read \"{}\": {}",
-                    filename,
-                    exc.to_string()
-                )
+                Exception::io_error(&format!("IOError trying to read \"{}\": {}", filename, exc))
             })?;
             Ok(Type::String(contents))
         }
@@ -244,6 +278,7 @@ fn emptyp(args: Args) -> Ret {
 
     match &args[0] {
         Type::List(seq) | Type::Vector(seq) => Ok(Type::Bool(seq.is_empty())),
+        Type::Generator(generator) => Ok(Type::Bool(generator.first()?.is_none())),
         _ => Err(Exception::seq_fun("empty?")),
     }
 }
@@ -253,6 +288,7 @@ fn count(args: Args) -> Ret {
 
     match &args[0] {
         Type::List(seq) | Type::Vector(seq) => Ok(Type::Int(seq.len() as i64)),
+        Type::Generator(generator) => Ok(Type::Int(generator.values()?.len() as i64)),
         Type::Nil => Ok(Type::Int(0)),
         _ => Err(Exception::seq_fun("count")),
     }
@@ -339,6 +375,8 @@ fn deref(args: Args) -> Ret {
 
     match &args[0] {
         Type::Atom(atom) => Ok(atom.borrow().clone()),
+        // runs the event loop until the promise is delivered
+        Type::Promise(promise) => promise.wait(),
         _ => Err(Exception::atom_fun("deref")),
     }
 }
@@ -374,7 +412,7 @@ fn swap(args: Args) -> Ret {
         return Err(Exception::type_error("'swap' takes at least two argument"));
     }
 
-    let (atom, atom_value) = match args.get(0) {
+    let (atom, atom_value) = match args.first() {
         Some(Type::Atom(a)) => (a, a.borrow().clone()),
         _ => {
             return Err(Exception::type_error(
@@ -390,29 +428,8 @@ fn swap(args: Args) -> Ret {
     }
 
     let new_atom_value = match args.get(1) {
-        Some(Type::Fun(fun)) => fun(f_args)?,
-        Some(Type::Closure {
-            env, params, body, ..
-        }) => {
-            let params = match **params {
-                Type::List(ref l) | Type::Vector(ref l) => {
-                    let param_list: Vec<&str> = l
-                        .iter()
-                        .map(|elem| match *elem {
-                            Type::Symbol(ref sym) => sym.as_str(),
-                            _ => "",
-                        })
-                        .filter(|elem| !elem.is_empty())
-                        .collect();
-                    param_list
-                }
-                _ => return Err(Exception::interpreter_error("malformed closure!")),
-            };
-
-            let fun_env = Env::new(Some(env.clone()), &params, f_args.as_slice());
-
-            eval(*body.to_owned(), &Rc::new(fun_env))?
-        }
+        Some(Type::Fun(fun, _)) => fun(f_args)?,
+        Some(closure @ Type::Closure { .. }) => closure.apply(f_args)?,
         _ => {
             return Err(Exception::type_error(
                 "first argument to 'swap!' must be an function",
@@ -439,6 +456,11 @@ fn cons(args: Args) -> Ret {
             list.insert(0, head.to_owned());
             Ok(Type::List(list))
         }
+        (head, Type::Generator(generator)) => {
+            let mut list = generator.values()?;
+            list.insert(0, head.to_owned());
+            Ok(Type::List(list))
+        }
         _ => Err(Exception::type_error(
             "second 'cons' argument must be a sequence",
         )),
@@ -457,6 +479,7 @@ fn concat(args: Args) -> Ret {
             Type::List(arg) | Type::Vector(arg) => {
                 list.extend(arg);
             }
+            Type::Generator(generator) => list.extend(generator.values()?),
             _ => return Err(Exception::seq_fun("concat")),
         }
     }
@@ -470,6 +493,7 @@ fn vec(args: Args) -> Ret {
     match &args[0] {
         Type::List(v) => Ok(Type::Vector(v.to_owned())),
         Type::Vector(v) => Ok(Type::Vector(v.to_owned())),
+        Type::Generator(generator) => Ok(Type::Vector(generator.values()?)),
         _ => Err(Exception::type_error(
             "'vec' only accepts arguments of sequence types",
         )),
@@ -494,8 +518,17 @@ fn nth(args: Args) -> Ret {
 
             Ok(seq[idx].clone())
         }
+        (Type::Generator(generator), Type::Int(idx)) => {
+            if *idx < 0 {
+                return Err(Exception::negative_index());
+            }
+            generator.nth(*idx as usize)?.ok_or_else(|| {
+                let len = generator.values().map_or(0, |values| values.len());
+                Exception::index_out_of_bounds(*idx as usize, len)
+            })
+        }
         (_, Type::Int(_)) => Err(Exception::type_error("first argument must be an sequence")),
-        (Type::List(_), _) | (Type::Vector(_), _) => {
+        (Type::List(_) | Type::Vector(_) | Type::Generator(_), _) => {
             Err(Exception::type_error("second argument must be an integer"))
         }
         _ => Err(Exception::type_error("must pass a sequence and an integer")),
@@ -515,6 +548,7 @@ fn first(args: Args) -> Ret {
                 Ok(seq[0].clone())
             }
         }
+        Type::Generator(generator) => Ok(generator.first()?.unwrap_or(Type::Nil)),
         _ => Ok(Type::Nil),
     }
 }
@@ -533,6 +567,7 @@ fn rest(args: Args) -> Ret {
                 Ok(Type::List(seq[1..].to_vec()))
             }
         }
+        Type::Generator(generator) => Ok(Type::Generator(generator.rest()?)),
         Type::Nil => Ok(Type::List(vec![])),
         _ => Ok(Type::Nil),
     }
@@ -545,7 +580,75 @@ fn throw(args: Args) -> Ret {
     Err(Exception::custom(args[0].to_owned()))
 }
 
+/// Return the call stack of an exception map caught by `catch*`,
+/// innermost call first, or nil for other values
+fn ex_stack(args: Args) -> Ret {
+    ex_field("ex-stack", "stack", args)
+}
+
+/// Create an exception map with a message, a data map and optionally
+/// the exception that caused it
+fn ex_info(args: Args) -> Ret {
+    if args.len() != 2 && args.len() != 3 {
+        return Err(Exception::type_error(
+            "'ex-info' takes two or three arguments",
+        ));
+    }
+
+    let message = args[0].convert_to_string()?;
+    if !args[1].is_map() {
+        return Err(Exception::type_error("'ex-info' data must be a hash map"));
+    }
+    let mut map = HashMap::new();
+    map.insert(error::field("message"), Box::new(Type::String(message)));
+    map.insert(error::field("data"), Box::new(args[1].clone()));
+    if let Some(cause) = args.get(2) {
+        map.insert(error::field("cause"), Box::new(cause.clone()));
+    }
+    Ok(Type::HashMap(map))
+}
+
+/// Return a field of an exception map, or nil for other values
+fn ex_field(fun_name: &str, name: &str, args: Args) -> Ret {
+    error::nargs_check(fun_name, 1, args.len())?;
+
+    match args[0] {
+        Type::HashMap(ref map) => Ok(map
+            .get(&error::field(name))
+            .map_or(Type::Nil, |value| (**value).clone())),
+        _ => Ok(Type::Nil),
+    }
+}
+
+fn ex_message(args: Args) -> Ret {
+    ex_field("ex-message", "message", args)
+}
+
+fn ex_data(args: Args) -> Ret {
+    ex_field("ex-data", "data", args)
+}
+
+fn ex_cause(args: Args) -> Ret {
+    ex_field("ex-cause", "cause", args)
+}
+
+/// Open the debugger on uncaught exceptions if the argument is true
+fn debug_on_exception(args: Args) -> Ret {
+    error::nargs_check("debug-on-exception!", 1, args.len())?;
+
+    debug::set_debug_on_exception(!matches!(args[0], Type::Nil | Type::Bool(false)));
+    Ok(Type::Nil)
+}
+
 fn apply(args: Args) -> Ret {
+    let (fun, arguments) = apply_args(args)?;
+    fun.apply(arguments)
+}
+
+/// Return the function called by `apply` and its arguments, with the
+/// sequences spread. The evaluators call it directly, so `apply` in
+/// tail position is a tail call.
+pub fn apply_args(args: Args) -> Result<(Type, Args), Exception> {
     if args.len() < 2 {
         return Err(Exception::type_error("'apply' takes at least two argument"));
     }
@@ -554,11 +657,12 @@ fn apply(args: Args) -> Ret {
     for arg in args[1..].iter() {
         match arg {
             Type::List(seq) | Type::Vector(seq) => arguments.extend(seq.to_owned()),
+            Type::Generator(generator) => arguments.extend(generator.values()?),
             t => arguments.push(t.to_owned()),
         }
     }
 
-    args[0].apply(arguments)
+    Ok((args[0].to_owned(), arguments))
 }
 
 fn map(args: Args) -> Ret {
@@ -567,6 +671,7 @@ fn map(args: Args) -> Ret {
     let fun = args[0].to_owned();
     let arguments = match &args[1] {
         Type::List(seq) | Type::Vector(seq) => seq,
+        Type::Generator(generator) => &generator.values()?,
         _ => {
             return Err(Exception::type_error(
                 "must pass a function and a list to 'map'",
@@ -611,11 +716,25 @@ fn symbol(args: Args) -> Ret {
     error::nargs_check("symbol", 1, args.len())?;
 
     match &args[0] {
-        Type::String(s) => Ok(Type::Symbol(s.to_owned())),
+        Type::String(s) => Ok(Type::Symbol(Symbol::new(s))),
         _ => Err(Exception::string_fun("symbol")),
     }
 }
 
+/// Return a fresh symbol, named with an optional prefix
+fn gensym(args: Args) -> Ret {
+    let prefix = match args.len() {
+        0 => String::from("G__"),
+        1 => match &args[0] {
+            Type::String(prefix) => prefix.clone(),
+            _ => return Err(Exception::string_fun("gensym")),
+        },
+        _ => return Err(Exception::type_error("'gensym' takes zero or one argument")),
+    };
+
+    Ok(Type::Symbol(Symbol::generate(&prefix, "")))
+}
+
 fn keywordp(args: Args) -> Ret {
     error::nargs_check("keyword?", 1, args.len())?;
 
@@ -647,11 +766,13 @@ fn vector(args: Args) -> Ret {
 fn sequentialp(args: Args) -> Ret {
     error::nargs_check("sequential?", 1, args.len())?;
 
-    Ok(Type::Bool(args[0].is_sequence()))
+    Ok(Type::Bool(
+        args[0].is_sequence() || matches!(args[0], Type::Generator(_)),
+    ))
 }
 
 fn hash_map(args: Args) -> Ret {
-    if args.len() % 2 != 0 {
+    if !args.len().is_multiple_of(2) {
         return Err(Exception::type_error(
             "must pass an even number of arguments to 'hash-map'",
         ));
@@ -691,7 +812,7 @@ fn mapp(args: Args) -> Ret {
 fn assoc(args: Args) -> Ret {
     if args.len() < 2 {
         return Err(Exception::type_error("'assoc' takes at least two argument"));
-    } else if (args.len() - 1) % 2 != 0 {
+    } else if !(args.len() - 1).is_multiple_of(2) {
         return Err(Exception::type_error(
             "must pass key value pairs to 'assoc'",
         ));
@@ -724,9 +845,9 @@ fn dissoc(args: Args) -> Ret {
     }
 
     let mut hm = args[0].convert_to_map()?;
-    for i in 1..args.len() {
-        let key = match &args[i] {
-            Type::String(_) | Type::Keyword(_) => pr_str(args[i].to_owned(), true),
+    for arg in args.iter().skip(1) {
+        let key = match arg {
+            Type::String(_) | Type::Keyword(_) => pr_str(arg.to_owned(), true),
             _ => {
                 return Err(Exception::type_error(
                     "must pass string or keyword as key in a hash map (dissoc)",
@@ -782,8 +903,8 @@ fn keys(args: Args) -> Ret {
     Ok(Type::List(
         hm.keys()
             .map(|k| {
-                if k.starts_with(':') {
-                    Type::Keyword(k[1..].to_string())
+                if let Some(k) = k.strip_prefix(':') {
+                    Type::Keyword(k.to_string())
                 } else {
                     Type::String(k[1..(k.len() - 1)].to_string())
                 }
@@ -807,11 +928,12 @@ fn vals(args: Args) -> Ret {
 
 fn readline(args: Args) -> Ret {
     error::nargs_check("readline", 1, args.len())?;
+    sandbox::check_io("readline")?;
 
     let prompt = args[0].convert_to_string()?;
     print!("{}", prompt);
     if std::io::stdout().flush().is_err() {
-        return Err(Exception::builtin("IOError printing prompt"));
+        return Err(Exception::io_error("IOError printing prompt"));
     };
 
     let mut line = String::new();
@@ -902,8 +1024,227 @@ fn seq(args: Args) -> Ret {
         Type::Vector(v) if v.is_empty() => Ok(Type::Nil),
         Type::Vector(v) => Ok(Type::List(v.to_owned())),
 
+        Type::Generator(generator) => match generator.first()? {
+            Some(_) => Ok(Type::Generator(generator.copy())),
+            None => Ok(Type::Nil),
+        },
+
         _ => Err(Exception::type_error(
             "Must pass a list, vector, string or nil to 'seq'",
         )),
     }
 }
+
+/// Create a generator running a function without arguments as a
+/// coroutine. Each `yield` of the function suspends it and gives the
+/// next value of the generator.
+fn generator(args: Args) -> Ret {
+    error::nargs_check("generator", 1, args.len())?;
+
+    if !args[0].is_callable() {
+        return Err(Exception::type_error(
+            "'generator' argument must be a function",
+        ));
+    }
+    Ok(Type::Generator(Generator::new(args[0].to_owned())))
+}
+
+fn generatorp(args: Args) -> Ret {
+    error::nargs_check("generator?", 1, args.len())?;
+
+    Ok(Type::Bool(matches!(args[0], Type::Generator(_))))
+}
+
+/// Return the next value of a generator and move past it, or nil if
+/// the generator has no more values
+fn next(args: Args) -> Ret {
+    error::nargs_check("next!", 1, args.len())?;
+
+    match &args[0] {
+        Type::Generator(generator) => Ok(generator.next()?.unwrap_or(Type::Nil)),
+        _ => Err(Exception::type_error(
+            "'next!' argument must be a generator",
+        )),
+    }
+}
+
+fn set_timeout(args: Args) -> Ret {
+    set_timer("set-timeout", args, false)
+}
+
+fn set_interval(args: Args) -> Ret {
+    set_timer("set-interval", args, true)
+}
+
+/// Schedule a call of a function without arguments after a delay in
+/// milliseconds, and return the id of the timer
+fn set_timer(name: &str, args: Args, interval: bool) -> Ret {
+    error::nargs_check(name, 2, args.len())?;
+
+    if !args[0].is_callable() {
+        return Err(Exception::type_error(&format!(
+            "first argument to '{}' must be a function",
+            name
+        )));
+    }
+    let delay = match args[1] {
+        Type::Int(delay) if delay >= 0 => Duration::from_millis(delay as u64),
+        _ => {
+            return Err(Exception::type_error(&format!(
+                "second argument to '{}' must be a non-negative integer",
+                name
+            )))
+        }
+    };
+    Ok(Type::Int(event::set_timer(
+        args[0].clone(),
+        delay,
+        interval,
+    )))
+}
+
+/// Cancel a timer, return false if it already fired or was cancelled
+fn clear_timer(args: Args) -> Ret {
+    error::nargs_check("clear-timer", 1, args.len())?;
+
+    match args[0] {
+        Type::Int(id) => Ok(Type::Bool(event::clear_timer(id))),
+        _ => Err(Exception::type_error(
+            "'clear-timer' argument must be a timer id",
+        )),
+    }
+}
+
+fn promise(args: Args) -> Ret {
+    error::nargs_check("promise", 0, args.len())?;
+
+    Ok(Type::Promise(Promise::new()))
+}
+
+fn promisep(args: Args) -> Ret {
+    error::nargs_check("promise?", 1, args.len())?;
+
+    Ok(Type::Bool(matches!(args[0], Type::Promise(_))))
+}
+
+/// Deliver a value to a promise, return false if it was already
+/// delivered
+fn deliver(args: Args) -> Ret {
+    error::nargs_check("deliver", 2, args.len())?;
+
+    match &args[0] {
+        Type::Promise(promise) => Ok(Type::Bool(promise.deliver(args[1].clone()))),
+        _ => Err(Exception::type_error(
+            "first argument to 'deliver' must be a promise",
+        )),
+    }
+}
+
+fn realizedp(args: Args) -> Ret {
+    error::nargs_check("realized?", 1, args.len())?;
+
+    match &args[0] {
+        Type::Promise(promise) => Ok(Type::Bool(promise.is_settled())),
+        _ => Err(Exception::type_error(
+            "'realized?' argument must be a promise",
+        )),
+    }
+}
+
+/// Return a promise of the value of a function without arguments,
+/// called by the event loop
+fn future(args: Args) -> Ret {
+    error::nargs_check("future", 1, args.len())?;
+
+    if !args[0].is_callable() {
+        return Err(Exception::type_error(
+            "'future' argument must be a function",
+        ));
+    }
+    Ok(Type::Promise(Promise::future(args[0].clone())))
+}
+
+/// Return a promise of the value of a function called with the value
+/// of a promise
+fn then(args: Args) -> Ret {
+    error::nargs_check("then", 2, args.len())?;
+
+    match (&args[0], &args[1]) {
+        (Type::Promise(promise), fun) if fun.is_callable() => {
+            Ok(Type::Promise(promise.then(fun.clone())))
+        }
+        (Type::Promise(_), _) => Err(Exception::type_error(
+            "second argument to 'then' must be a function",
+        )),
+        _ => Err(Exception::type_error(
+            "first argument to 'then' must be a promise",
+        )),
+    }
+}
+
+/// Return the definitions of a namespace, `(ns-publics 'name)` ->
+/// `{"symbol" value ...}`
+fn ns_publics(args: Args) -> Ret {
+    error::nargs_check("ns-publics", 1, args.len())?;
+
+    let ns_env = ns::find_named(&ns::evaluated()?, &args[0])?;
+    let publics = ns_env
+        .bindings()
+        .into_iter()
+        .map(|(symbol, value)| {
+            let key = Type::String(symbol.name().to_string());
+            (pr_str(key, true), Box::new(value))
+        })
+        .collect();
+    Ok(Type::HashMap(publics))
+}
+
+/// Return the namespace qualified symbol a symbol refers to in the
+/// current namespace, or nil if it isn't bound
+fn resolve(args: Args) -> Ret {
+    error::nargs_check("resolve", 1, args.len())?;
+
+    match args[0] {
+        Type::Symbol(symbol) => match ns::qualify(&ns::evaluated()?, symbol) {
+            Some(qualified) => Ok(Type::Symbol(qualified)),
+            None => Ok(Type::Nil),
+        },
+        _ => Err(Exception::type_error("'resolve' only accepts symbols")),
+    }
+}
+
+/// Return true if a symbol is bound in the current namespace
+fn boundp(args: Args) -> Ret {
+    error::nargs_check("bound?", 1, args.len())?;
+
+    match args[0] {
+        Type::Symbol(symbol) => Ok(Type::Bool(ns::evaluated()?.get(symbol).is_ok())),
+        _ => Err(Exception::type_error("'bound?' only accepts symbols")),
+    }
+}
+
+/// Load and evaluate a mal file, like the `load-file` special form
+/// when it's passed around as a function. Relative paths are looked
+/// up first in the directory of the file being loaded.
+fn load_file(args: Args) -> Ret {
+    error::nargs_check("load-file", 1, args.len())?;
+
+    let path = args[0].convert_to_string()?;
+    module::load_file(&ns::evaluated()?, Path::new(&path))
+}
+
+/// Remove a symbol from a namespace, `(ns-unmap 'name 'symbol)`
+fn ns_unmap(args: Args) -> Ret {
+    error::nargs_check("ns-unmap", 2, args.len())?;
+
+    let ns_env = ns::find_named(&ns::evaluated()?, &args[0])?;
+    match args[1] {
+        Type::Symbol(symbol) => {
+            ns::unmap(&ns_env, symbol);
+            Ok(Type::Nil)
+        }
+        _ => Err(Exception::type_error(
+            "second 'ns-unmap' argument must be a symbol",
+        )),
+    }
+}
@@ -0,0 +1,334 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::error::Exception;
+use crate::printer;
+use crate::reader;
+use crate::stack::{self, Call};
+use crate::symbol::Symbol;
+use crate::types::Type;
+
+/// Frontend of the debugger, reading its commands and showing its
+/// output
+pub trait Debugger {
+    /// Read a line of input after showing prompt. Returns None when
+    /// the input is closed.
+    fn read_line(&mut self, prompt: &str) -> Option<String>;
+
+    /// Show a line of output
+    fn print(&mut self, line: &str);
+}
+
+/// Where to stop next
+#[derive(Clone, Copy, PartialEq)]
+enum Step {
+    Run,
+    /// At the next call or return
+    Into,
+    /// At the next call or return at this level or an outer one
+    Over(usize),
+    /// When the call at this level returns
+    Out(usize),
+}
+
+thread_local! {
+    static DEBUGGER: RefCell<Option<Box<dyn Debugger>>> = RefCell::new(None);
+    static STEP: Cell<Step> = const { Cell::new(Step::Run) };
+    static ON_EXCEPTION: Cell<bool> = const { Cell::new(false) };
+    static PAUSED: Cell<bool> = const { Cell::new(false) };
+    /// Number of `try*` bodies being evaluated
+    static TRYING: Cell<usize> = const { Cell::new(0) };
+}
+
+const HELP: &str = "\
+Commands:
+  :continue :c      resume the evaluation
+  :step :s          stop at the next call or return
+  :next :n          stop at the next call or return, not in nested calls
+  :out :o           stop when the current call returns
+  :locals :l        show the local variables of the selected call
+  :set name form    set a local variable of the selected call
+  :where :w         show the calls being evaluated
+  :up :down         select the caller or the callee of the selected call
+  :help :h          show this help
+Any other input is evaluated in the selected call.";
+
+/// Install the debugger frontend of this thread. Without one, `(break)`
+/// does nothing and exceptions are never debugged.
+pub fn set_debugger(debugger: Option<Box<dyn Debugger>>) {
+    DEBUGGER.with(|current| *current.borrow_mut() = debugger);
+}
+
+/// Open the debugger when an exception isn't going to be caught
+pub fn set_debug_on_exception(enabled: bool) {
+    ON_EXCEPTION.with(|on_exception| on_exception.set(enabled));
+}
+
+/// Return true if the debugger is stepping through the evaluation
+pub fn stepping() -> bool {
+    STEP.with(|step| step.get()) != Step::Run
+}
+
+/// Count a `try*` body being evaluated, its exceptions are caught
+pub fn enter_try() {
+    TRYING.with(|trying| trying.set(trying.get() + 1));
+}
+
+/// Stop counting `try*` bodies whose evaluation ended
+pub fn exit_try(count: usize) {
+    TRYING.with(|trying| trying.set(trying.get() - count));
+}
+
+/// `try*` body being evaluated, counted until dropped
+pub struct Trying;
+
+impl Trying {
+    pub fn new() -> Trying {
+        enter_try();
+        Trying
+    }
+}
+
+impl Drop for Trying {
+    fn drop(&mut self) {
+        exit_try(1);
+    }
+}
+
+/// Why the evaluation stopped
+enum Event<'a> {
+    Break,
+    Call,
+    Return(&'a Type),
+    Exception(&'a Exception),
+}
+
+/// Stop at `(break)`, in env
+pub fn break_at(env: &Rc<Env>) {
+    pause(Event::Break, Some(env));
+}
+
+/// Stop at the innermost call, if stepping reached it
+pub fn called() {
+    if should_stop(false) {
+        pause(Event::Call, None);
+    }
+}
+
+/// Stop at the return of the innermost call, if stepping reached it
+pub fn returned(value: &Type) {
+    if should_stop(true) {
+        pause(Event::Return(value), None);
+    }
+}
+
+/// Stop at an exception that was just raised, if it's debugged
+#[cold]
+pub fn raised(exc: &Exception) {
+    let uncaught = TRYING.with(|trying| trying.get() == 0);
+    if ON_EXCEPTION.with(|on_exception| on_exception.get()) && uncaught {
+        pause(Event::Exception(exc), None);
+    }
+}
+
+fn should_stop(is_return: bool) -> bool {
+    if !matches!(stack::innermost(), Some(Call::Closure(..))) {
+        return false;
+    }
+    let level = stack::level();
+    match STEP.with(|step| step.get()) {
+        Step::Run => false,
+        Step::Into => true,
+        Step::Over(paused) => level <= paused,
+        Step::Out(paused) => is_return && level <= paused,
+    }
+}
+
+fn set_step(step: Step) {
+    STEP.with(|current| current.set(step));
+    stack::update_hooks();
+}
+
+/// Calls that can be selected in the debugger, innermost first
+struct Frame {
+    name: String,
+    env: Rc<Env>,
+}
+
+fn pause(event: Event, env: Option<&Rc<Env>>) {
+    if PAUSED.with(|paused| paused.get()) {
+        return;
+    }
+    let mut debugger = match DEBUGGER.with(|debugger| debugger.borrow_mut().take()) {
+        Some(debugger) => debugger,
+        None => return,
+    };
+
+    let mut frames: Vec<Frame> = stack::calls()
+        .into_iter()
+        .rev()
+        .filter_map(|call| match call {
+            Call::Closure(_, ref env) => Some(Frame {
+                name: call.to_string(),
+                env: env.clone(),
+            }),
+            _ => None,
+        })
+        .collect();
+    if let Some(env) = env {
+        // (break) knows the innermost scope, inside let* too
+        match frames.first_mut() {
+            Some(frame) => frame.env = env.clone(),
+            None => frames.push(Frame {
+                name: String::from("top level"),
+                env: env.clone(),
+            }),
+        }
+    }
+    if frames.is_empty() {
+        DEBUGGER.with(|current| *current.borrow_mut() = Some(debugger));
+        return;
+    }
+
+    let name = frames[0].name.clone();
+    debugger.print(&match event {
+        Event::Break => format!("break in {}", name),
+        Event::Call => match stack::innermost() {
+            Some(call) => format!("call {}", call.form()),
+            None => unreachable!("calls are made in a frame"),
+        },
+        Event::Return(value) => {
+            format!("{} returned {}", name, printer::pr_str(value.clone(), true))
+        }
+        Event::Exception(exc) => format!("exception in {}: {}", name, exc),
+    });
+
+    PAUSED.with(|paused| paused.set(true));
+    set_step(Step::Run);
+    let mut session = Session {
+        debugger: &mut *debugger,
+        frames,
+        selected: 0,
+    };
+    let step = session.run();
+    PAUSED.with(|paused| paused.set(false));
+    set_step(step);
+
+    DEBUGGER.with(|current| *current.borrow_mut() = Some(debugger));
+}
+
+struct Session<'a> {
+    debugger: &'a mut dyn Debugger,
+    frames: Vec<Frame>,
+    selected: usize,
+}
+
+impl Session<'_> {
+    /// Read and run commands until the evaluation is resumed. Returns
+    /// where to stop next.
+    fn run(&mut self) -> Step {
+        let level = stack::level();
+        loop {
+            let prompt = format!("debug[{}]> ", self.frames[self.selected].name);
+            let line = match self.debugger.read_line(&prompt) {
+                Some(line) => line,
+                None => return Step::Run,
+            };
+            let line = line.trim();
+            let (command, rest) = match line.split_once(char::is_whitespace) {
+                Some((command, rest)) => (command, rest.trim()),
+                None => (line, ""),
+            };
+
+            match command {
+                "" => {}
+                ":continue" | ":c" => return Step::Run,
+                ":step" | ":s" => return Step::Into,
+                ":next" | ":n" => return Step::Over(level),
+                ":out" | ":o" => return Step::Out(level),
+                ":locals" | ":l" => self.locals(),
+                ":set" => self.set(rest),
+                ":where" | ":w" => self.where_(),
+                ":up" if self.selected + 1 < self.frames.len() => self.selected += 1,
+                ":down" if self.selected > 0 => self.selected -= 1,
+                ":up" | ":down" => self.debugger.print("no call to select"),
+                ":help" | ":h" => {
+                    for help in HELP.lines() {
+                        self.debugger.print(help);
+                    }
+                }
+                _ => {
+                    let output = crate::rep(line, &self.frames[self.selected].env);
+                    if !output.is_empty() {
+                        self.debugger.print(&output);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Local scopes of the selected call, innermost first
+    fn scopes(&self) -> Vec<Rc<Env>> {
+        self.frames[self.selected]
+            .env
+            .scopes()
+            .into_iter()
+            .take_while(|scope| !scope.is_namespace())
+            .collect()
+    }
+
+    fn locals(&mut self) {
+        let mut shown = vec![];
+        for scope in self.scopes() {
+            for (symbol, value) in scope.bindings() {
+                // inner scopes shadow the outer ones
+                if !shown.contains(&symbol) {
+                    shown.push(symbol);
+                    let value = printer::pr_str(value, true);
+                    self.debugger.print(&format!("{} = {}", symbol, value));
+                }
+            }
+        }
+    }
+
+    fn set(&mut self, args: &str) {
+        let (name, form) = match args.split_once(char::is_whitespace) {
+            Some((name, form)) => (Symbol::new(name), form),
+            None => {
+                self.debugger.print("usage: :set name form");
+                return;
+            }
+        };
+        let scope = match self
+            .scopes()
+            .into_iter()
+            .find(|scope| scope.get_local(name).is_some())
+        {
+            Some(scope) => scope,
+            None => {
+                self.debugger
+                    .print(&format!("no local variable '{}'", name));
+                return;
+            }
+        };
+
+        let env = &self.frames[self.selected].env;
+        let value = match reader::read_str(form) {
+            Ok(Some(ast)) => crate::eval_with_backend(ast, env),
+            Ok(None) => Err(Exception::builtin("missing form")),
+            Err(exc) => Err(Exception::builtin(&exc)),
+        };
+        match value {
+            Ok(value) => scope.set(name, value),
+            Err(exc) => self.debugger.print(&format!("Error: {}", exc)),
+        }
+    }
+
+    fn where_(&mut self) {
+        for (i, frame) in self.frames.iter().enumerate() {
+            let marker = if i == self.selected { ">" } else { " " };
+            self.debugger.print(&format!("{} {}", marker, frame.name));
+        }
+    }
+}
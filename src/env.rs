@@ -4,12 +4,22 @@ use std::rc::Rc;
 
 use crate::core::Namespace;
 use crate::error::Exception;
+use crate::ns::{self, NsInfo, Registry};
+use crate::symbol::{self, Symbol};
 use crate::types::Type;
 
+/// Environments hold their bindings in slots, in the order they are
+/// bound, so local variables can be addressed by (depth, slot) pairs
+/// computed ahead of time. Namespace environments and symbols defined
+/// with `def!` inside local scopes go to a hash map instead.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Env {
-    data: RefCell<HashMap<String, Type>>,
+    names: RefCell<Vec<Symbol>>,
+    slots: RefCell<Vec<Type>>,
+    data: RefCell<HashMap<Symbol, Type>>,
     outer: Option<Rc<Env>>,
+    ns: Option<NsInfo>,
+    registry: Option<Rc<Registry>>,
 }
 
 impl Env {
@@ -20,72 +30,255 @@ impl Env {
     /// After a '&', the next parameter will gather the rest of
     /// arguments in exprs.
     ///
-    /// Panics if don't pass an argument name after an '&' in binds
-    pub fn new(outer: Option<Rc<Env>>, binds: &[&str], exprs: &[Type]) -> Env {
+    /// Panics if don't pass an argument name after an '&' in binds, or
+    /// fewer exprs than binds before it. The analyzer rejects such
+    /// `fn*` parameters and closures check their number of arguments.
+    pub fn new(outer: Option<Rc<Env>>, binds: &[Symbol], exprs: &[Type]) -> Env {
         let env = Env {
+            names: RefCell::new(Vec::with_capacity(binds.len())),
+            slots: RefCell::new(Vec::with_capacity(binds.len())),
             data: RefCell::new(HashMap::new()),
             outer,
+            ns: None,
+            registry: None,
         };
 
         for i in 0..binds.len() {
-            if binds[i] == "&" {
+            if binds[i] == symbol::AMPERSAND {
                 if i + 1 >= binds.len() {
                     panic!("Must pass a variadic parameter name after an &");
                 }
-                env.set(binds[i + 1], Type::List(exprs[i..].to_vec()));
+                env.bind(binds[i + 1], Type::List(exprs[i..].to_vec()));
                 break;
             }
-            env.set(binds[i], exprs[i].clone());
+            env.bind(binds[i], exprs[i].clone());
         }
 
         env
     }
 
-    /// Create a new environment with the default built-in symbols and functions
+    /// Create a new environment with the default built-in symbols and
+    /// functions. It's the core namespace, the root of every other
+    /// namespace. Top level code evaluated in it goes to the current
+    /// namespace, `user` until an `ns` form switches it.
     pub fn new_default() -> Env {
-        let env = Env::new(None, &[], &[]);
+        let env = Env::new_root();
         let ns = Namespace::new_default();
         for (sym, fun) in ns {
-            env.set(&sym, Type::Fun(fun));
+            let name = Symbol::new(&sym);
+            env.set(name, Type::Fun(fun, name));
         }
         env
     }
 
+    /// Create a root environment without any symbol defined
+    pub(crate) fn new_root() -> Env {
+        let mut env = Env::new(None, &[], &[]);
+        env.ns = Some(NsInfo::new(ns::CORE_NS));
+        env.registry = Some(Rc::new(Registry::new()));
+        env
+    }
+
+    /// Create the environment of a namespace called name. Its outer
+    /// environment is the root one.
+    pub fn new_namespace(root: Rc<Env>, name: &str) -> Env {
+        let mut env = Env::new(Some(root), &[], &[]);
+        env.ns = Some(NsInfo::new(name));
+        env
+    }
+
+    /// Bind a symbol in a new slot, or update its slot if it's
+    /// already bound
+    pub fn bind(&self, symbol: Symbol, value: Type) {
+        let position = self.names.borrow().iter().position(|name| *name == symbol);
+        match position {
+            Some(slot) => self.slots.borrow_mut()[slot] = value,
+            None => {
+                self.names.borrow_mut().push(symbol);
+                self.slots.borrow_mut().push(value);
+            }
+        }
+    }
+
     /// Set a symbol to a value in the environment
-    pub fn set(&self, symbol: &str, value: Type) {
-        self.data.borrow_mut().insert(symbol.to_owned(), value);
+    pub fn set(&self, symbol: Symbol, value: Type) {
+        let position = self.names.borrow().iter().position(|name| *name == symbol);
+        match position {
+            Some(slot) => self.slots.borrow_mut()[slot] = value,
+            None => {
+                self.data.borrow_mut().insert(symbol, value);
+            }
+        }
     }
 
-    fn find(&self, symbol: &str) -> Option<&Env> {
-        match self.data.borrow().get(symbol) {
-            Some(_) => Some(self),
-            None => match self.outer {
-                Some(ref env) => env.find(symbol),
-                None => None,
-            },
+    /// Return the value of the symbol in this environment, without
+    /// looking at the outer ones
+    pub fn get_local(&self, symbol: Symbol) -> Option<Type> {
+        let position = self.names.borrow().iter().position(|name| *name == symbol);
+        match position {
+            Some(slot) => Some(self.slots.borrow()[slot].clone()),
+            None => self.data.borrow().get(&symbol).cloned(),
         }
     }
 
     /// Return the value assigned to the symbol in this or any nested
-    /// environment.
+    /// environment. Namespace qualified and referred symbols are
+    /// resolved too.
     ///
     /// Returns an error if the symbol is not found
-    pub fn get(&self, symbol: &str) -> Result<Type, Exception> {
-        match self.find(symbol) {
-            Some(env) => match env.data.borrow().get(symbol) {
-                Some(value) => Ok(value.clone()),
-                None => Err(Exception::builtin(&format!(
-                    "Env should have the symbol '{}'",
-                    symbol
-                ))),
+    pub fn get(self: &Rc<Env>, symbol: Symbol) -> Result<Type, Exception> {
+        let mut env = self;
+        loop {
+            if let Some(value) = env.get_local(symbol) {
+                return Ok(value);
+            }
+            match env.outer {
+                Some(ref outer) => env = outer,
+                None => break,
+            }
+        }
+
+        ns::resolve(self, symbol).ok_or_else(|| Exception::symbol_not_found(symbol))
+    }
+
+    /// Return the value of a local variable by its lexical address:
+    /// the number of environments to go out and the slot in that
+    /// environment. If the address doesn't hold the symbol anymore
+    /// (e.g. it has been shadowed with `def!`), it's looked up by
+    /// name.
+    pub fn get_address(
+        self: &Rc<Env>,
+        depth: usize,
+        slot: usize,
+        symbol: Symbol,
+    ) -> Result<Type, Exception> {
+        let mut env = self;
+        for _ in 0..depth {
+            if !env.data.borrow().is_empty() {
+                return self.get(symbol);
+            }
+            match env.outer {
+                Some(ref outer) => env = outer,
+                None => return self.get(symbol),
+            }
+        }
+
+        match env.names.borrow().get(slot) {
+            Some(name) if *name == symbol => Ok(env.slots.borrow()[slot].clone()),
+            _ => self.get(symbol),
+        }
+    }
+
+    /// Return the slot of the symbol, if it's bound in a slot of this
+    /// environment
+    pub fn slot_of(&self, symbol: Symbol) -> Option<usize> {
+        self.names.borrow().iter().position(|name| *name == symbol)
+    }
+
+    /// Return the outer environment
+    pub fn outer(&self) -> Option<&Rc<Env>> {
+        self.outer.as_ref()
+    }
+
+    /// Return the environment depth levels out of this one
+    pub fn ancestor(self: &Rc<Env>, depth: usize) -> &Rc<Env> {
+        let mut env = self;
+        for _ in 0..depth {
+            env = env.outer.as_ref().expect("ancestors are in scope");
+        }
+        env
+    }
+
+    /// Remove a symbol defined in the environment (with `def!` or in a
+    /// namespace) and return its value. Slots of local scopes can't
+    /// be removed.
+    pub fn unset(&self, symbol: Symbol) -> Option<Type> {
+        self.data.borrow_mut().remove(&symbol)
+    }
+
+    /// Return the symbols defined in this environment, without the
+    /// ones from the outer environments
+    pub fn local_symbols(&self) -> Vec<Symbol> {
+        let mut symbols = self.names.borrow().clone();
+        symbols.extend(self.data.borrow().keys());
+        symbols
+    }
+
+    /// Return the bindings of this environment, without the ones from
+    /// the outer environments. Slots come first, in the order they
+    /// were bound, then the rest sorted by name.
+    pub fn bindings(&self) -> Vec<(Symbol, Type)> {
+        let mut bindings = self.slot_bindings();
+        bindings.extend(self.defined_bindings());
+        bindings
+    }
+
+    /// Return the bindings held in slots, in slot order
+    pub(crate) fn slot_bindings(&self) -> Vec<(Symbol, Type)> {
+        self.names
+            .borrow()
+            .iter()
+            .cloned()
+            .zip(self.slots.borrow().iter().cloned())
+            .collect()
+    }
+
+    /// Return the bindings not held in slots (namespace vars and
+    /// symbols defined with `def!`), sorted by name
+    pub(crate) fn defined_bindings(&self) -> Vec<(Symbol, Type)> {
+        let mut defined: Vec<(Symbol, Type)> = self
+            .data
+            .borrow()
+            .iter()
+            .map(|(symbol, value)| (*symbol, value.clone()))
+            .collect();
+        defined.sort_by_key(|(symbol, _)| symbol.name());
+        defined
+    }
+
+    /// Return this environment and all its outer environments, from
+    /// the innermost to the outermost
+    pub fn scopes(self: &Rc<Env>) -> Vec<Rc<Env>> {
+        let mut scopes = vec![self.clone()];
+        while let Some(outer) = scopes.last().unwrap().outer.clone() {
+            scopes.push(outer);
+        }
+        scopes
+    }
+
+    /// Return every namespace and its environment, sorted by name
+    pub fn namespaces(self: &Rc<Env>) -> Vec<(String, Rc<Env>)> {
+        ns::all(self)
+    }
+
+    /// Return the name of the namespace if this is the environment of
+    /// a namespace
+    pub fn namespace_name(&self) -> Option<String> {
+        self.ns.as_ref().map(|info| info.name.clone())
+    }
+
+    /// Returns true if this is the environment of a namespace
+    pub fn is_namespace(&self) -> bool {
+        self.ns.is_some()
+    }
+
+    /// Return the information of the namespace this environment
+    /// belongs to
+    pub fn ns_info(&self) -> Option<&NsInfo> {
+        match self.ns {
+            Some(ref info) => Some(info),
+            None => match self.outer {
+                Some(ref outer) => outer.ns_info(),
+                None => None,
             },
-            None => Err(Exception::builtin(&format!(
-                "symbol '{}' not found",
-                symbol
-            ))),
         }
     }
 
+    /// Return the namespace registry. Only root environments have one
+    pub fn registry(&self) -> Option<Rc<Registry>> {
+        self.registry.clone()
+    }
+
     fn _outermost(self: &Rc<Env>) -> Option<Rc<Env>> {
         match self.outer {
             Some(ref outer) => {
@@ -1,19 +1,151 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::continuation::Continuation;
 use crate::printer;
+use crate::stack::Call;
+use crate::symbol::Symbol;
 use crate::types::Type;
 
-#[derive(Debug)]
-pub enum Exception {
-    Builtin(String),
+#[derive(Debug, Clone)]
+pub struct Exception {
+    pub payload: Payload,
+    /// Calls being evaluated when the exception was raised, innermost
+    /// last. It's captured the first time the exception unwinds.
+    pub stack: Option<Rc<[Call]>>,
+}
+
+/// What was raised, an interpreter error message or a thrown value
+#[derive(Debug, Clone)]
+pub enum Payload {
+    Builtin(Kind, String),
     Custom(Type),
+    /// Call of a continuation with a value, unwinding to the
+    /// evaluation that captured it. It's never caught by `catch*`, but
+    /// the `finally` blocks it unwinds through are run.
+    Escape(Rc<Continuation>, Type),
+}
+
+/// Kind of the errors raised by the interpreter, matched by `catch*`
+/// clauses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Any other error
+    Error,
+    Type,
+    Index,
+    /// Wrong number of arguments
+    Arity,
+    Io,
+    /// Symbol not bound
+    Symbol,
+    /// Malformed code read by `read-string` or from a file
+    Reader,
+    /// Maximum evaluation depth exceeded
+    StackDepth,
+    /// Sandbox quotas exceeded
+    StepQuota,
+    TimeQuota,
+    SizeQuota,
+    /// Evaluation interrupted, only caught by its kind
+    Interrupt,
+}
+
+const KINDS: &[(Kind, &str)] = &[
+    (Kind::Error, "error"),
+    (Kind::Type, "type-error"),
+    (Kind::Index, "index-error"),
+    (Kind::Arity, "arity-error"),
+    (Kind::Io, "io-error"),
+    (Kind::Symbol, "symbol-error"),
+    (Kind::Reader, "reader-error"),
+    (Kind::StackDepth, "stack-depth-error"),
+    (Kind::StepQuota, "step-quota-error"),
+    (Kind::TimeQuota, "time-quota-error"),
+    (Kind::SizeQuota, "size-quota-error"),
+    (Kind::Interrupt, "interrupt"),
+];
+
+impl Kind {
+    /// Return the kind named by a keyword, like `:type-error`
+    pub fn from_name(name: &str) -> Option<Kind> {
+        KINDS
+            .iter()
+            .find(|(_, kind_name)| *kind_name == name)
+            .map(|(kind, _)| *kind)
+    }
+
+    pub fn name(self) -> &'static str {
+        KINDS.iter().find(|(kind, _)| *kind == self).unwrap().1
+    }
+}
+
+/// Key of a field of the exception maps, like `:message`
+pub fn field(name: &str) -> String {
+    format!(":{}", name)
 }
 
 impl Exception {
     pub fn builtin(exc: &str) -> Exception {
-        Exception::Builtin(exc.to_string())
+        Exception::of_kind(Kind::Error, exc)
+    }
+
+    fn of_kind(kind: Kind, exc: &str) -> Exception {
+        Exception::new(Payload::Builtin(kind, exc.to_string()))
     }
 
     pub fn custom(exc: Type) -> Exception {
-        Exception::Custom(exc)
+        Exception::new(Payload::Custom(exc))
+    }
+
+    pub fn escape(continuation: Rc<Continuation>, value: Type) -> Exception {
+        Exception::new(Payload::Escape(continuation, value))
+    }
+
+    /// Return true if the exception unwinds to a continuation
+    pub fn is_escape(&self) -> bool {
+        matches!(self.payload, Payload::Escape(..))
+    }
+
+    fn new(payload: Payload) -> Exception {
+        Exception {
+            payload,
+            stack: None,
+        }
+    }
+
+    /// Convert the exception to the value bound by `catch*`. Builtin
+    /// exceptions are caught as a map with their `:kind` and
+    /// `:message`.
+    pub fn into_value(self) -> Type {
+        match self.payload {
+            Payload::Builtin(kind, message) => {
+                let mut map = HashMap::new();
+                map.insert(
+                    field("kind"),
+                    Box::new(Type::Keyword(kind.name().to_string())),
+                );
+                map.insert(field("message"), Box::new(Type::String(message)));
+                Type::HashMap(map)
+            }
+            Payload::Custom(t) | Payload::Escape(_, t) => t,
+        }
+    }
+
+    /// Return the kind of a builtin exception, or of a thrown map with
+    /// a `:kind`, like a rethrown builtin exception
+    pub fn kind(&self) -> Option<Kind> {
+        match self.payload {
+            Payload::Builtin(kind, _) => Some(kind),
+            Payload::Custom(Type::HashMap(ref map)) => {
+                match map.get(&field("kind")).map(|kind| &**kind) {
+                    Some(Type::Keyword(name)) => Kind::from_name(name),
+                    _ => None,
+                }
+            }
+            Payload::Custom(_) | Payload::Escape(..) => None,
+        }
     }
 
     pub fn interpreter_error(exc: &str) -> Exception {
@@ -21,14 +153,52 @@ impl Exception {
     }
 
     pub fn type_error(exc: &str) -> Exception {
-        Exception::builtin(&format!("Type error: {}", exc))
+        Exception::of_kind(Kind::Type, &format!("Type error: {}", exc))
     }
 
     pub fn nargs_error(fun_name: &str, nargs: usize, given: usize) -> Exception {
-        Exception::type_error(&format!(
-            "'{}' takes exactly {} argument ({} given)",
-            fun_name, nargs, given
-        ))
+        Exception::of_kind(
+            Kind::Arity,
+            &format!(
+                "Type error: '{}' takes exactly {} argument ({} given)",
+                fun_name, nargs, given
+            ),
+        )
+    }
+
+    pub fn variadic_nargs_error(fun_name: &str, nargs: usize, given: usize) -> Exception {
+        Exception::of_kind(
+            Kind::Arity,
+            &format!(
+                "Type error: '{}' takes at least {} argument ({} given)",
+                fun_name, nargs, given
+            ),
+        )
+    }
+
+    pub fn io_error(exc: &str) -> Exception {
+        Exception::of_kind(Kind::Io, exc)
+    }
+
+    pub fn reader_error(exc: &str) -> Exception {
+        Exception::of_kind(Kind::Reader, exc)
+    }
+
+    pub fn stack_depth_error(exc: &str) -> Exception {
+        Exception::of_kind(Kind::StackDepth, exc)
+    }
+
+    pub fn interrupted() -> Exception {
+        Exception::of_kind(Kind::Interrupt, "Interrupted")
+    }
+
+    /// Exceeded sandbox quota, kind is one of the quota kinds
+    pub fn quota_error(kind: Kind, exc: &str) -> Exception {
+        Exception::of_kind(kind, exc)
+    }
+
+    pub fn symbol_not_found(symbol: Symbol) -> Exception {
+        Exception::of_kind(Kind::Symbol, &format!("symbol '{}' not found", symbol))
     }
 
     pub fn numeric_fun(fun_name: &str) -> Exception {
@@ -44,7 +214,7 @@ impl Exception {
     }
 
     pub fn map_fun(fun_name: &str) -> Exception {
-        Exception::type_error(&format!("{} only accepts hash map type", fun_name)) 
+        Exception::type_error(&format!("{} only accepts hash map type", fun_name))
     }
 
     pub fn atom_fun(fun_name: &str) -> Exception {
@@ -56,7 +226,7 @@ impl Exception {
     }
 
     pub fn index_error(exc: &str) -> Exception {
-        Exception::builtin(&format!("Index error: {}", exc))
+        Exception::of_kind(Kind::Index, &format!("Index error: {}", exc))
     }
 
     pub fn index_out_of_bounds(idx: usize, length: usize) -> Exception {
@@ -77,11 +247,12 @@ impl From<String> for Exception {
     }
 }
 
-impl ToString for Exception {
-    fn to_string(&self) -> String {
-        match self {
-            Exception::Builtin(s) => s.to_owned(),
-            Exception::Custom(t) => printer::pr_str(t.to_owned(), false),
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.payload {
+            Payload::Builtin(_, ref s) => write!(f, "{}", s),
+            Payload::Custom(ref t) => write!(f, "{}", printer::pr_str(t.to_owned(), false)),
+            Payload::Escape(..) => write!(f, "continuation called out of its extent"),
         }
     }
 }
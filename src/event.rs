@@ -0,0 +1,265 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::Exception;
+use crate::interrupt;
+use crate::sandbox;
+use crate::types::{Ret, Type};
+
+/// Longest sleep between two checks of the interrupts and of the
+/// sandbox deadline, while waiting for a timer
+const POLL: Duration = Duration::from_millis(50);
+
+thread_local! {
+    static EVENTS: RefCell<Events> = RefCell::new(Events::default());
+}
+
+/// Tasks of the event loop of a thread
+#[derive(Default)]
+struct Events {
+    /// Callbacks of settled promises, run before the timers
+    ready: VecDeque<Task>,
+    /// Timers by due time, then by id
+    timers: BTreeMap<(Instant, i64), Timer>,
+    /// Due time of the timers by id
+    due: HashMap<i64, Instant>,
+    last_id: i64,
+}
+
+struct Timer {
+    fun: Type,
+    /// Period of an interval, None for a timeout
+    interval: Option<Duration>,
+}
+
+enum Task {
+    /// Call a function and settle the promise with its result
+    Future(Type, Promise),
+    /// Call the callback of `then` with the result of a promise
+    Then(Type, Result<Type, Exception>, Promise),
+}
+
+enum State {
+    /// Callbacks to run and promises to settle when it's settled
+    Pending(Vec<Reaction>),
+    Settled(Result<Type, Exception>),
+}
+
+enum Reaction {
+    Then(Type, Promise),
+    /// Settle a promise resolved with this one the same way
+    Follow(Promise),
+}
+
+/// Result of an asynchronous computation, the value of a function or
+/// the exception it raised, delivered by a task of the event loop
+#[derive(Clone)]
+pub struct Promise(Rc<RefCell<State>>);
+
+impl fmt::Debug for Promise {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Promise")
+    }
+}
+
+impl Default for Promise {
+    fn default() -> Promise {
+        Promise::new()
+    }
+}
+
+impl Promise {
+    /// Create a promise settled by `deliver`
+    pub fn new() -> Promise {
+        Promise(Rc::new(RefCell::new(State::Pending(vec![]))))
+    }
+
+    /// Create a promise of the value of fun, called by the event loop
+    pub fn future(fun: Type) -> Promise {
+        let promise = Promise::new();
+        schedule(Task::Future(fun, promise.clone()));
+        promise
+    }
+
+    /// Return a promise of the value of fun called with the value of
+    /// this one. If it raised an exception, the new promise raises it
+    /// too.
+    pub fn then(&self, fun: Type) -> Promise {
+        let promise = Promise::new();
+        match *self.0.borrow_mut() {
+            State::Pending(ref mut reactions) => {
+                reactions.push(Reaction::Then(fun, promise.clone()))
+            }
+            State::Settled(ref result) => {
+                schedule(Task::Then(fun, result.clone(), promise.clone()))
+            }
+        }
+        promise
+    }
+
+    /// Settle the promise with value, or like value if it's a promise.
+    /// Return false if it was already settled.
+    pub fn deliver(&self, value: Type) -> bool {
+        if self.is_settled() {
+            return false;
+        }
+        match value {
+            Type::Promise(ref promise) if promise.same(self) => {
+                self.settle(Err(Exception::builtin("promise delivered with itself")))
+            }
+            Type::Promise(promise) => {
+                let result = match *promise.0.borrow_mut() {
+                    State::Pending(ref mut reactions) => {
+                        reactions.push(Reaction::Follow(self.clone()));
+                        None
+                    }
+                    State::Settled(ref result) => Some(result.clone()),
+                };
+                if let Some(result) = result {
+                    self.settle(result);
+                }
+            }
+            value => self.settle(Ok(value)),
+        }
+        true
+    }
+
+    /// Settle the promise with the result of a task
+    fn resolve(&self, result: Ret) {
+        match result {
+            Ok(value) => {
+                self.deliver(value);
+            }
+            Err(exc) => self.settle(Err(exc)),
+        }
+    }
+
+    fn settle(&self, result: Result<Type, Exception>) {
+        let reactions = match *self.0.borrow_mut() {
+            State::Pending(ref mut reactions) => std::mem::take(reactions),
+            State::Settled(_) => return,
+        };
+        self.0.replace(State::Settled(result.clone()));
+        for reaction in reactions {
+            match reaction {
+                Reaction::Then(fun, promise) => schedule(Task::Then(fun, result.clone(), promise)),
+                Reaction::Follow(promise) => promise.settle(result.clone()),
+            }
+        }
+    }
+
+    pub fn is_settled(&self) -> bool {
+        matches!(*self.0.borrow(), State::Settled(_))
+    }
+
+    /// Run the event loop until the promise is settled, and return its
+    /// value or raise its exception
+    pub fn wait(&self) -> Ret {
+        loop {
+            if let State::Settled(ref result) = *self.0.borrow() {
+                return result.clone();
+            }
+            if !step()? {
+                return Err(Exception::builtin("deref of a promise no task can deliver"));
+            }
+        }
+    }
+
+    /// Return true if both are the same promise
+    pub fn same(&self, other: &Promise) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+fn schedule(task: Task) {
+    EVENTS.with(|events| events.borrow_mut().ready.push_back(task));
+}
+
+/// Call fun after delay, every period after if it's an interval.
+/// Return the id of the timer.
+pub fn set_timer(fun: Type, delay: Duration, interval: bool) -> i64 {
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        events.last_id += 1;
+        let id = events.last_id;
+        let due = Instant::now() + delay;
+        // a null period would starve the other timers
+        let interval = interval.then_some(delay.max(Duration::from_millis(1)));
+        events.timers.insert((due, id), Timer { fun, interval });
+        events.due.insert(id, due);
+        id
+    })
+}
+
+/// Cancel a timer, return false if it already fired or was cancelled
+pub fn clear_timer(id: i64) -> bool {
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        match events.due.remove(&id) {
+            Some(due) => events.timers.remove(&(due, id)).is_some(),
+            None => false,
+        }
+    })
+}
+
+/// Run the tasks until none remains. An exception raised by a task
+/// stops the loop, the other tasks are left for the next run.
+pub fn run() -> Result<(), Exception> {
+    while step()? {}
+    Ok(())
+}
+
+/// Run the next task, waiting for the next timer if none is ready.
+/// Return false if there are no tasks.
+fn step() -> Result<bool, Exception> {
+    let task = EVENTS.with(|events| events.borrow_mut().ready.pop_front());
+    if let Some(task) = task {
+        match task {
+            Task::Future(fun, promise) => promise.resolve(fun.apply(vec![])),
+            Task::Then(fun, Ok(value), promise) => promise.resolve(fun.apply(vec![value])),
+            Task::Then(_, Err(exc), promise) => promise.settle(Err(exc)),
+        }
+        return Ok(true);
+    }
+
+    let next = EVENTS.with(|events| events.borrow().timers.keys().next().copied());
+    let (due, id) = match next {
+        Some(next) => next,
+        None => return Ok(false),
+    };
+    let now = Instant::now();
+    if due > now {
+        let mut sleep = (due - now).min(POLL);
+        if let Some(deadline) = sandbox::deadline() {
+            sleep = sleep.min(deadline.saturating_duration_since(now));
+        }
+        thread::sleep(sleep);
+        interrupt::check()?;
+        sandbox::check_deadline()?;
+        return Ok(true);
+    }
+
+    let fun = EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let timer = events.timers.remove(&(due, id)).unwrap();
+        match timer.interval {
+            Some(period) => {
+                // intervals keep their pace when a callback is late
+                let fun = timer.fun.clone();
+                events.timers.insert((due + period, id), timer);
+                events.due.insert(id, due + period);
+                fun
+            }
+            None => {
+                events.due.remove(&id);
+                timer.fun
+            }
+        }
+    });
+    fun.apply(vec![])?;
+    Ok(true)
+}
@@ -0,0 +1,132 @@
+use std::cell::{OnceCell, RefCell};
+use std::fmt;
+use std::rc::Rc;
+
+use crate::error::Exception;
+use crate::types::Type;
+use crate::vm::{self, Coroutine};
+
+/// Values yielded by a coroutine from a position on. The value at the
+/// position is realized the first time it's needed, by resuming the
+/// coroutine.
+struct Seq {
+    coroutine: Rc<RefCell<Coroutine>>,
+    /// Value at the position and the values after it, None if the
+    /// coroutine returned instead of yielding it
+    realized: OnceCell<Option<(Type, Rc<Seq>)>>,
+}
+
+impl Seq {
+    fn new(coroutine: Rc<RefCell<Coroutine>>) -> Rc<Seq> {
+        Rc::new(Seq {
+            coroutine,
+            realized: OnceCell::new(),
+        })
+    }
+
+    fn step(&self) -> Result<Option<&(Type, Rc<Seq>)>, Exception> {
+        if self.realized.get().is_none() {
+            let yielded = vm::resume(&self.coroutine)?;
+            let step = yielded.map(|value| (value, Seq::new(self.coroutine.clone())));
+            let _ = self.realized.set(step);
+        }
+        Ok(self.realized.get().unwrap().as_ref())
+    }
+}
+
+impl Drop for Seq {
+    fn drop(&mut self) {
+        // long realized chains are dropped without recursion
+        let mut next = self.realized.take().flatten().map(|(_, rest)| rest);
+        while let Some(seq) = next {
+            next = match Rc::try_unwrap(seq) {
+                Ok(mut seq) => seq.realized.take().flatten().map(|(_, rest)| rest),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+/// Cursor in the values yielded by a coroutine. The sequence functions
+/// return new cursors, only `next!` moves it.
+#[derive(Clone)]
+pub struct Generator(Rc<RefCell<Rc<Seq>>>);
+
+impl fmt::Debug for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Generator")
+    }
+}
+
+impl Generator {
+    /// Create a generator running fun as a coroutine. It's called when
+    /// the first value is needed.
+    pub fn new(fun: Type) -> Generator {
+        let coroutine = Rc::new(RefCell::new(Coroutine::Start(fun)));
+        Generator::at(Seq::new(coroutine))
+    }
+
+    fn at(seq: Rc<Seq>) -> Generator {
+        Generator(Rc::new(RefCell::new(seq)))
+    }
+
+    fn seq(&self) -> Rc<Seq> {
+        self.0.borrow().clone()
+    }
+
+    /// Return the value at the cursor, or None if the coroutine
+    /// returned
+    pub fn first(&self) -> Result<Option<Type>, Exception> {
+        Ok(self.seq().step()?.map(|(value, _)| value.clone()))
+    }
+
+    /// Return a new cursor after the value at this one
+    pub fn rest(&self) -> Result<Generator, Exception> {
+        let seq = self.seq();
+        let rest = seq.step()?.map(|(_, rest)| rest.clone());
+        Ok(Generator::at(rest.unwrap_or(seq)))
+    }
+
+    /// Return a new cursor at the same value
+    pub fn copy(&self) -> Generator {
+        Generator::at(self.seq())
+    }
+
+    /// Return the value at the cursor and move it to the next one
+    pub fn next(&self) -> Result<Option<Type>, Exception> {
+        let step = self.seq().step()?.cloned();
+        Ok(step.map(|(value, rest)| {
+            *self.0.borrow_mut() = rest;
+            value
+        }))
+    }
+
+    /// Return the value index positions after the cursor
+    pub fn nth(&self, index: usize) -> Result<Option<Type>, Exception> {
+        let mut seq = self.seq();
+        for _ in 0..index {
+            seq = match seq.step()? {
+                Some((_, rest)) => rest.clone(),
+                None => return Ok(None),
+            };
+        }
+        Ok(seq.step()?.map(|(value, _)| value.clone()))
+    }
+
+    /// Return the values from the cursor on, running the coroutine
+    /// until it returns
+    pub fn values(&self) -> Result<Vec<Type>, Exception> {
+        let mut values = vec![];
+        let mut seq = self.seq();
+        while let Some((value, rest)) = seq.step()?.cloned() {
+            values.push(value);
+            seq = rest;
+        }
+        Ok(values)
+    }
+
+    /// Return true if both are the same cursor
+    pub fn same(&self, other: &Generator) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
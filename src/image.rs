@@ -0,0 +1,612 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::analyzer::Lambda;
+use crate::core::Namespace;
+use crate::env::Env;
+use crate::error::Exception;
+use crate::ns;
+use crate::printer;
+use crate::sandbox;
+use crate::symbol::Symbol;
+use crate::trace;
+use crate::types::{Function, Type};
+
+/// First bytes of every image file
+const MAGIC: &[u8] = b"MALIMAGE";
+
+/// Version of the image layout. Increase it on any change to the way
+/// images are written, images with other versions are rejected.
+pub const IMAGE_FORMAT_VERSION: u32 = 3;
+
+/// Version of the interpreter. Builtins and core definitions may change
+/// between releases, so images are only restored by the same version
+/// that saved them.
+const INTERPRETER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const NIL: u8 = 0;
+const FALSE: u8 = 1;
+const TRUE: u8 = 2;
+const INT: u8 = 3;
+const FLOAT: u8 = 4;
+const SYMBOL: u8 = 5;
+const KEYWORD: u8 = 6;
+const STRING: u8 = 7;
+const ATOM: u8 = 8;
+const LIST: u8 = 9;
+const VECTOR: u8 = 10;
+const HASH_MAP: u8 = 11;
+const FUN: u8 = 12;
+const CLOSURE: u8 = 13;
+const MACRO: u8 = 14;
+
+const LOCAL_ENV: u8 = 0;
+const NAMESPACE_ENV: u8 = 1;
+
+/// Save the state of the interpreter env belongs to: every namespace
+/// with its vars, and the closures, captured environments and atoms
+/// reachable from them. Shared environments and atoms are saved once,
+/// so they are still shared when the image is loaded.
+pub fn save(env: &Rc<Env>, path: &Path) -> Result<(), Exception> {
+    sandbox::check_io("write files")?;
+    let root = env.outermost();
+    let graph = Graph::collect(&root)?;
+
+    let mut writer = Writer {
+        buf: vec![],
+        graph: &graph,
+    };
+    writer.header();
+    writer.envs();
+    writer.registry(&root);
+
+    fs::write(path, writer.buf).map_err(|err| {
+        Exception::io_error(&format!("can't save image '{}': {}", path.display(), err))
+    })
+}
+
+/// Load an image saved with `save` and return its root environment
+pub fn load(path: &Path) -> Result<Rc<Env>, Exception> {
+    let data = fs::read(path).map_err(|err| {
+        Exception::io_error(&format!("can't load image '{}': {}", path.display(), err))
+    })?;
+
+    let mut reader = Reader {
+        data: &data,
+        position: 0,
+        envs: vec![],
+        atoms: vec![],
+        builtins: Namespace::new_default().into_iter().collect(),
+    };
+    reader.image().map_err(|err| {
+        Exception::builtin(&format!("can't load image '{}': {}", path.display(), err))
+    })
+}
+
+/// Environments and atoms reachable from a root environment, in the
+/// order they are saved. Outer environments always come before the
+/// environments they enclose.
+struct Graph {
+    envs: Vec<Rc<Env>>,
+    env_ids: HashMap<*const Env, usize>,
+    atoms: Vec<Rc<RefCell<Type>>>,
+    atom_ids: HashMap<*const RefCell<Type>, usize>,
+}
+
+impl Graph {
+    /// Fail if a value reachable from root can't be saved: the state
+    /// of a suspended coroutine, of a continuation or of the event loop
+    fn collect(root: &Rc<Env>) -> Result<Graph, Exception> {
+        let mut graph = Graph {
+            envs: vec![],
+            env_ids: HashMap::new(),
+            atoms: vec![],
+            atom_ids: HashMap::new(),
+        };
+
+        graph.add_env(root);
+        for (_, ns_env) in ns::all(root) {
+            graph.add_env(&ns_env);
+        }
+
+        // environments found while walking the values are walked too
+        let mut next = 0;
+        while next < graph.envs.len() {
+            let env = graph.envs[next].clone();
+            for (symbol, value) in env.slot_bindings().iter().chain(&env.defined_bindings()) {
+                graph.add_value(value).map_err(|unsaved| {
+                    Exception::builtin(&format!(
+                        "can't save {} in an image (found in '{}')",
+                        printer::pr_str(unsaved, true),
+                        symbol
+                    ))
+                })?;
+            }
+            next += 1;
+        }
+
+        Ok(graph)
+    }
+
+    fn add_env(&mut self, env: &Rc<Env>) {
+        if self.env_ids.contains_key(&Rc::as_ptr(env)) {
+            return;
+        }
+        if let Some(outer) = env.outer() {
+            self.add_env(outer);
+        }
+        self.env_ids.insert(Rc::as_ptr(env), self.envs.len());
+        self.envs.push(env.clone());
+    }
+
+    /// Add the atoms and environments reachable from value, or return
+    /// the value found in it that can't be saved
+    fn add_value(&mut self, value: &Type) -> Result<(), Type> {
+        match value {
+            Type::Atom(atom) => {
+                if self.atom_ids.contains_key(&Rc::as_ptr(atom)) {
+                    return Ok(());
+                }
+                self.atom_ids.insert(Rc::as_ptr(atom), self.atoms.len());
+                self.atoms.push(atom.clone());
+                self.add_value(&atom.borrow())
+            }
+            Type::List(seq) | Type::Vector(seq) => {
+                seq.iter().try_for_each(|elem| self.add_value(elem))
+            }
+            Type::HashMap(hash_map) => hash_map
+                .values()
+                .try_for_each(|value| self.add_value(value)),
+            Type::Closure { env, lambda, .. } => {
+                self.add_env(env);
+                self.add_value(&lambda.params)?;
+                self.add_value(&lambda.body)
+            }
+            Type::Generator(_) | Type::Continuation(_) | Type::Promise(_) => Err(value.clone()),
+            _ => Ok(()),
+        }
+    }
+
+    fn env_id(&self, env: &Rc<Env>) -> usize {
+        self.env_ids[&Rc::as_ptr(env)]
+    }
+
+    fn atom_id(&self, atom: &Rc<RefCell<Type>>) -> usize {
+        self.atom_ids[&Rc::as_ptr(atom)]
+    }
+}
+
+struct Writer<'a> {
+    buf: Vec<u8>,
+    graph: &'a Graph,
+}
+
+impl Writer<'_> {
+    fn header(&mut self) {
+        self.buf.extend(MAGIC);
+        self.buf.extend(IMAGE_FORMAT_VERSION.to_le_bytes());
+        self.string(INTERPRETER_VERSION);
+    }
+
+    /// Write the environment and atom tables, then their contents
+    fn envs(&mut self) {
+        let graph = self.graph;
+
+        self.number(graph.envs.len());
+        for env in graph.envs.iter() {
+            match env.outer() {
+                Some(outer) => self.number(graph.env_id(outer) + 1),
+                None => self.number(0),
+            }
+            match env.namespace_name() {
+                Some(name) => {
+                    self.byte(NAMESPACE_ENV);
+                    self.string(&name);
+                }
+                None => self.byte(LOCAL_ENV),
+            }
+        }
+        self.number(graph.atoms.len());
+
+        for env in graph.envs.iter() {
+            self.bindings(&env.slot_bindings());
+            self.bindings(&env.defined_bindings());
+            if env.is_namespace() {
+                self.ns_info(env);
+            }
+        }
+
+        for atom in graph.atoms.iter() {
+            self.value(&atom.borrow());
+        }
+    }
+
+    fn bindings(&mut self, bindings: &[(Symbol, Type)]) {
+        self.number(bindings.len());
+        for (symbol, value) in bindings {
+            self.string(&symbol.name());
+            self.value(value);
+        }
+    }
+
+    fn ns_info(&mut self, ns_env: &Env) {
+        let info = ns_env.ns_info().unwrap();
+
+        let mut aliases: Vec<(String, String)> = info
+            .aliases
+            .borrow()
+            .iter()
+            .map(|(alias, name)| (alias.clone(), name.clone()))
+            .collect();
+        aliases.sort();
+        self.number(aliases.len());
+        for (alias, name) in aliases {
+            self.string(&alias);
+            self.string(&name);
+        }
+
+        let mut refers: Vec<(Symbol, String)> = info
+            .refers
+            .borrow()
+            .iter()
+            .map(|(symbol, name)| (*symbol, name.clone()))
+            .collect();
+        refers.sort_by_key(|(symbol, _)| symbol.name());
+        self.number(refers.len());
+        for (symbol, name) in refers {
+            self.string(&symbol.name());
+            self.string(&name);
+        }
+
+        let mut dynamic: Vec<Symbol> = info.dynamic.borrow().iter().cloned().collect();
+        dynamic.sort_by_key(|symbol| symbol.name());
+        self.number(dynamic.len());
+        for symbol in dynamic {
+            self.string(&symbol.name());
+        }
+    }
+
+    fn registry(&mut self, root: &Rc<Env>) {
+        let registry = root.registry().unwrap();
+        self.string(&registry.current());
+
+        let namespaces: Vec<(String, Rc<Env>)> = ns::all(root)
+            .into_iter()
+            .filter(|(name, _)| name != ns::CORE_NS)
+            .collect();
+        self.number(namespaces.len());
+        for (name, ns_env) in namespaces {
+            self.string(&name);
+            self.number(self.graph.env_id(&ns_env));
+        }
+
+        let mut modules: Vec<(String, String)> = registry
+            .modules
+            .borrow()
+            .iter()
+            .map(|(name, file)| (name.clone(), file.to_string_lossy().to_string()))
+            .collect();
+        modules.sort();
+        self.number(modules.len());
+        for (name, file) in modules {
+            self.string(&name);
+            self.string(&file);
+        }
+    }
+
+    fn value(&mut self, value: &Type) {
+        match value {
+            Type::Nil => self.byte(NIL),
+            Type::Bool(false) => self.byte(FALSE),
+            Type::Bool(true) => self.byte(TRUE),
+            Type::Int(int) => {
+                self.byte(INT);
+                self.buf.extend(int.to_le_bytes());
+            }
+            Type::Float(float) => {
+                self.byte(FLOAT);
+                self.buf.extend(float.to_le_bytes());
+            }
+            Type::Symbol(symbol) => {
+                self.byte(SYMBOL);
+                self.string(&symbol.name());
+            }
+            Type::Keyword(keyword) => {
+                self.byte(KEYWORD);
+                self.string(keyword);
+            }
+            Type::String(string) => {
+                self.byte(STRING);
+                self.string(string);
+            }
+            Type::Atom(atom) => {
+                self.byte(ATOM);
+                self.number(self.graph.atom_id(atom));
+            }
+            Type::List(seq) | Type::Vector(seq) => {
+                self.byte(if value.is_list() { LIST } else { VECTOR });
+                self.number(seq.len());
+                for elem in seq {
+                    self.value(elem);
+                }
+            }
+            Type::HashMap(hash_map) => {
+                self.byte(HASH_MAP);
+                self.number(hash_map.len());
+                let mut keys: Vec<&String> = hash_map.keys().collect();
+                keys.sort();
+                for key in keys {
+                    self.string(key);
+                    self.value(&hash_map[key]);
+                }
+            }
+            Type::Generator(_) | Type::Continuation(_) | Type::Promise(_) => {
+                unreachable!("unsaved values are rejected when collected")
+            }
+            Type::Fun(_, name) => {
+                self.byte(FUN);
+                self.string(&name.name());
+            }
+            Type::Closure {
+                env,
+                lambda,
+                is_macro,
+            } => {
+                // closures are saved unanalyzed, their bodies are
+                // analyzed again when they are first called
+                self.byte(if *is_macro { MACRO } else { CLOSURE });
+                self.number(self.graph.env_id(env));
+                self.value(&lambda.params);
+                self.value(&lambda.body);
+            }
+        }
+    }
+
+    fn byte(&mut self, byte: u8) {
+        self.buf.push(byte);
+    }
+
+    fn number(&mut self, number: usize) {
+        self.buf.extend((number as u64).to_le_bytes());
+    }
+
+    fn string(&mut self, string: &str) {
+        self.number(string.len());
+        self.buf.extend(string.as_bytes());
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+    envs: Vec<Rc<Env>>,
+    atoms: Vec<Rc<RefCell<Type>>>,
+    builtins: HashMap<String, Function>,
+}
+
+impl Reader<'_> {
+    fn image(&mut self) -> Result<Rc<Env>, String> {
+        self.header()?;
+        self.envs()?;
+        self.registry()?;
+
+        if self.position != self.data.len() {
+            return Err(String::from("corrupted image"));
+        }
+        Ok(self.envs[0].clone())
+    }
+
+    fn header(&mut self) -> Result<(), String> {
+        if !self.data.starts_with(MAGIC) {
+            return Err(String::from("not a mal-rust image"));
+        }
+        self.position = MAGIC.len();
+
+        let format = u32::from_le_bytes(self.bytes(4)?.try_into().unwrap());
+        let version = if format == IMAGE_FORMAT_VERSION {
+            self.string()?
+        } else {
+            String::from("unknown")
+        };
+        if format != IMAGE_FORMAT_VERSION || version != INTERPRETER_VERSION {
+            return Err(format!(
+                "stale image saved by mal-rust {} (image format {}), \
+                 this is mal-rust {} (image format {})",
+                version, format, INTERPRETER_VERSION, IMAGE_FORMAT_VERSION
+            ));
+        }
+        Ok(())
+    }
+
+    fn envs(&mut self) -> Result<(), String> {
+        let nenvs = self.number()?;
+        for id in 0..nenvs {
+            let outer = match self.number()? {
+                0 => None,
+                outer if outer <= id => Some(self.envs[outer - 1].clone()),
+                _ => return Err(String::from("corrupted image")),
+            };
+            let env = match (self.byte()?, outer) {
+                (NAMESPACE_ENV, None) if id == 0 => {
+                    self.string()?;
+                    Env::new_root()
+                }
+                (NAMESPACE_ENV, Some(outer)) => Env::new_namespace(outer, &self.string()?),
+                (LOCAL_ENV, Some(outer)) => Env::new(Some(outer), &[], &[]),
+                _ => return Err(String::from("corrupted image")),
+            };
+            self.envs.push(Rc::new(env));
+        }
+        if self.envs.is_empty() {
+            return Err(String::from("corrupted image"));
+        }
+
+        let natoms = self.number()?;
+        for _ in 0..natoms {
+            self.atoms.push(Rc::new(RefCell::new(Type::Nil)));
+        }
+
+        for id in 0..nenvs {
+            let env = self.envs[id].clone();
+            for _ in 0..self.number()? {
+                let symbol = self.symbol()?;
+                env.bind(symbol, self.value()?);
+            }
+            for _ in 0..self.number()? {
+                let symbol = self.symbol()?;
+                let value = self.value()?;
+                value.name_closure(symbol);
+                if env.is_namespace() {
+                    trace::set_var(symbol, &value);
+                }
+                env.set(symbol, value);
+            }
+            if env.is_namespace() {
+                self.ns_info(&env)?;
+            }
+        }
+
+        for id in 0..natoms {
+            let value = self.value()?;
+            *self.atoms[id].borrow_mut() = value;
+        }
+
+        Ok(())
+    }
+
+    fn ns_info(&mut self, ns_env: &Env) -> Result<(), String> {
+        let info = ns_env.ns_info().unwrap();
+
+        for _ in 0..self.number()? {
+            let alias = self.string()?;
+            let name = self.string()?;
+            info.aliases.borrow_mut().insert(alias, name);
+        }
+        for _ in 0..self.number()? {
+            let symbol = self.symbol()?;
+            let name = self.string()?;
+            info.refers.borrow_mut().insert(symbol, name);
+        }
+        for _ in 0..self.number()? {
+            let symbol = self.symbol()?;
+            info.dynamic.borrow_mut().insert(symbol);
+        }
+
+        Ok(())
+    }
+
+    fn registry(&mut self) -> Result<(), String> {
+        let registry = self.envs[0].registry().unwrap();
+        let current = self.string()?;
+
+        for _ in 0..self.number()? {
+            let name = self.string()?;
+            let ns_env = self.env()?;
+            registry.namespaces.borrow_mut().insert(name, ns_env);
+        }
+        for _ in 0..self.number()? {
+            let name = self.string()?;
+            let file = self.string()?;
+            registry.modules.borrow_mut().insert(name, file.into());
+        }
+        registry.set_current(&current);
+
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Type, String> {
+        Ok(match self.byte()? {
+            NIL => Type::Nil,
+            FALSE => Type::Bool(false),
+            TRUE => Type::Bool(true),
+            INT => Type::Int(i64::from_le_bytes(self.bytes(8)?.try_into().unwrap())),
+            FLOAT => Type::Float(f64::from_le_bytes(self.bytes(8)?.try_into().unwrap())),
+            SYMBOL => Type::Symbol(self.symbol()?),
+            KEYWORD => Type::Keyword(self.string()?),
+            STRING => Type::String(self.string()?),
+            ATOM => {
+                let id = self.number()?;
+                match self.atoms.get(id) {
+                    Some(atom) => Type::Atom(atom.clone()),
+                    None => return Err(String::from("corrupted image")),
+                }
+            }
+            tag @ (LIST | VECTOR) => {
+                let len = self.number()?;
+                let mut seq = Vec::with_capacity(len.min(self.data.len()));
+                for _ in 0..len {
+                    seq.push(self.value()?);
+                }
+                if tag == LIST {
+                    Type::List(seq)
+                } else {
+                    Type::Vector(seq)
+                }
+            }
+            HASH_MAP => {
+                let mut hash_map = HashMap::new();
+                for _ in 0..self.number()? {
+                    let key = self.string()?;
+                    hash_map.insert(key, Box::new(self.value()?));
+                }
+                Type::HashMap(hash_map)
+            }
+            FUN => {
+                let name = self.string()?;
+                match self.builtins.get(&name) {
+                    Some(fun) => Type::Fun(*fun, Symbol::new(&name)),
+                    None => return Err(format!("unknown builtin '{}'", name)),
+                }
+            }
+            tag @ (CLOSURE | MACRO) => {
+                let env = self.env()?;
+                let params = self.value()?;
+                let body = self.value()?;
+                Type::Closure {
+                    env,
+                    lambda: Rc::new(Lambda::new(params, body)),
+                    is_macro: tag == MACRO,
+                }
+            }
+            _ => return Err(String::from("corrupted image")),
+        })
+    }
+
+    fn env(&mut self) -> Result<Rc<Env>, String> {
+        let id = self.number()?;
+        match self.envs.get(id) {
+            Some(env) => Ok(env.clone()),
+            None => Err(String::from("corrupted image")),
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&[u8], String> {
+        if len > self.data.len() - self.position {
+            return Err(String::from("corrupted image"));
+        }
+        let bytes = &self.data[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn number(&mut self) -> Result<usize, String> {
+        let number = u64::from_le_bytes(self.bytes(8)?.try_into().unwrap());
+        usize::try_from(number).map_err(|_| String::from("corrupted image"))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.number()?;
+        String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| String::from("corrupted image"))
+    }
+
+    fn symbol(&mut self) -> Result<Symbol, String> {
+        Ok(Symbol::new(&self.string()?))
+    }
+}
@@ -0,0 +1,36 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::Exception;
+
+/// Set by `interrupt`, from any thread or a signal handler
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Interrupt the running evaluation: its next function call raises an
+/// "Interrupted" exception of kind `:interrupt`, which only the
+/// `catch*` clauses selecting that kind catch. The flag is shared by
+/// all the threads, the first one making a call takes it.
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+/// Drop the pending interrupt, if any. Called when a top level
+/// evaluation starts, so an interrupt the previous one didn't see
+/// doesn't abort it.
+pub fn clear() {
+    INTERRUPTED.store(false, Ordering::Relaxed);
+}
+
+/// Raise the pending interrupt, if any
+#[inline(always)]
+pub fn check() -> Result<(), Exception> {
+    if INTERRUPTED.load(Ordering::Relaxed) {
+        return Err(interrupted());
+    }
+    Ok(())
+}
+
+#[cold]
+fn interrupted() -> Exception {
+    INTERRUPTED.store(false, Ordering::Relaxed);
+    Exception::interrupted()
+}
@@ -1,437 +1,706 @@
 use std::collections::HashMap;
+use std::path::Path;
 use std::rc::Rc;
 
-use error::{nargs_check, Exception};
+use error::{Exception, Kind};
 
 use crate::{
+    analyzer::{Catch, Lambda, Node, Selector, Target},
     env::Env,
+    stack::{Call, Depth},
+    symbol::Symbol,
     types::{Ret, Type},
 };
 
+mod analyzer;
+mod continuation;
 mod core;
+mod debug;
 pub mod env;
 mod error;
+mod event;
+mod generator;
+mod image;
+mod interrupt;
+mod module;
+mod ns;
 mod printer;
 mod reader;
+mod sandbox;
+mod stack;
+pub mod symbol;
+mod trace;
 mod types;
 mod utils;
+mod vm;
+
+pub use debug::{set_debug_on_exception, set_debugger, Debugger};
+pub use interrupt::interrupt;
+pub use sandbox::{set_sandbox, Sandbox};
+pub use stack::{
+    max_depth, set_max_depth, set_stack_traces, stack_size_for, DEFAULT_MAX_DEPTH, MAX_STACK_SIZE,
+};
+pub use trace::set_trace_output;
+pub use vm::{set_backend, Backend};
 
 fn read(input: &str) -> Result<Option<Type>, String> {
     reader::read_str(input)
 }
 
-struct TcoVals {
-    ast: Option<Type>,
-    env: Option<Rc<Env>>,
+fn eval(ast: Type, env: &Rc<Env>) -> Ret {
+    let env = if env.is_namespace() {
+        // top level forms are evaluated in the current namespace
+        ns::current(env)
+    } else {
+        env.clone()
+    };
+    eval_node(&analyzer::analyze(&ast, &env), &env)
 }
 
-fn eval(ast: Type, env: &Rc<Env>) -> Ret {
-    let mut ast = ast;
-    let mut tco_env = env.clone(); // use as owner
-    let mut env; // reuse parameter names but use them as mutable
-
-    let result;
-    let mut tco_values = None;
-
-    'tco: loop {
-        if let Some(TcoVals {
-            ast: new_ast,
-            env: new_env,
-        }) = tco_values
-        {
-            ast = new_ast.unwrap_or(ast);
-            tco_env = new_env.unwrap_or(tco_env);
+/// Evaluate analyzed code in env
+fn eval_node(node: &Rc<Node>, env: &Rc<Env>) -> Ret {
+    let depth = stack::enter()?;
+    let result = eval_loop(node.clone(), env.clone(), &depth);
+    leave(&depth, result)
+}
+
+/// Apply a closure to args with the tree-walking interpreter
+fn apply_closure(lambda: &Rc<Lambda>, env: &Rc<Env>, args: &[Type]) -> Ret {
+    lambda.check_nargs(args.len())?;
+    let depth = stack::enter()?;
+    let fun_env = Rc::new(Env::new(Some(env.clone()), &lambda.binds, args));
+    stack::tail_call(&depth, Call::Closure(lambda.clone(), fun_env.clone()))?;
+    let result = eval_loop(lambda.body_node(env), fun_env, &depth);
+    leave(&depth, result)
+}
+
+/// Return the result of the level saved in depth. Exceptions get the
+/// call stack and the debugger can stop at the return of the call made
+/// at this level.
+#[inline(always)]
+fn leave(depth: &Depth, result: Ret) -> Ret {
+    match result {
+        Ok(value) => {
+            if stack::made_call(depth) && stack::hooked() {
+                stack::returned(&value);
+            }
+            Ok(value)
         }
+        Err(exc) => Err(stack::traced(exc)),
+    }
+}
+
+/// Evaluate node in env at the level of depth. Calls in tail position
+/// reuse the loop, replacing the call of the level.
+#[inline(always)]
+fn eval_loop(mut node: Rc<Node>, mut env: Rc<Env>, depth: &Depth) -> Ret {
+    // body of the loop entered last, run again by its recur forms
+    let mut loop_body = None;
+    loop {
+        if env.is_namespace() {
+            // top level forms are evaluated in the current namespace
+            env = ns::current(&env);
+        }
+
+        let (next_node, next_env) = match *node {
+            Node::Constant(ref value) => return Ok(value.clone()),
+
+            Node::Global(symbol) => return env.get(symbol),
+
+            Node::Local {
+                symbol,
+                depth,
+                slot,
+            } => return env.get_address(depth, slot, symbol),
+
+            Node::Let {
+                ref bindings,
+                ref body,
+            } => {
+                let scope_env = Rc::new(Env::new(Some(env.clone()), &[], &[]));
+                for (name, value) in bindings {
+                    let value = eval_node(value, &scope_env)?;
+                    scope_env.bind(*name, value);
+                }
+                (body.clone(), scope_env)
+            }
 
-        env = &tco_env;
+            Node::Loop {
+                ref bindings,
+                ref body,
+            } => {
+                let scope_env = Rc::new(Env::new(Some(env.clone()), &[], &[]));
+                for (name, value) in bindings {
+                    let value = eval_node(value, &scope_env)?;
+                    scope_env.bind(*name, value);
+                }
+                loop_body = Some(body.clone());
+                (body.clone(), scope_env)
+            }
+
+            Node::Recur {
+                ref args,
+                depth,
+                ref slots,
+                target,
+            } => {
+                let values = eval_nodes(args, &env)?;
+                let env = recur_env(&env, depth, slots, values)?;
+                let body = match target {
+                    Target::Loop => loop_body.clone().expect("recur is in a loop"),
+                    Target::Fn => stack::recur_call(&env).body_node(env.outer().unwrap()),
+                };
+                (body, env)
+            }
 
-        ast = macroexpand(ast, env)?;
+            Node::Do(ref forms) => {
+                let (last, forms) = forms.split_last().unwrap();
+                for form in forms {
+                    eval_node(form, &env)?;
+                }
+                (last.clone(), env)
+            }
 
-        result = match ast {
-            Type::List(ref list) => {
-                if list.is_empty() {
-                    Ok(ast)
+            Node::If {
+                ref cond,
+                ref then,
+                ref otherwise,
+            } => {
+                if eval_node(cond, &env)?.is_truthy() {
+                    (then.clone(), env)
                 } else {
-                    match list[0].to_owned() {
-                        Type::Symbol(symbol) if symbol == "def!" => {
-                            nargs_check("def!", 2, list.len() - 1)?;
-
-                            let name = match list[1].to_owned() {
-                                Type::Symbol(name) => name,
-                                _ => {
-                                    return Err(Exception::type_error(
-                                        "first def! argument must be a symbol",
-                                    ))
-                                }
-                            };
-                            let value = list[2].to_owned();
-                            let value = eval(value, env)?;
-                            env.set(&name, value.clone());
-                            Ok(value)
-                        }
+                    match otherwise {
+                        Some(otherwise) => (otherwise.clone(), env),
+                        None => return Ok(Type::Nil),
+                    }
+                }
+            }
 
-                        Type::Symbol(symbol) if symbol == "let*" => {
-                            nargs_check("let*", 2, list.len() - 1)?;
-
-                            let scope_env = Rc::new(Env::new(Some(env.clone()), &[], &[]));
-
-                            let binding_list = match list[1].to_owned() {
-                                Type::List(seq) | Type::Vector(seq) => seq,
-                                _ => {
-                                    return Err(Exception::type_error(
-                                        "first let* argument must be a list",
-                                    ))
-                                }
-                            };
-                            if binding_list.len() % 2 != 0 {
-                                return Err(Exception::builtin(
-                                    "let* binding list must be composed of pairs",
-                                ));
-                            }
-                            let mut i = 0;
-                            while i + 1 < binding_list.len() {
-                                let symbol = match binding_list[i].to_owned() {
-                                    Type::Symbol(symbol) => symbol,
-                                    _ => {
-                                        return Err(Exception::builtin(
-                                            "let* variable names must be symbols",
-                                        ))
-                                    }
-                                };
-
-                                let value = binding_list[i + 1].to_owned();
-                                let value = eval(value, &scope_env)?;
-
-                                scope_env.set(&symbol, value);
-
-                                i += 2;
-                            }
-
-                            let scoped_code = list[2].to_owned();
-
-                            tco_values = Some(TcoVals {
-                                ast: Some(scoped_code),
-                                env: Some(scope_env),
-                            });
-                            // eval(scoped_code, &scope_env)
-                            continue 'tco;
-                        }
+            Node::Fn(ref lambda) => {
+                return Ok(Type::Closure {
+                    env,
+                    lambda: lambda.clone(),
+                    is_macro: false,
+                })
+            }
 
-                        Type::Symbol(symbol) if symbol == "do" => {
-                            let (last, do_list) = list.split_last().unwrap();
-                            let do_list = Type::List(do_list[1..].to_vec());
-                            eval_ast(do_list, env)?;
-                            tco_values = Some(TcoVals {
-                                ast: Some(last.to_owned()),
-                                env: None,
-                            });
-                            continue 'tco;
-                        }
+            Node::Eval(ref form) => {
+                let ast = eval_node(form, &env)?;
+                let root = ns::current(&env.outermost());
+                (analyzer::analyze(&ast, &root), root)
+            }
 
-                        Type::Symbol(symbol) if symbol == "if" => {
-                            if list.len() < 3 || list.len() > 4 {
-                                return Err(Exception::builtin("Malformed if expression"));
-                            }
-
-                            let cond = list.get(1).unwrap().to_owned();
-                            let if_clause = list.get(2).unwrap().to_owned();
-                            let else_clause = list.get(3);
-
-                            if !matches!(eval(cond, env)?, Type::Bool(false) | Type::Nil) {
-                                eval(if_clause, env)
-                            } else if else_clause.is_some() {
-                                let else_clause = else_clause.unwrap().to_owned();
-                                tco_values = Some(TcoVals {
-                                    ast: Some(else_clause),
-                                    env: None,
-                                });
-                                continue 'tco;
-                            } else {
-                                Ok(Type::Nil)
-                            }
+            Node::Try {
+                ref body,
+                ref catches,
+                finally: None,
+            } => match eval_try(body, catches, &env)? {
+                Tried::Value(value) => return Ok(value),
+                Tried::Caught(handler, exc_env) => (handler, exc_env),
+            },
+
+            Node::Call(ref call) => {
+                let mut f = eval_node(&call[0], &env)?;
+                let mut args = eval_nodes(&call[1..], &env)?;
+
+                loop {
+                    match f {
+                        Type::Fun(_, symbol::APPLY) => (f, args) = core::apply_args(args)?,
+                        Type::Fun(fun, name) => return stack::call_builtin(fun, name, args),
+                        Type::Closure { env, lambda, .. } => {
+                            lambda.check_nargs(args.len())?;
+                            let body = lambda.body_node(&env);
+                            let fun_env = Rc::new(Env::new(Some(env), &lambda.binds, &args));
+                            stack::tail_call(depth, Call::Closure(lambda, fun_env.clone()))?;
+                            break (body, fun_env);
                         }
-
-                        Type::Symbol(symbol) if symbol == "fn*" => {
-                            nargs_check("fn*", 2, list.len() - 1)?;
-
-                            let params = match list[1] {
-                                Type::List(_) | Type::Vector(_) => list[1].clone(),
-                                _ => {
-                                    return Err(Exception::builtin(
-                                        "fn* must be defined with a sequence as parameter",
-                                    ))
-                                }
-                            };
-                            let body = list[2].clone();
-
-                            let closure = Type::Closure {
-                                params: Box::new(params),
-                                body: Box::new(body),
-                                env: env.clone(),
-                                is_macro: false,
-                            };
-
-                            Ok(closure)
+                        Type::Continuation(continuation) => return continuation.invoke(args),
+                        _ => {
+                            return Err(Exception::type_error("first argument must be a function"))
                         }
+                    }
+                }
+            }
 
-                        Type::Symbol(symbol) if symbol == "eval" => {
-                            nargs_check("eval", 1, list.len() - 1)?;
+            Node::MacroCall { .. } => (analyzer::expansion(&node, &env)?.node.clone(), env),
 
-                            tco_values = Some(TcoVals {
-                                ast: Some(eval(list[1].to_owned(), env)?),
-                                env: Some(env.outermost()),
-                            });
+            _ => return eval_form(&node, &env),
+        };
 
-                            continue 'tco;
-                        }
+        node = next_node;
+        env = next_env;
+    }
+}
 
-                        Type::Symbol(symbol) if symbol == "quote" => {
-                            nargs_check("quote", 1, list.len() - 1)?;
+/// Value of a `try*` body, or handler of the `catch*` clause catching
+/// its exception and the environment binding the exception
+enum Tried {
+    Value(Type),
+    Caught(Rc<Node>, Rc<Env>),
+}
 
-                            Ok(list[1].to_owned())
-                        }
+fn eval_try(body: &Rc<Node>, catches: &[Catch], env: &Rc<Env>) -> Result<Tried, Exception> {
+    if catches.is_empty() {
+        return eval_node(body, env).map(Tried::Value);
+    }
 
-                        Type::Symbol(symbol) if symbol == "quasiquote" => {
-                            tco_values = Some(TcoVals {
-                                ast: Some(quasiquote(list[1].to_owned())?),
-                                env: None,
-                            });
+    let result = {
+        let _trying = debug::Trying::new();
+        eval_node(body, env)
+    };
+    match result {
+        Ok(value) => Ok(Tried::Value(value)),
+        Err(exc) => {
+            let (index, exception) = catching(catches, exc, env)?;
+            let catch = &catches[index];
+            let exc_env = Env::new(Some(env.clone()), &[catch.var], &[exception]);
+            Ok(Tried::Caught(catch.handler.clone(), Rc::new(exc_env)))
+        }
+    }
+}
 
-                            continue 'tco;
-                        }
+/// Return the index of the first `catch*` clause catching an exception
+/// and the value it's bound to, or the exception to rethrow if no
+/// clause catches it. The predicates are evaluated in env. Interrupts
+/// are only caught by the clauses selecting their kind, so loops
+/// catching every exception can still be interrupted.
+fn catching(catches: &[Catch], exc: Exception, env: &Rc<Env>) -> Result<(usize, Type), Exception> {
+    if exc.is_escape() {
+        return Err(exc);
+    }
+    let exc = stack::traced(exc);
+    let interrupted = exc.kind() == Some(Kind::Interrupt);
+    for (index, catch) in catches.iter().enumerate() {
+        let caught = match catch.selector {
+            Selector::Kind(kind) => exc.kind() == Some(kind),
+            _ if interrupted => false,
+            Selector::All => true,
+            Selector::Predicate(ref predicate) => {
+                let predicate = eval_node(predicate, env)?;
+                predicate.apply(vec![exc.clone().into_value()])?.is_truthy()
+            }
+        };
+        if caught {
+            return Ok((index, stack::catch(exc)));
+        }
+    }
+    Err(exc)
+}
 
-                        Type::Symbol(symbol) if symbol == "quasiquoteexpand" => {
-                            Ok(quasiquote(list[1].to_owned())?)
-                        }
+/// Return the environment `recur` runs its target in: the scope depth
+/// levels out of env bound again to values
+fn recur_env(
+    env: &Rc<Env>,
+    depth: usize,
+    slots: &[Symbol],
+    values: Vec<Type>,
+) -> Result<Rc<Env>, Exception> {
+    stack::iterate()?;
+    let scope = Env::new(env.ancestor(depth).outer().cloned(), &[], &[]);
+    for (slot, value) in slots.iter().zip(values) {
+        scope.bind(*slot, value);
+    }
+    Ok(Rc::new(scope))
+}
 
-                        Type::Symbol(symbol) if symbol == "defmacro!" => {
-                            nargs_check("defmacro!", 2, list.len() - 1)?;
-
-                            let name = match list[1].to_owned() {
-                                Type::Symbol(name) => name,
-                                _ => {
-                                    return Err(Exception::type_error(
-                                        "First defmacro! argument must be a symbol",
-                                    ))
-                                }
-                            };
-
-                            let value = match eval(list[2].to_owned(), env)? {
-                                Type::Closure {
-                                    env, params, body, ..
-                                } => Type::Closure {
-                                    env,
-                                    params,
-                                    body,
-                                    is_macro: true,
-                                },
-                                _ => {
-                                    return Err(Exception::type_error(
-                                        "Type error defmacro! must be called with a function",
-                                    ))
-                                }
-                            };
-
-                            env.set(&name, value.clone());
-                            Ok(value)
-                        }
+/// Evaluate the forms that are never in tail position. It's kept out
+/// of eval_node so the recursive frames stay small.
+#[inline(never)]
+fn eval_form(node: &Node, env: &Rc<Env>) -> Ret {
+    match *node {
+        Node::Vector(ref elems) => {
+            let vector = Type::Vector(eval_nodes(elems, env)?);
+            sandbox::check_size(&vector)?;
+            Ok(vector)
+        }
 
-                        Type::Symbol(symbol) if symbol == "macroexpand" => {
-                            nargs_check("macroexpand", 1, list.len() - 1)?;
+        Node::HashMap(ref entries) => {
+            let mut evaluated = HashMap::with_capacity(entries.len());
+            for (key, value) in entries {
+                evaluated.insert(key.clone(), Box::new(eval_node(value, env)?));
+            }
+            let hash_map = Type::HashMap(evaluated);
+            sandbox::check_size(&hash_map)?;
+            Ok(hash_map)
+        }
 
-                            Ok(macroexpand(list[1].to_owned(), env)?)
-                        }
+        Node::Def {
+            name,
+            dynamic,
+            ref value,
+        } => {
+            let value = eval_node(value, env)?;
+            value.name_closure(name);
+            env.set(name, value.clone());
+            if env.is_namespace() {
+                ns::set_dynamic(env, name, dynamic);
+                trace::set_var(name, &value);
+            }
+            Ok(value)
+        }
 
-                        Type::Symbol(symbol) if symbol == "try*" => {
-                            // (try* A (catch* B C))
-                            nargs_check("try*", 1, list.len() - 1)
-                                .or_else(|_| error::nargs_check("try*", 2, list.len() - 1))?;
-
-                            // try*
-                            let try_body = list[1].to_owned();
-                            let try_result = eval(try_body, env);
-
-                            if try_result.is_ok() || list.len() == 2 {
-                                return try_result;
-                            }
-
-                            // catch*
-                            let exception = match try_result.unwrap_err() {
-                                Exception::Builtin(s) => Type::String(s),
-                                Exception::Custom(t) => t,
-                            };
-
-                            let catch = match list[2].to_owned() {
-                                Type::List(seq) | Type::Vector(seq) => seq,
-                                _ => {
-                                    return Err(Exception::type_error(
-                                        "catch* block must be a list",
-                                    ))
-                                }
-                            };
-                            nargs_check("catch*", 2, catch.len() - 1)?;
-
-                            let catch_var = match &catch[1] {
-                                Type::Symbol(s) => s,
-                                _ => {
-                                    return Err(Exception::type_error(
-                                        "First catch* argument must be a symbol",
-                                    ))
-                                }
-                            };
-                            let catch_body = catch[2].to_owned();
-
-                            let exc_env = Env::new(Some(env.clone()), &[catch_var], &[exception]);
-
-                            Ok(eval(catch_body, &Rc::new(exc_env))?)
-                        }
+        Node::Declare(ref names) => {
+            for name in names {
+                if env.get_local(*name).is_none() {
+                    env.set(*name, Type::Nil);
+                }
+            }
+            Ok(Type::Nil)
+        }
+
+        Node::DefMacro { name, ref value } => {
+            let value = match eval_node(value, env)? {
+                Type::Closure { env, lambda, .. } => Type::Closure {
+                    env,
+                    lambda,
+                    is_macro: true,
+                },
+                _ => {
+                    return Err(Exception::type_error(
+                        "Type error defmacro! must be called with a function",
+                    ))
+                }
+            };
+            value.name_closure(name);
+            env.set(name, value.clone());
+            Ok(value)
+        }
+
+        Node::Macroexpand(ref form) => macroexpand(form.clone(), env),
+
+        Node::Macroexpand1(ref form) => macroexpand_1(form.clone(), env),
+
+        Node::MacroexpandAll(ref form) => macroexpand_all(form.clone(), env),
+
+        Node::Ns { name, ref clauses } => {
+            let ns_env = ns::switch(env, &name.name())?;
 
+            for clause in clauses {
+                match clause {
+                    Type::List(clause) if !clause.is_empty() => match clause[0] {
+                        Type::Keyword(ref k) if k == "require" => {
+                            ns::require(&ns_env, &clause[1..])?
+                        }
                         _ => {
-                            // eval list and call first item as a
-                            // function and the rest as its arguments
-                            let list = eval_ast(ast.clone(), env)?;
-                            let list = match list {
-                                Type::List(list) => list,
-                                _ => return Err(Exception::type_error("Type can't not be a List")),
-                            };
-
-                            let (f, args) = list.split_first().unwrap();
-                            let f = f.to_owned();
-                            let args = args.iter().map(|arg| arg.to_owned()).collect();
-
-                            match f {
-                                Type::Fun(fun) => fun(args),
-
-                                Type::Closure {
-                                    ref params,
-                                    ref body,
-                                    ref env,
-                                    ..
-                                } => {
-                                    let params = match **params {
-                                        Type::List(ref l) | Type::Vector(ref l) => {
-                                            let param_list: Vec<&str> = l
-                                                .iter()
-                                                .map(|elem| match *elem {
-                                                    Type::Symbol(ref sym) => sym.as_str(),
-                                                    _ => "",
-                                                })
-                                                .filter(|elem| !elem.is_empty())
-                                                .collect();
-                                            param_list
-                                        }
-                                        _ => {
-                                            return Err(Exception::interpreter_error(
-                                                "Interpreter error: malformed closure!",
-                                            ))
-                                        }
-                                    };
-
-                                    let fun_env =
-                                        Env::new(Some(env.clone()), &params, args.as_slice());
-
-                                    tco_values = Some(TcoVals {
-                                        ast: Some(*body.to_owned()),
-                                        env: Some(Rc::new(fun_env)),
-                                    });
-                                    continue 'tco;
-                                }
-                                _ => {
-                                    Err(Exception::type_error("first argument must be a function"))
-                                }
-                            }
+                            return Err(Exception::builtin(
+                                "unknown ns clause (only :require is supported)",
+                            ))
                         }
-                    }
+                    },
+                    _ => return Err(Exception::type_error("ns clauses must be non empty lists")),
                 }
             }
-            _ => eval_ast(ast, env),
-        };
 
-        // end Tail call optimization
-        break;
-    }
-    result
-}
+            Ok(Type::Nil)
+        }
 
-fn eval_ast(ast: Type, env: &Rc<Env>) -> Ret {
-    match ast {
-        Type::Symbol(sym) => match env.get(sym.as_str()) {
-            Ok(value) => Ok(value),
-            Err(e) => Err(e),
-        },
+        Node::LoadFile(ref path) => {
+            let path = eval_node(path, env)?.convert_to_string()?;
+            module::load_file(env, Path::new(&path))
+        }
+
+        Node::SaveImage(ref path) => {
+            let path = eval_node(path, env)?.convert_to_string()?;
+            image::save(env, Path::new(&path))?;
+            Ok(Type::Nil)
+        }
+
+        Node::Require(ref specs) => {
+            // (require 'name '[other :as o :refer [a b]])
+            let specs = eval_nodes(specs, env)?;
+            ns::require(env, &specs)?;
+            Ok(Type::Nil)
+        }
 
-        Type::List(list) => {
-            let mut evaluated = Vec::with_capacity(list.len());
-            for elem in list {
-                let elem = eval(elem, env)?;
-                evaluated.push(elem);
+        Node::Binding { ref vars, ref body } => {
+            let bindings = binding_vars(vars, env)?;
+
+            let mut saved = Vec::with_capacity(bindings.len());
+            for (ns_env, name, value) in bindings {
+                let old = ns_env.get_local(name).unwrap_or(Type::Nil);
+                trace::set_var(name, &value);
+                ns_env.set(name, value);
+                saved.push((ns_env, name, old));
             }
-            Ok(Type::List(evaluated))
+
+            let result = eval_node(body, env);
+
+            // restore the vars even if the body failed
+            for (ns_env, name, old) in saved.into_iter().rev() {
+                trace::set_var(name, &old);
+                ns_env.set(name, old);
+            }
+            result
+        }
+
+        Node::Try {
+            ref body,
+            ref catches,
+            finally: Some(ref finally),
+        } => {
+            let result = match eval_try(body, catches, env) {
+                Ok(Tried::Value(value)) => Ok(value),
+                Ok(Tried::Caught(handler, exc_env)) => eval_node(&handler, &exc_env),
+                Err(exc) => Err(exc),
+            };
+            eval_node(finally, env)?;
+            result
         }
 
-        Type::Vector(vector) => {
-            let mut evaluated = Vec::with_capacity(vector.len());
-            for elem in vector {
-                let elem = eval(elem, env)?;
-                evaluated.push(elem);
+        // generators are run by the VM, the tree-walking interpreter
+        // only evaluates yield in the functions they call indirectly
+        Node::Yield(_) => Err(vm::yield_outside()),
+
+        Node::Reset { .. } => vm::reset(node, env),
+
+        // the continuations are captured by the VM running the body of
+        // the reset
+        Node::Shift { .. } => Err(vm::shift_outside()),
+
+        Node::Break => {
+            debug::break_at(env);
+            Ok(Type::Nil)
+        }
+
+        Node::Trace(ref names) => {
+            for name in traced_names(names, env, "trace")? {
+                trace::trace(env, name)?;
             }
-            Ok(Type::Vector(evaluated))
+            Ok(Type::Nil)
         }
 
-        Type::HashMap(hash_map) => {
-            let mut evaluated = HashMap::with_capacity(hash_map.len());
-            for (key, value) in hash_map {
-                let k = key.clone();
-                let v = Box::new(eval(*value.to_owned(), env)?);
-                evaluated.insert(k, v);
+        Node::Untrace(ref names) => {
+            if names.is_empty() {
+                trace::untrace(env, None)?;
             }
-            Ok(Type::HashMap(evaluated))
+            for name in traced_names(names, env, "untrace")? {
+                trace::untrace(env, Some(name))?;
+            }
+            Ok(Type::Nil)
         }
 
-        _ => Ok(ast),
+        Node::Error(ref exc) => Err(exc.clone()),
+        _ => unreachable!("tail forms are evaluated by eval_node"),
+    }
+}
+
+/// Evaluate the arguments of `trace` or `untrace` to symbols
+fn traced_names(names: &[Rc<Node>], env: &Rc<Env>, form: &str) -> Result<Vec<Symbol>, Exception> {
+    eval_nodes(names, env)?
+        .into_iter()
+        .map(|name| match name {
+            Type::Symbol(name) => Ok(name),
+            _ => Err(Exception::type_error(&format!(
+                "'{}' arguments must be symbols",
+                form
+            ))),
+        })
+        .collect()
+}
+
+/// Evaluate ast with the backend selected with `set_backend`
+fn eval_with_backend(ast: Type, env: &Rc<Env>) -> Ret {
+    ns::enter(env);
+    match vm::backend() {
+        Backend::TreeWalker => eval(ast, env),
+        Backend::Vm => vm::run(ast, env),
+    }
+}
+
+/// Evaluate a sequence of nodes in order
+fn eval_nodes(nodes: &[Rc<Node>], env: &Rc<Env>) -> Result<Vec<Type>, Exception> {
+    let mut evaluated = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        evaluated.push(eval_node(node, env)?);
+    }
+    Ok(evaluated)
+}
+
+/// Return the name defined by the first argument of `def!` and
+/// whether it's dynamic. The name can have metadata:
+/// `^:dynamic name` or `^{:dynamic true} name`.
+fn def_name(name: &Type) -> Result<(Symbol, bool), Exception> {
+    match name {
+        Type::Symbol(name) => Ok((*name, false)),
+        Type::List(list) if list.len() == 3 && list[0] == Type::Symbol(symbol::WITH_META) => {
+            let (name, _) = def_name(&list[1])?;
+            let dynamic = match list[2] {
+                Type::Keyword(ref keyword) => keyword == "dynamic",
+                Type::HashMap(ref meta) => match meta.get(":dynamic") {
+                    Some(value) => !matches!(**value, Type::Nil | Type::Bool(false)),
+                    None => false,
+                },
+                _ => false,
+            };
+            Ok((name, dynamic))
+        }
+        _ => Err(Exception::type_error(
+            "first def! argument must be a symbol",
+        )),
     }
 }
 
-fn quasiquote_seq(seq: Vec<Type>) -> Ret {
-    let mut result = Type::List(vec![]);
-    for elt in seq.iter().rev() {
-        if elt.is_list() {
-            let list = elt.convert_to_vec()?;
-            if list.len() >= 2 && list[0] == Type::Symbol("splice-unquote".to_string()) {
-                result = Type::List(vec![
-                    Type::Symbol("concat".to_string()),
-                    list[1].to_owned(),
-                    result,
-                ]);
-                continue;
+/// Evaluate the bindings of a `binding` form. Returns the namespace
+/// environment of each var, its name and its new value.
+fn binding_vars(
+    vars: &[(Symbol, Rc<Node>)],
+    env: &Rc<Env>,
+) -> Result<Vec<(Rc<Env>, Symbol, Type)>, Exception> {
+    let mut bindings = Vec::with_capacity(vars.len());
+    for (symbol, value) in vars {
+        let (ns_env, name) = match ns::locate(env, *symbol) {
+            Some((scope, name)) if scope.is_namespace() => (scope, name),
+            Some(_) => {
+                return Err(Exception::builtin(&format!(
+                    "can't dynamically bind local '{}'",
+                    symbol
+                )))
             }
+            None => {
+                return Err(Exception::builtin(&format!(
+                    "symbol '{}' not found",
+                    symbol
+                )))
+            }
+        };
+        if !ns::is_dynamic(&ns_env, name) {
+            return Err(Exception::builtin(&format!(
+                "can't dynamically bind non-dynamic var '{}'",
+                symbol
+            )));
         }
-        result = Type::List(vec![
-            Type::Symbol("cons".to_string()),
-            quasiquote(elt.to_owned())?,
-            result,
-        ])
+        let value = eval_node(value, env)?;
+        bindings.push((ns_env, name, value));
     }
-    Ok(result)
+    Ok(bindings)
 }
 
-fn quasiquote(ast: Type) -> Ret {
-    match ast {
-        Type::List(list) => {
-            if list.len() >= 2 && list[0] == Type::Symbol("unquote".to_string()) {
-                Ok(list[1].to_owned())
-            } else {
-                quasiquote_seq(list)
+/// Expand a quasiquoted template
+pub(crate) fn quasiquote(ast: Type) -> Ret {
+    Template::new(None).form(ast, 1)
+}
+
+/// Expand a syntax-quoted template, a quasiquoted one whose free
+/// symbols are qualified with the namespace of env
+pub(crate) fn syntax_quote(ast: Type, env: &Rc<Env>) -> Ret {
+    let mut ns_env = env;
+    while let (false, Some(outer)) = (ns_env.is_namespace(), ns_env.outer()) {
+        ns_env = outer;
+    }
+    Template::new(Some(ns_env)).form(ast, 1)
+}
+
+/// Quasiquoted template being expanded. The `name#` symbols are
+/// replaced by the same fresh symbol everywhere in the template.
+struct Template<'a> {
+    /// Namespace the symbols are qualified with, when syntax-quoted
+    ns_env: Option<&'a Rc<Env>>,
+    gensyms: HashMap<Symbol, Symbol>,
+}
+
+impl<'a> Template<'a> {
+    fn new(ns_env: Option<&'a Rc<Env>>) -> Template<'a> {
+        Template {
+            ns_env,
+            gensyms: HashMap::new(),
+        }
+    }
+
+    /// Expand a form of the template nested in depth quasiquotes. Only
+    /// the forms unquoted as many times are evaluated.
+    fn form(&mut self, ast: Type, depth: usize) -> Ret {
+        match ast {
+            Type::List(list) => match list.first() {
+                Some(&Type::Symbol(symbol::UNQUOTE)) if list.len() >= 2 && depth == 1 => {
+                    Ok(list[1].to_owned())
+                }
+                Some(&Type::Symbol(head @ (symbol::UNQUOTE | symbol::SPLICE_UNQUOTE)))
+                    if list.len() >= 2 && depth > 1 =>
+                {
+                    self.nested(head, list[1].to_owned(), depth - 1)
+                }
+                Some(&Type::Symbol(head @ (symbol::QUASIQUOTE | symbol::SYNTAX_QUOTE)))
+                    if list.len() >= 2 =>
+                {
+                    self.nested(head, list[1].to_owned(), depth + 1)
+                }
+                _ => self.seq(list, depth),
+            },
+            Type::Vector(vector) => Ok(Type::List(vec![
+                Type::Symbol(symbol::VEC),
+                self.seq(vector, depth)?,
+            ])),
+            Type::Symbol(symbol) if depth == 1 => Ok(Type::List(vec![
+                Type::Symbol(symbol::QUOTE),
+                Type::Symbol(self.symbol(symbol)),
+            ])),
+            Type::HashMap(_) | Type::Symbol(_) => {
+                Ok(Type::List(vec![Type::Symbol(symbol::QUOTE), ast]))
             }
+            _ => Ok(ast),
         }
-        Type::Vector(vector) => Ok(Type::List(vec![
-            Type::Symbol("vec".to_string()),
-            quasiquote_seq(vector)?,
-        ])),
-        Type::HashMap(_) | Type::Symbol(_) => {
-            Ok(Type::List(vec![Type::Symbol("quote".to_string()), ast]))
+    }
+
+    fn seq(&mut self, seq: Vec<Type>, depth: usize) -> Ret {
+        let mut result = Type::List(vec![]);
+        for elt in seq.into_iter().rev() {
+            if let Type::List(ref list) = elt {
+                if depth == 1 && list.len() >= 2 && list[0] == Type::Symbol(symbol::SPLICE_UNQUOTE)
+                {
+                    result = Type::List(vec![
+                        Type::Symbol(symbol::CONCAT),
+                        list[1].to_owned(),
+                        result,
+                    ]);
+                    continue;
+                }
+            }
+            result = Type::List(vec![
+                Type::Symbol(symbol::CONS),
+                self.form(elt, depth)?,
+                result,
+            ])
+        }
+        Ok(result)
+    }
+
+    /// Expand a `(head form)` list nested in the template, form being
+    /// nested in depth quasiquotes
+    fn nested(&mut self, head: Symbol, form: Type, depth: usize) -> Ret {
+        let form = Type::List(vec![
+            Type::Symbol(symbol::CONS),
+            self.form(form, depth)?,
+            Type::List(vec![]),
+        ]);
+        Ok(Type::List(vec![
+            Type::Symbol(symbol::CONS),
+            Type::List(vec![Type::Symbol(symbol::QUOTE), Type::Symbol(head)]),
+            form,
+        ]))
+    }
+
+    /// Return the symbol a symbol of the template stands for
+    fn symbol(&mut self, symbol: Symbol) -> Symbol {
+        let name = symbol.name();
+        if name.len() > 1 && name.ends_with('#') {
+            let prefix = format!("{}__", &name[..name.len() - 1]);
+            return *self
+                .gensyms
+                .entry(symbol)
+                .or_insert_with(|| Symbol::generate(&prefix, "__auto__"));
+        }
+
+        match self.ns_env {
+            Some(ns_env) if !analyzer::is_special(symbol) => ns::qualify(ns_env, symbol)
+                .unwrap_or_else(|| {
+                    if name.contains('/') {
+                        symbol
+                    } else {
+                        Symbol::new(&format!("{}/{}", ns::name_of(ns_env), name))
+                    }
+                }),
+            _ => symbol,
         }
-        _ => Ok(ast),
     }
 }
 
@@ -439,8 +708,8 @@ fn is_macro_call(ast: &Type, env: &Rc<Env>) -> bool {
     let mut is_macro_call = false;
 
     if let Type::List(list) = ast {
-        if let Some(Type::Symbol(sym)) = list.get(0) {
-            is_macro_call = matches!(env.get(sym), Ok(Type::Closure { is_macro: true, .. }));
+        if let Some(Type::Symbol(sym)) = list.first() {
+            is_macro_call = matches!(env.get(*sym), Ok(Type::Closure { is_macro: true, .. }));
         }
     }
 
@@ -450,34 +719,129 @@ fn is_macro_call(ast: &Type, env: &Rc<Env>) -> bool {
 fn macroexpand(ast: Type, env: &Rc<Env>) -> Ret {
     let mut ast = ast;
     while is_macro_call(&ast, env) {
-        let list = ast.convert_to_vec()?;
-        let sym = match list[0] {
-            Type::Symbol(ref sym) => sym,
-            _ => return Err(Exception::interpreter_error("impossible situation")),
-        };
-        let macro_fun = env.get(sym)?;
-        let args = if list.len() > 1 {
-            list[1..].to_vec()
-        } else {
-            vec![]
-        };
-        ast = macro_fun.apply(args)?;
+        ast = expand_once(ast, env)?;
     }
 
     Ok(ast)
 }
 
+/// Expand a macro call once, other forms are returned as is
+fn macroexpand_1(ast: Type, env: &Rc<Env>) -> Ret {
+    if is_macro_call(&ast, env) {
+        expand_once(ast, env)
+    } else {
+        Ok(ast)
+    }
+}
+
+/// Expand a form and the forms nested in it, except the quoted ones
+fn macroexpand_all(ast: Type, env: &Rc<Env>) -> Ret {
+    match macroexpand(ast, env)? {
+        Type::List(list) => match list.first() {
+            Some(Type::Symbol(symbol::QUOTE | symbol::QUASIQUOTE)) => Ok(Type::List(list)),
+            // the parameters aren't forms
+            Some(Type::Symbol(symbol::FN)) if list.len() == 3 => {
+                let body = macroexpand_all(list[2].clone(), env)?;
+                Ok(Type::List(vec![list[0].clone(), list[1].clone(), body]))
+            }
+            _ => list
+                .into_iter()
+                .map(|form| macroexpand_all(form, env))
+                .collect::<Result<_, _>>()
+                .map(Type::List),
+        },
+        Type::Vector(vector) => vector
+            .into_iter()
+            .map(|form| macroexpand_all(form, env))
+            .collect::<Result<_, _>>()
+            .map(Type::Vector),
+        Type::HashMap(map) => {
+            let mut expanded = HashMap::with_capacity(map.len());
+            for (key, value) in map {
+                expanded.insert(key, Box::new(macroexpand_all(*value, env)?));
+            }
+            Ok(Type::HashMap(expanded))
+        }
+        ast => Ok(ast),
+    }
+}
+
+fn expand_once(ast: Type, env: &Rc<Env>) -> Ret {
+    let list = ast.convert_to_vec()?;
+    let sym = match list[0] {
+        Type::Symbol(sym) => sym,
+        _ => return Err(Exception::interpreter_error("impossible situation")),
+    };
+    let macro_fun = env.get(sym)?;
+    let args = if list.len() > 1 {
+        list[1..].to_vec()
+    } else {
+        vec![]
+    };
+    macro_fun.apply(args)
+}
+
 fn print(ast: Result<Type, Exception>) -> String {
     match ast {
         Ok(ast) => printer::pr_str(ast, true),
-        Err(exc) => format!("Error: {}", exc.to_string()),
+        Err(exc) => match exc.stack {
+            Some(ref stack) if stack::stack_traces() && !stack.is_empty() => {
+                format!("Error: {}\n{}", exc, stack::format_stack(stack))
+            }
+            _ => format!("Error: {}", exc),
+        },
     }
 }
 
+/// Run the tasks of the event loop of the thread until none remains,
+/// waiting for the timers. Return the printed exception raised by a
+/// task, the tasks left are run by the next call.
+pub fn run_event_loop() -> Option<String> {
+    event::run().err().map(|exc| print(Err(stack::traced(exc))))
+}
+
+/// Evaluate the definitions of core.mal, the part of the core
+/// written in mal itself. They go to the core namespace, so every
+/// namespace sees them.
+pub fn load_core(env: &Rc<Env>) {
+    let registry = env.outermost().registry();
+    let current = registry.as_ref().map(|registry| registry.current());
+    if let Some(ref registry) = registry {
+        registry.set_current(ns::CORE_NS);
+    }
+    for line in include_str!("core.mal").lines() {
+        rep(line, env);
+    }
+    if let (Some(registry), Some(current)) = (registry, current) {
+        registry.set_current(&current);
+    }
+}
+
+/// Save the state of the interpreter to an image file, so it can be
+/// restored with `load_image` without evaluating any source again
+pub fn save_image(path: &str, env: &Rc<Env>) -> Result<(), String> {
+    image::save(env, Path::new(path)).map_err(|exc| exc.to_string())
+}
+
+/// Restore an interpreter saved with `save_image` or `save-image` and
+/// return its root environment. Images saved by other versions of the
+/// interpreter are rejected.
+pub fn load_image(path: &str) -> Result<Rc<Env>, String> {
+    image::load(Path::new(path)).map_err(|exc| exc.to_string())
+}
+
+/// Load a mal file and return the printed result. Modules required
+/// by it are searched relative to its directory.
+pub fn run_file(path: &str, env: &Rc<Env>) -> String {
+    interrupt::clear();
+    print(module::load_file(env, Path::new(path)))
+}
+
 pub fn rep(input: &str, env: &Rc<Env>) -> String {
+    interrupt::clear();
     let parsed_input = read(input);
     match parsed_input {
-        Ok(Some(ast)) => print(eval(ast, env)),
+        Ok(Some(ast)) => print(eval_with_backend(ast, env)),
         Ok(None) => String::new(),
         Err(exc) => exc,
     }
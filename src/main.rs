@@ -1,57 +1,128 @@
+use std::cell::RefCell;
 use std::error::Error;
-use std::fs::File;
-use std::io::Read;
 use std::rc::Rc;
+use std::thread;
 
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
 use mal_rust::env::Env;
+use mal_rust::{Backend, Debugger};
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let env = Rc::new(Env::new_default());
+    let mut argv: Vec<String> = std::env::args().skip(1).collect();
 
-    // Definitions using the interpreter itself
-    let mut file = File::open("src/core.mal")?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
+    let mut backend = Backend::TreeWalker;
+    let mut image = None;
+    let mut max_depth = mal_rust::DEFAULT_MAX_DEPTH;
+    let mut debug_on_exception = false;
+    loop {
+        match argv.first().map(|arg| arg.as_str()) {
+            Some("--vm") => {
+                // compile to bytecode instead of walking the forms
+                backend = Backend::Vm;
+                argv.remove(0);
+            }
+            Some("--image") if argv.len() >= 2 => {
+                image = Some(argv.drain(..2).nth(1).unwrap());
+            }
+            Some("--max-depth") if argv.len() >= 2 => {
+                max_depth = argv.drain(..2).nth(1).unwrap().parse()?;
+            }
+            Some("--debug-on-exception") => {
+                debug_on_exception = true;
+                argv.remove(0);
+            }
+            _ => break,
+        }
+    }
 
-    mal_rust::rep("(def! *host-language* \"jotare-rust\")", &env);
+    // Ctrl-C interrupts the evaluation instead of killing the process
+    ctrlc::set_handler(mal_rust::interrupt)?;
 
-    for line in contents.lines() {
-        mal_rust::rep(line, &env);
+    // the interpreter recurses on the native stack, run it in a thread
+    // with a stack big enough for the maximum depth
+    let stack_size = mal_rust::stack_size_for(max_depth);
+    let interpreter = thread::Builder::new()
+        .stack_size(stack_size)
+        .spawn(move || {
+            mal_rust::set_backend(backend);
+            mal_rust::set_max_depth(max_depth);
+            mal_rust::set_stack_traces(true);
+            mal_rust::set_debug_on_exception(debug_on_exception);
+            run(argv, image)
+        });
+    let interpreter = match interpreter {
+        Ok(interpreter) => interpreter,
+        Err(err) => {
+            eprintln!(
+                "Error: can't start the interpreter with a {} MiB stack for --max-depth {}: {}",
+                stack_size / (1024 * 1024),
+                max_depth,
+                err
+            );
+            std::process::exit(1);
+        }
+    };
+    if interpreter.join().is_err() {
+        std::process::exit(1);
     }
 
-    let argv: Vec<String> = std::env::args().skip(1).collect();
+    Ok(())
+}
+
+fn run(argv: Vec<String>, image: Option<String>) {
+    let env = if let Some(image) = image {
+        // restore a saved interpreter instead of loading the sources
+        match mal_rust::load_image(&image) {
+            Ok(env) => env,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        let env = Rc::new(Env::new_default());
+
+        mal_rust::rep("(def! *host-language* \"jotare-rust\")", &env);
+
+        // Definitions using the interpreter itself
+        mal_rust::load_core(&env);
+
+        env
+    };
+
+    // the debugger reads its commands with the same line editor
+    let rl = Rc::new(RefCell::new(Editor::<()>::new()));
+    mal_rust::set_debugger(Some(Box::new(Console(rl.clone()))));
 
     if argv.is_empty() {
         // interactive interpreter
         let prompt = "mal-rust> ";
         let history = ".history";
 
-        let mut rl = Editor::<()>::new();
-        if rl.load_history(&history).is_err() {
+        if rl.borrow_mut().load_history(&history).is_err() {
             println!("Creating history at '{}'", history);
         }
 
         mal_rust::rep(r#"(println (str "Mal [" *host-language* "]"))"#, &env);
 
         loop {
-            let input = rl.readline(prompt);
+            let input = rl.borrow_mut().readline(prompt);
             match input {
                 Ok(input) => {
                     if input.trim().is_empty() {
                         continue;
                     }
-                    rl.add_history_entry(input.as_str());
+                    rl.borrow_mut().add_history_entry(input.as_str());
                     let output = mal_rust::rep(&input, &env);
                     if !output.is_empty() {
                         println!("{}", output);
                     }
                 }
-                Err(ReadlineError::Interrupted | ReadlineError::Eof) => {
-                    break;
-                }
+                // Ctrl-C clears the line
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
                 Err(err) => {
                     println!("Failed to read: {:?}", err);
                     break;
@@ -59,7 +130,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             }
         }
 
-        rl.save_history(&history).unwrap();
+        rl.borrow_mut().save_history(&history).unwrap();
     } else {
         // run file
         let script = &argv[0];
@@ -74,11 +145,34 @@ fn main() -> Result<(), Box<dyn Error>> {
         );
         mal_rust::rep(&def_argv, &env);
 
-        let output = mal_rust::rep(&format!("(load-file \"{}\")", script), &env);
+        let output = mal_rust::run_file(script, &env);
         if !output.is_empty() && output != "nil" {
             println!("{}", output);
         }
+
+        // the script ends when the timers and promises it left are done
+        while let Some(error) = mal_rust::run_event_loop() {
+            println!("{}", error);
+        }
+    }
+}
+
+/// Debugger frontend reading commands with the line editor of the REPL
+struct Console(Rc<RefCell<Editor<()>>>);
+
+impl Debugger for Console {
+    fn read_line(&mut self, prompt: &str) -> Option<String> {
+        let mut rl = self.0.borrow_mut();
+        match rl.readline(prompt) {
+            Ok(line) => {
+                rl.add_history_entry(line.as_str());
+                Some(line)
+            }
+            Err(_) => None,
+        }
     }
 
-    Ok(())
+    fn print(&mut self, line: &str) {
+        println!("{}", line);
+    }
 }
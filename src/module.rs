@@ -0,0 +1,199 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::error::{Exception, Payload};
+use crate::eval_with_backend;
+use crate::ns::Registry;
+use crate::reader::read_str;
+use crate::sandbox;
+use crate::types::{Ret, Type};
+
+/// Environment variable with the module search path
+pub const MAL_PATH: &str = "MAL_PATH";
+
+/// Convert a module name to the relative path of its source file,
+/// `foo.bar-baz` is found at `foo/bar-baz.mal`
+fn module_file(name: &str) -> PathBuf {
+    let mut path: PathBuf = name.split('.').collect();
+    path.set_extension("mal");
+    path
+}
+
+/// Directory of the file being loaded, if any
+fn loading_dir(registry: &Registry) -> Option<PathBuf> {
+    registry
+        .loading
+        .borrow()
+        .last()
+        .and_then(|(_, file)| file.parent().map(|dir| dir.to_path_buf()))
+}
+
+/// Directories where a module is searched. First the directory of
+/// the requiring file (or the working directory at top level) and,
+/// when the requiring file is a module itself, the root directory it
+/// was found in. Then every directory in MAL_PATH.
+fn search_path(registry: &Registry) -> Vec<PathBuf> {
+    let mut dirs = vec![loading_dir(registry).unwrap_or_else(|| PathBuf::from("."))];
+
+    if let Some((name, file)) = registry.loading.borrow().last() {
+        if file.ends_with(module_file(name)) {
+            if let Some(root) = file.ancestors().nth(name.split('.').count()) {
+                if !dirs.iter().any(|dir| dir == root) {
+                    dirs.push(root.to_path_buf());
+                }
+            }
+        }
+    }
+
+    if let Some(mal_path) = std::env::var_os(MAL_PATH) {
+        dirs.extend(std::env::split_paths(&mal_path));
+    }
+
+    dirs
+}
+
+fn find_module(registry: &Registry, name: &str) -> Result<PathBuf, Exception> {
+    let file = module_file(name);
+    let dirs = search_path(registry);
+
+    for dir in dirs.iter() {
+        let candidate = dir.join(&file);
+        if candidate.is_file() {
+            return Ok(candidate);
+        }
+    }
+
+    Err(Exception::builtin(&format!(
+        "module '{}' not found in: {}",
+        name,
+        dirs.iter()
+            .map(|dir| dir.display().to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    )))
+}
+
+fn require_chain(registry: &Registry) -> Vec<String> {
+    registry
+        .loading
+        .borrow()
+        .iter()
+        .map(|(name, _)| name.to_owned())
+        .collect()
+}
+
+/// Returns true while the module called name is being loaded
+pub fn is_loading(env: &Rc<Env>, name: &str) -> bool {
+    match env.outermost().registry() {
+        Some(registry) => registry.loading.borrow().iter().any(|(m, _)| m == name),
+        None => false,
+    }
+}
+
+/// Load a module (once) by its name. Circular requires are detected
+/// and errors raised while finding or loading the module are reported
+/// with the chain of requires leading to them.
+pub fn require(env: &Rc<Env>, name: &str) -> Result<(), Exception> {
+    let root = env.outermost();
+    let registry = match root.registry() {
+        Some(registry) => registry,
+        None => {
+            return Err(Exception::builtin(
+                "modules are not supported in this environment",
+            ))
+        }
+    };
+
+    if registry.modules.borrow().contains_key(name) {
+        return Ok(());
+    }
+    sandbox::check_io("read files")?;
+
+    if registry.loading.borrow().iter().any(|(m, _)| m == name) {
+        let mut chain = require_chain(&registry);
+        chain.push(name.to_string());
+        return Err(with_chain(
+            Exception::builtin(&format!("circular require of '{}'", name)),
+            &chain,
+        ));
+    }
+
+    let mut chain = require_chain(&registry);
+    chain.push(name.to_string());
+    let path = find_module(&registry, name).map_err(|exc| with_chain(exc, &chain))?;
+
+    load(&root, &registry, name, &path).map_err(|exc| with_chain(exc, &chain))?;
+    registry.modules.borrow_mut().insert(name.to_string(), path);
+
+    Ok(())
+}
+
+/// Load and evaluate a mal file. Relative paths are looked up first
+/// in the directory of the file being loaded, if any. Requires inside
+/// it are resolved relative to its directory.
+pub fn load_file(env: &Rc<Env>, path: &Path) -> Ret {
+    sandbox::check_io("read files")?;
+    let root = env.outermost();
+    match root.registry() {
+        Some(registry) => {
+            let path = match loading_dir(&registry) {
+                Some(dir) if path.is_relative() && dir.join(path).is_file() => dir.join(path),
+                _ => path.to_path_buf(),
+            };
+            load(&root, &registry, &path.display().to_string(), &path)
+        }
+        None => eval_file(&root, path),
+    }
+}
+
+fn load(root: &Rc<Env>, registry: &Registry, name: &str, path: &Path) -> Ret {
+    let previous_ns = registry.current();
+    registry
+        .loading
+        .borrow_mut()
+        .push((name.to_string(), path.to_path_buf()));
+
+    let result = eval_file(root, path);
+
+    registry.loading.borrow_mut().pop();
+    registry.set_current(&previous_ns);
+
+    result
+}
+
+fn eval_file(env: &Rc<Env>, path: &Path) -> Ret {
+    let mut contents = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .map_err(|exc| {
+            Exception::io_error(&format!(
+                "IOError trying to read \"{}\": {}",
+                path.display(),
+                exc
+            ))
+        })?;
+
+    match read_str(&format!("(do {}\nnil)", contents)) {
+        Ok(Some(ast)) => eval_with_backend(ast, env),
+        Ok(None) => Ok(Type::Nil),
+        Err(exc) => Err(Exception::reader_error(&format!(
+            "{} in {}",
+            exc,
+            path.display()
+        ))),
+    }
+}
+
+/// Add the require chain to builtin exceptions. Only the innermost
+/// require adds it, so it's not repeated while unwinding.
+fn with_chain(mut exc: Exception, chain: &[String]) -> Exception {
+    if let Payload::Builtin(_, ref mut msg) = exc.payload {
+        if !msg.contains("(require chain: ") {
+            *msg = format!("{} (require chain: {})", msg, chain.join(" -> "));
+        }
+    }
+    exc
+}
@@ -0,0 +1,383 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::rc::{Rc, Weak};
+
+use crate::env::Env;
+use crate::error::Exception;
+use crate::module;
+use crate::symbol::Symbol;
+use crate::types::Type;
+
+/// Name of the namespace where top level code is evaluated first
+pub const DEFAULT_NS: &str = "user";
+
+/// Name of the namespace held by the root environment, with the
+/// builtins and the definitions of *core.mal*
+pub const CORE_NS: &str = "mal.core";
+
+thread_local! {
+    /// Root environment of the last evaluation started in this thread,
+    /// where the namespace functions look symbols up
+    static ROOT: RefCell<Weak<Env>> = const { RefCell::new(Weak::new()) };
+}
+
+/// Namespaces known by an interpreter. It lives in the root
+/// environment, which is itself the core namespace and the outer
+/// environment of every other namespace.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Registry {
+    pub(crate) namespaces: RefCell<HashMap<String, Rc<Env>>>,
+    current: RefCell<String>,
+    /// Modules already loaded and the file they were loaded from
+    pub(crate) modules: RefCell<HashMap<String, PathBuf>>,
+    /// Chain of modules being loaded, the last one is the innermost
+    pub(crate) loading: RefCell<Vec<(String, PathBuf)>>,
+}
+
+/// Information attached to the environment of a namespace
+#[derive(Debug, PartialEq, Clone)]
+pub struct NsInfo {
+    pub name: String,
+    pub(crate) aliases: RefCell<HashMap<String, String>>,
+    pub(crate) refers: RefCell<HashMap<Symbol, String>>,
+    /// Vars defined as `^:dynamic`
+    pub(crate) dynamic: RefCell<HashSet<Symbol>>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry {
+            namespaces: RefCell::new(HashMap::new()),
+            current: RefCell::new(DEFAULT_NS.to_string()),
+            modules: RefCell::new(HashMap::new()),
+            loading: RefCell::new(vec![]),
+        }
+    }
+
+    pub fn current(&self) -> String {
+        self.current.borrow().clone()
+    }
+
+    /// Make name the current namespace without creating it
+    pub(crate) fn set_current(&self, name: &str) {
+        *self.current.borrow_mut() = name.to_string();
+    }
+
+    fn get(&self, name: &str) -> Option<Rc<Env>> {
+        self.namespaces.borrow().get(name).cloned()
+    }
+
+    /// Return the names of the namespaces beneath the root one
+    fn names(&self) -> Vec<String> {
+        self.namespaces.borrow().keys().cloned().collect()
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry::new()
+    }
+}
+
+impl NsInfo {
+    pub fn new(name: &str) -> NsInfo {
+        NsInfo {
+            name: name.to_string(),
+            aliases: RefCell::new(HashMap::new()),
+            refers: RefCell::new(HashMap::new()),
+            dynamic: RefCell::new(HashSet::new()),
+        }
+    }
+}
+
+/// Return the environment of the namespace called name, if it exists
+pub fn find(env: &Rc<Env>, name: &str) -> Option<Rc<Env>> {
+    let root = env.outermost();
+    let registry = root.registry()?;
+    match name {
+        CORE_NS => Some(root.clone()),
+        // the default namespace is created when it's first needed
+        DEFAULT_NS => Some(
+            registry
+                .get(name)
+                .unwrap_or_else(|| create(&root, &registry, name)),
+        ),
+        _ => registry.get(name),
+    }
+}
+
+/// Create the environment of the namespace called name beneath root
+fn create(root: &Rc<Env>, registry: &Registry, name: &str) -> Rc<Env> {
+    let ns_env = Rc::new(Env::new_namespace(root.clone(), name));
+    registry
+        .namespaces
+        .borrow_mut()
+        .insert(name.to_string(), ns_env.clone());
+    ns_env
+}
+
+/// Return the environment of the current namespace
+pub fn current(env: &Rc<Env>) -> Rc<Env> {
+    let root = env.outermost();
+    match root.registry() {
+        Some(registry) => find(&root, &registry.current()).unwrap_or(root),
+        None => root,
+    }
+}
+
+/// Make the namespace functions of this thread look symbols up in the
+/// interpreter env belongs to
+pub fn enter(env: &Rc<Env>) {
+    ROOT.with(|root| *root.borrow_mut() = Rc::downgrade(&env.outermost()));
+}
+
+/// Return the environment of the current namespace of the interpreter
+/// evaluating code in this thread
+pub fn evaluated() -> Result<Rc<Env>, Exception> {
+    match ROOT.with(|root| root.borrow().upgrade()) {
+        Some(root) => Ok(current(&root)),
+        None => Err(Exception::builtin("no interpreter is evaluating code")),
+    }
+}
+
+/// Return the environment of a namespace given its name as a symbol
+/// or a string
+pub fn find_named(env: &Rc<Env>, name: &Type) -> Result<Rc<Env>, Exception> {
+    let name = match name {
+        Type::Symbol(symbol) => symbol.name().to_string(),
+        Type::String(s) => s.to_owned(),
+        _ => {
+            return Err(Exception::type_error(
+                "namespace name must be a symbol or a string",
+            ))
+        }
+    };
+    find(env, &name).ok_or_else(|| Exception::builtin(&format!("namespace '{}' not found", name)))
+}
+
+/// Return the name of the namespace where env has been defined
+pub fn name_of(env: &Env) -> String {
+    match env.ns_info() {
+        Some(info) => info.name.clone(),
+        None => DEFAULT_NS.to_string(),
+    }
+}
+
+/// Make name the current namespace, creating it if needed
+pub fn switch(env: &Rc<Env>, name: &str) -> Result<Rc<Env>, Exception> {
+    let root = env.outermost();
+    let registry = match root.registry() {
+        Some(registry) => registry,
+        None => {
+            return Err(Exception::builtin(
+                "namespaces are not supported in this environment",
+            ))
+        }
+    };
+
+    let ns_env = match find(&root, name) {
+        Some(ns_env) => ns_env,
+        None => create(&root, &registry, name),
+    };
+    registry.set_current(name);
+
+    Ok(ns_env)
+}
+
+/// Return every namespace with its environment, sorted by name
+pub fn all(env: &Rc<Env>) -> Vec<(String, Rc<Env>)> {
+    let root = env.outermost();
+    let registry = match root.registry() {
+        Some(registry) => registry,
+        None => return vec![],
+    };
+
+    find(&root, DEFAULT_NS);
+    let mut names = registry.names();
+    names.push(CORE_NS.to_string());
+    names.sort();
+    names
+        .into_iter()
+        .filter_map(|name| find(&root, &name).map(|ns_env| (name, ns_env)))
+        .collect()
+}
+
+/// Return the namespace name and the symbol a namespace qualified
+/// symbol (`ns/name` or `alias/name`) or a referred symbol stands for
+fn target(env: &Rc<Env>, symbol: Symbol) -> Option<(String, Symbol)> {
+    let info = env.ns_info()?;
+
+    match symbol.name().split_once('/') {
+        Some((ns_name, name)) if !ns_name.is_empty() && !name.is_empty() => {
+            let ns_name = match info.aliases.borrow().get(ns_name) {
+                Some(aliased) => aliased.clone(),
+                None => ns_name.to_string(),
+            };
+            Some((ns_name, Symbol::new(name)))
+        }
+        _ => Some((info.refers.borrow().get(&symbol)?.clone(), symbol)),
+    }
+}
+
+/// Resolve a symbol that hasn't been found in the environment
+/// chain. It can be a namespace qualified symbol (`ns/name` or
+/// `alias/name`) or a symbol referred from other namespace.
+pub fn resolve(env: &Rc<Env>, symbol: Symbol) -> Option<Type> {
+    let (ns_name, name) = target(env, symbol)?;
+    find(env, &ns_name)?.get_local(name)
+}
+
+/// Return the symbol qualified with the namespace where it's bound
+/// when evaluated in env. Local variables are returned as they are.
+///
+/// Returns None if the symbol isn't bound
+pub fn qualify(env: &Rc<Env>, symbol: Symbol) -> Option<Symbol> {
+    let (scope, name) = locate(env, symbol)?;
+    match scope.namespace_name() {
+        Some(ns_name) => Some(Symbol::new(&format!("{}/{}", ns_name, name))),
+        None => Some(symbol),
+    }
+}
+
+/// Return the environment where the var named by symbol is defined
+/// when evaluated in env, and its unqualified name. Local variables
+/// are found too, their environment isn't a namespace one.
+pub fn locate(env: &Rc<Env>, symbol: Symbol) -> Option<(Rc<Env>, Symbol)> {
+    let mut scope = Some(env);
+    while let Some(current) = scope {
+        if current.get_local(symbol).is_some() {
+            return Some((current.clone(), symbol));
+        }
+        scope = current.outer();
+    }
+
+    let (ns_name, name) = target(env, symbol)?;
+    let ns_env = find(env, &ns_name)?;
+    ns_env.get_local(name)?;
+    Some((ns_env, name))
+}
+
+/// Mark or unmark a var of a namespace as dynamic
+pub fn set_dynamic(ns_env: &Env, symbol: Symbol, dynamic: bool) {
+    if let Some(info) = ns_env.ns_info() {
+        if dynamic {
+            info.dynamic.borrow_mut().insert(symbol);
+        } else {
+            info.dynamic.borrow_mut().remove(&symbol);
+        }
+    }
+}
+
+/// Returns true if the var can be rebound with `binding`: it has
+/// been defined as `^:dynamic` or its name has earmuffs (`*name*`)
+pub fn is_dynamic(ns_env: &Env, symbol: Symbol) -> bool {
+    let name = symbol.name();
+    if name.len() > 2 && name.starts_with('*') && name.ends_with('*') {
+        return true;
+    }
+    match ns_env.ns_info() {
+        Some(info) => info.dynamic.borrow().contains(&symbol),
+        None => false,
+    }
+}
+
+/// Remove a symbol from a namespace, either its definition or the
+/// symbol referred with that name. Returns true if it was mapped.
+pub fn unmap(ns_env: &Env, symbol: Symbol) -> bool {
+    let referred = match ns_env.ns_info() {
+        Some(info) => {
+            info.dynamic.borrow_mut().remove(&symbol);
+            info.refers.borrow_mut().remove(&symbol).is_some()
+        }
+        None => false,
+    };
+    ns_env.unset(symbol).is_some() || referred
+}
+
+/// Apply the specs of a `(:require ...)` clause or a `require` call
+/// to the current namespace. Each spec is a namespace name or a vector like
+/// `[name :as alias :refer [a b]]`. Namespaces not defined yet are
+/// loaded as modules.
+pub fn require(env: &Rc<Env>, specs: &[Type]) -> Result<(), Exception> {
+    let ns_env = &current(env);
+    let info = match ns_env.ns_info() {
+        Some(info) => info,
+        None => return Err(Exception::interpreter_error("require outside a namespace")),
+    };
+
+    for spec in specs {
+        let (name, options) = match spec {
+            Type::Symbol(name) => (name.name().to_string(), vec![]),
+            Type::List(seq) | Type::Vector(seq) => match seq.split_first() {
+                Some((Type::Symbol(name), options)) => (name.name().to_string(), options.to_vec()),
+                _ => {
+                    return Err(Exception::type_error(
+                        "require spec must start with a namespace name",
+                    ))
+                }
+            },
+            _ => {
+                return Err(Exception::type_error(
+                    "require spec must be a symbol or a vector",
+                ))
+            }
+        };
+
+        if find(ns_env, &name).is_none() || module::is_loading(ns_env, &name) {
+            module::require(ns_env, &name)?;
+        }
+        let required = match find(ns_env, &name) {
+            Some(required) => required,
+            None => {
+                return Err(Exception::builtin(&format!(
+                    "namespace '{}' not found",
+                    name
+                )))
+            }
+        };
+
+        if !options.len().is_multiple_of(2) {
+            return Err(Exception::builtin(
+                "require options must be composed of pairs",
+            ));
+        }
+        for option in options.chunks(2) {
+            match (&option[0], &option[1]) {
+                (Type::Keyword(k), Type::Symbol(alias)) if k == "as" => {
+                    info.aliases
+                        .borrow_mut()
+                        .insert(alias.name().to_string(), name.clone());
+                }
+                (Type::Keyword(k), Type::Keyword(all)) if k == "refer" && all == "all" => {
+                    for symbol in required.local_symbols() {
+                        info.refers.borrow_mut().insert(symbol, name.clone());
+                    }
+                }
+                (Type::Keyword(k), Type::List(symbols) | Type::Vector(symbols)) if k == "refer" => {
+                    for symbol in symbols {
+                        match symbol {
+                            Type::Symbol(symbol) => {
+                                if required.get_local(*symbol).is_none() {
+                                    return Err(Exception::builtin(&format!(
+                                        "symbol '{}' not found in namespace '{}'",
+                                        symbol, name
+                                    )));
+                                }
+                                info.refers.borrow_mut().insert(*symbol, name.clone());
+                            }
+                            _ => return Err(Exception::type_error(":refer only accepts symbols")),
+                        }
+                    }
+                }
+                _ => {
+                    return Err(Exception::builtin(
+                        "unknown require option (only :as and :refer are supported)",
+                    ))
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
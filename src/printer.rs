@@ -6,73 +6,111 @@ pub fn pr_str(ast: Type, print_readably: bool) -> String {
     pr_type(&ast, print_readably)
 }
 
-/// Recursively format a Type and returns it's string representation
+/// Output of the printer still to be written
+enum Item<'a> {
+    Value(&'a Type, bool),
+    Text(&'a str),
+}
+
+/// Format a Type and returns it's string representation. Collections
+/// are printed with an explicit stack instead of recursion, so they
+/// can be nested arbitrarily deep.
 fn pr_type(t: &Type, print_readably: bool) -> String {
     let mut s = String::new();
-    match t {
-        Type::Nil => s.push_str("nil"),
-        Type::Bool(b) => {
-            if *b {
-                s.push_str("true")
-            } else {
-                s.push_str("false")
+    let mut pending = vec![Item::Value(t, print_readably)];
+
+    while let Some(item) = pending.pop() {
+        let (t, print_readably) = match item {
+            Item::Text(text) => {
+                s.push_str(text);
+                continue;
             }
-        }
-        Type::Int(integer) => s.push_str(&format!("{}", integer)),
-        Type::Float(float) => {
-            if float - float.floor() > 0.0 {
-                s.push_str(&format!("{}", float))
-            } else {
-                s.push_str(&format!("{}.0", float))
+            Item::Value(t, print_readably) => (t, print_readably),
+        };
+
+        match t {
+            Type::Nil => s.push_str("nil"),
+            Type::Bool(b) => {
+                if *b {
+                    s.push_str("true")
+                } else {
+                    s.push_str("false")
+                }
             }
-        }
-        Type::Symbol(symbol) => s.push_str(symbol),
-        Type::Keyword(keyword) => s.push_str(&format!(":{}", keyword)),
-        Type::String(string) => {
-            if print_readably {
-                s.push_str(&format!("\"{}\"", escape_string(string)));
-            } else {
-                s.push_str(string);
+            Type::Int(integer) => s.push_str(&format!("{}", integer)),
+            Type::Float(float) => {
+                if float - float.floor() > 0.0 {
+                    s.push_str(&format!("{}", float))
+                } else {
+                    s.push_str(&format!("{}.0", float))
+                }
             }
-        }
-        Type::Atom(atom) => {
-            s.push_str(&format!(
-                "(atom {})",
-                pr_type(&atom.borrow(), print_readably)
-            ));
-        }
-        Type::List(list) => {
-            s.push_str(&format!("({})", pr_seq(list, print_readably).as_str()));
-        }
-        Type::Vector(vector) => {
-            s.push_str(&format!("[{}]", pr_seq(vector, print_readably).as_str()));
-        }
-        Type::HashMap(hashmap) => {
-            s.push('{');
-            s.push_str(
-                &hashmap
-                    .iter()
-                    .map(|(k, v)| format!("{} {}", k, pr_type(&*v, print_readably)))
-                    .collect::<Vec<String>>()
-                    .join(" "),
-            );
-            s.push('}');
-        }
-        Type::Fun(_) => s.push_str("#<function>"),
-        Type::Closure { params, body, .. } => {
-            s.push_str(&format!(
-                "(fn* {} {})",
-                pr_type(params, true),
-                pr_type(body, true)
-            ));
-        }
-    };
+            Type::Symbol(symbol) => s.push_str(&symbol.name()),
+            Type::Keyword(keyword) => s.push_str(&format!(":{}", keyword)),
+            Type::String(string) => {
+                if print_readably {
+                    s.push_str(&format!("\"{}\"", escape_string(string)));
+                } else {
+                    s.push_str(string);
+                }
+            }
+            Type::Atom(atom) => {
+                // atoms can't be borrowed past this iteration, their
+                // content is printed recursively
+                s.push_str(&format!(
+                    "(atom {})",
+                    pr_type(&atom.borrow(), print_readably)
+                ));
+            }
+            Type::List(list) => push_seq(&mut s, &mut pending, list, print_readably, "(", ")"),
+            Type::Vector(vector) => {
+                push_seq(&mut s, &mut pending, vector, print_readably, "[", "]")
+            }
+            Type::HashMap(hashmap) => {
+                s.push('{');
+                pending.push(Item::Text("}"));
+                let entries: Vec<(&String, &Box<Type>)> = hashmap.iter().collect();
+                for (i, (k, v)) in entries.into_iter().enumerate().rev() {
+                    pending.push(Item::Value(v, print_readably));
+                    pending.push(Item::Text(" "));
+                    pending.push(Item::Text(k));
+                    if i > 0 {
+                        pending.push(Item::Text(" "));
+                    }
+                }
+            }
+            Type::Fun(..) => s.push_str("#<function>"),
+            Type::Generator(_) => s.push_str("#<generator>"),
+            Type::Continuation(_) => s.push_str("#<continuation>"),
+            Type::Promise(_) => s.push_str("#<promise>"),
+            Type::Closure { lambda, .. } => {
+                s.push_str("(fn* ");
+                pending.push(Item::Text(")"));
+                pending.push(Item::Value(&lambda.body, true));
+                pending.push(Item::Text(" "));
+                pending.push(Item::Value(&lambda.params, true));
+            }
+        };
+    }
     s
 }
 
-fn pr_seq(seq: &[Type], print_readably: bool) -> String {
-    seq.iter()
-        .map(|element| pr_type(&*element, print_readably))
-        .collect::<Vec<String>>()
-        .join(" ")
+/// Push the elements of a sequence to be printed between open and
+/// close
+fn push_seq<'a>(
+    s: &mut String,
+    pending: &mut Vec<Item<'a>>,
+    seq: &'a [Type],
+    print_readably: bool,
+    open: &str,
+    close: &'a str,
+) {
+    s.push_str(open);
+    pending.push(Item::Text(close));
+    for (i, element) in seq.iter().enumerate().rev() {
+        pending.push(Item::Value(element, print_readably));
+        if i > 0 {
+            pending.push(Item::Text(" "));
+        }
+    }
 }
@@ -3,12 +3,25 @@ use std::collections::HashMap;
 use lazy_static::lazy_static;
 use regex::Regex;
 
+use crate::symbol::{self, Symbol};
 use crate::types::Type;
 use crate::utils::balanced_string;
 use crate::utils::unescape_string;
 
 type Token = String;
 
+/// Form being read, waiting for the forms it contains
+enum Open {
+    /// List or vector, and the token closing it
+    Seq(&'static str, Vec<Type>),
+    /// Hash map and the key of the value being read
+    HashMap(HashMap<String, Box<Type>>, Option<String>),
+    /// Reader macro, like `'form`, expanded to `(symbol form)`
+    Prefix(Symbol),
+    /// Metadata form of `^meta form`, once it's read
+    Meta(Option<Type>),
+}
+
 struct Reader {
     tokens: Vec<Token>,
     position: usize,
@@ -40,13 +53,87 @@ impl Reader {
         }
     }
 
+    /// Read a form. Collections are read with an explicit stack of
+    /// the open ones instead of recursion, so they can be nested
+    /// arbitrarily deep.
     fn read_form(&mut self) -> Result<Type, String> {
+        let mut open: Vec<Open> = vec![];
+
+        loop {
+            let token = self.peek()?;
+
+            if let Some(Open::HashMap(_, key @ None)) = open.last_mut() {
+                if token != "}" {
+                    // keys are kept as they are written
+                    *key = Some(token.to_owned());
+                    self.next();
+                    continue;
+                }
+            }
+
+            let mut form = match token.as_str() {
+                "(" | "[" | "{" | "@" | "'" | "`" | "~" | "~@" | "^" => {
+                    open.push(match token.as_str() {
+                        "(" => Open::Seq(")", vec![]),
+                        "[" => Open::Seq("]", vec![]),
+                        "{" => Open::HashMap(HashMap::new(), None),
+                        "@" => Open::Prefix(symbol::DEREF),
+                        "'" => Open::Prefix(symbol::QUOTE),
+                        "`" => Open::Prefix(symbol::QUASIQUOTE),
+                        "~" => Open::Prefix(symbol::UNQUOTE),
+                        "~@" => Open::Prefix(symbol::SPLICE_UNQUOTE),
+                        _ => Open::Meta(None),
+                    });
+                    self.next();
+                    continue;
+                }
+                ")" | "]" if matches!(open.last(), Some(Open::Seq(end, _)) if end == token) => {
+                    match open.pop() {
+                        Some(Open::Seq(")", items)) => Type::List(items),
+                        Some(Open::Seq(_, items)) => Type::Vector(items),
+                        _ => unreachable!(),
+                    }
+                }
+                "}" if matches!(open.last(), Some(Open::HashMap(..))) => match open.pop() {
+                    Some(Open::HashMap(hash_map, _)) => Type::HashMap(hash_map),
+                    _ => unreachable!(),
+                },
+                _ => self.read_token()?,
+            };
+            self.next();
+
+            // add the form to the innermost open form, closing the
+            // ones it completes
+            loop {
+                match open.last_mut() {
+                    None => return Ok(form),
+                    Some(Open::Seq(_, items)) => items.push(form),
+                    Some(Open::HashMap(hash_map, key)) => {
+                        hash_map.insert(key.take().unwrap(), Box::new(form));
+                    }
+                    Some(Open::Meta(meta @ None)) => *meta = Some(form),
+                    Some(Open::Meta(Some(_))) => {
+                        if let Some(Open::Meta(Some(meta))) = open.pop() {
+                            form = Type::List(vec![Type::Symbol(symbol::WITH_META), form, meta]);
+                        }
+                        continue;
+                    }
+                    Some(Open::Prefix(prefix)) => {
+                        form = Type::List(vec![Type::Symbol(*prefix), form]);
+                        open.pop();
+                        continue;
+                    }
+                }
+                break;
+            }
+        }
+    }
+
+    /// Read a form made of a single token
+    fn read_token(&mut self) -> Result<Type, String> {
         let token = self.peek()?;
 
         match token.chars().next().unwrap() {
-            '(' => Ok(self.read_list()?),
-            '[' => Ok(self.read_vector()?),
-            '{' => Ok(self.read_hash_map()?),
             '"' => {
                 if token.len() >= 2 && token.ends_with('"') && balanced_string(token) {
                     Ok(self.read_string()?)
@@ -62,65 +149,6 @@ impl Reader {
         }
     }
 
-    fn read_list(&mut self) -> Result<Type, String> {
-        Ok(Type::List(self.read_seq(")")?))
-    }
-
-    fn read_vector(&mut self) -> Result<Type, String> {
-        Ok(Type::Vector(self.read_seq("]")?))
-    }
-
-    fn read_seq(&mut self, end: &str) -> Result<Vec<Type>, String> {
-        let mut items = Vec::new();
-
-        self.next(); // skip "(", "["
-
-        loop {
-            let item = self.peek()?;
-
-            if item == end {
-                break;
-            }
-
-            items.push(self.read_form()?);
-
-            if self.next().is_none() {
-                break;
-            }
-        }
-
-        Ok(items)
-    }
-
-    fn read_hash_map(&mut self) -> Result<Type, String> {
-        let mut hash_map = HashMap::new();
-
-        self.next(); // skip "{"
-
-        let mut key = None;
-        loop {
-            let item = self.peek()?;
-
-            if item == "}" {
-                break;
-            }
-
-            match key {
-                Some(k) => {
-                    hash_map.insert(k, Box::new(self.read_form()?));
-                    key = None
-                }
-                None => key = Some(item.to_owned()),
-            }
-
-            if self.next().is_none() {
-                break;
-            }
-        }
-
-        Ok(Type::HashMap(hash_map))
-    }
-
     fn read_keyword(&mut self) -> Result<Type, String> {
         let token = self.peek()?;
         Ok(Type::Keyword(token[1..].to_string()))
@@ -133,33 +161,6 @@ impl Reader {
             "nil" => Type::Nil,
             "true" => Type::Bool(true),
             "false" => Type::Bool(false),
-            "@" => {
-                self.next();
-                Type::List(vec![Type::Symbol("deref".to_string()), self.read_form()?])
-            }
-            "'" => {
-                self.next();
-                Type::List(vec![Type::Symbol("quote".to_string()), self.read_form()?])
-            }
-            "`" => {
-                self.next();
-                Type::List(vec![
-                    Type::Symbol("quasiquote".to_string()),
-                    self.read_form()?,
-                ])
-            }
-            "~" => {
-                self.next();
-                Type::List(vec![Type::Symbol("unquote".to_string()), self.read_form()?])
-            }
-            "~@" => {
-                self.next();
-                Type::List(vec![
-                    Type::Symbol("splice-unquote".to_string()),
-                    self.read_form()?,
-                ])
-            }
-
             other => {
                 if let Ok(number) = token.parse() {
                     if token.contains('.') {
@@ -168,7 +169,7 @@ impl Reader {
                         Type::Int(number as i64)
                     }
                 } else {
-                    Type::Symbol(other.to_owned())
+                    Type::Symbol(Symbol::new(other))
                 }
             }
         })
@@ -282,7 +283,7 @@ mod tests {
 
         assert_eq!(read_str("123"), Ok(Some(Type::Int(123))));
 
-        assert_eq!(read_str("abc"), Ok(Some(Type::Symbol(String::from("abc")))));
+        assert_eq!(read_str("abc"), Ok(Some(Type::Symbol(Symbol::new("abc")))));
 
         assert_eq!(
             read_str("\"hello\""),
@@ -302,16 +303,25 @@ mod tests {
         assert_eq!(
             read_str("( + 2 (* 3 4) )"),
             Ok(Some(Type::List(vec![
-                Type::Symbol(String::from("+")),
+                Type::Symbol(Symbol::new("+")),
                 Type::Int(2),
                 Type::List(vec![
-                    Type::Symbol(String::from("*")),
+                    Type::Symbol(Symbol::new("*")),
                     Type::Int(3),
                     Type::Int(4),
                 ]),
             ])))
         );
 
+        assert_eq!(
+            read_str("^:dynamic x"),
+            Ok(Some(Type::List(vec![
+                Type::Symbol(Symbol::new("with-meta")),
+                Type::Symbol(Symbol::new("x")),
+                Type::Keyword(String::from("dynamic")),
+            ])))
+        );
+
         assert_eq!(read_str(";; comments"), Ok(None));
     }
 }
@@ -0,0 +1,168 @@
+use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant};
+
+use crate::error::{Exception, Kind};
+use crate::stack;
+use crate::types::Type;
+
+/// Limits of the code evaluated in a thread, for running untrusted
+/// code. Exceeding one raises an exception of its own kind, which can
+/// be caught, but every later step fails again once the step or time
+/// quota is exceeded so the code can't go on.
+#[derive(Debug, Clone, Default)]
+pub struct Sandbox {
+    /// Maximum number of function calls, builtins and tail calls
+    /// included
+    pub max_steps: Option<u64>,
+    /// Maximum size of the values returned by builtin functions and
+    /// built by collection literals: their elements, nested ones
+    /// included, plus the bytes of their strings. It's checked for each
+    /// value on its own, so it doesn't bound the memory the evaluation
+    /// allocates in total.
+    pub max_size: Option<usize>,
+    /// Wall-clock time the evaluation can take
+    pub timeout: Option<Duration>,
+    /// Leave the functions doing IO out of new environments, make them
+    /// fail in the existing ones and forbid reading and writing files
+    pub deny_io: bool,
+}
+
+/// Builtin functions left out of the environments when IO is denied
+pub const IO_FUNCTIONS: &[&str] = &["slurp", "readline", "load-file"];
+
+/// Number of steps between two checks of the deadline
+const CLOCK_STEPS: u64 = 256;
+
+thread_local! {
+    static SANDBOX: RefCell<Option<Sandbox>> = const { RefCell::new(None) };
+    static STEPS: Cell<u64> = const { Cell::new(0) };
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+    /// The deadline passed, checked on every step
+    static EXPIRED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Evaluate the code of this thread in sandbox, or without limits. The
+/// steps and the time are counted from this call.
+pub fn set_sandbox(sandbox: Option<Sandbox>) {
+    let deadline = sandbox
+        .as_ref()
+        .and_then(|sandbox| sandbox.timeout)
+        .map(|timeout| Instant::now() + timeout);
+    DEADLINE.with(|current| current.set(deadline));
+    EXPIRED.with(|expired| expired.set(false));
+    STEPS.with(|steps| steps.set(0));
+    SANDBOX.with(|current| *current.borrow_mut() = sandbox);
+    stack::update_hooks();
+}
+
+/// Return true if the code is evaluated in a sandbox
+pub fn sandboxed() -> bool {
+    SANDBOX.with(|sandbox| sandbox.borrow().is_some())
+}
+
+/// Return true if the evaluated code can do IO
+pub fn io_allowed() -> bool {
+    SANDBOX.with(|sandbox| {
+        !sandbox
+            .borrow()
+            .as_ref()
+            .is_some_and(|sandbox| sandbox.deny_io)
+    })
+}
+
+/// Fail if the evaluated code can't do IO
+pub fn check_io(what: &str) -> Result<(), Exception> {
+    if io_allowed() {
+        Ok(())
+    } else {
+        Err(Exception::io_error(&format!(
+            "IO denied: can't {} in the sandbox",
+            what
+        )))
+    }
+}
+
+/// Count a step, failing if a step or time quota is exceeded
+pub fn step() -> Result<(), Exception> {
+    let max_steps = SANDBOX.with(|sandbox| sandbox.borrow().as_ref().and_then(|s| s.max_steps));
+    let steps = STEPS.with(|steps| {
+        steps.set(steps.get().saturating_add(1));
+        steps.get()
+    });
+    if max_steps.is_some_and(|max_steps| steps > max_steps) {
+        return Err(Exception::quota_error(
+            Kind::StepQuota,
+            "step quota exceeded",
+        ));
+    }
+    if steps.is_multiple_of(CLOCK_STEPS) {
+        check_deadline()
+    } else if EXPIRED.with(|expired| expired.get()) {
+        Err(expired())
+    } else {
+        Ok(())
+    }
+}
+
+/// Return the time the evaluation must end by, if any
+pub fn deadline() -> Option<Instant> {
+    DEADLINE.with(|deadline| deadline.get())
+}
+
+/// Fail if the time quota is exceeded
+pub fn check_deadline() -> Result<(), Exception> {
+    match DEADLINE.with(|deadline| deadline.get()) {
+        Some(deadline) if Instant::now() >= deadline => {
+            EXPIRED.with(|expired| expired.set(true));
+            Err(expired())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn expired() -> Exception {
+    Exception::quota_error(Kind::TimeQuota, "time quota exceeded")
+}
+
+/// Fail if value is bigger than the size quota
+pub fn check_size(value: &Type) -> Result<(), Exception> {
+    let max_size = match SANDBOX.with(|sandbox| sandbox.borrow().as_ref().and_then(|s| s.max_size))
+    {
+        Some(max_size) => max_size,
+        None => return Ok(()),
+    };
+    if size(value, max_size) > max_size {
+        return Err(Exception::quota_error(
+            Kind::SizeQuota,
+            "size quota exceeded",
+        ));
+    }
+    Ok(())
+}
+
+/// Return the number of elements of value, nested ones included, plus
+/// the bytes of its strings. Counting stops once it's over max_size.
+fn size(value: &Type, max_size: usize) -> usize {
+    let mut size = 0;
+    let mut pending = vec![value];
+    while let Some(value) = pending.pop() {
+        match value {
+            Type::List(elems) | Type::Vector(elems) => {
+                size += elems.len();
+                pending.extend(elems);
+            }
+            Type::HashMap(entries) => {
+                for (key, value) in entries {
+                    size += 1 + key.len();
+                    pending.push(value);
+                }
+            }
+            Type::String(string) | Type::Keyword(string) => size += string.len(),
+            _ => (),
+        }
+        if size > max_size {
+            break;
+        }
+    }
+    size
+}
@@ -0,0 +1,459 @@
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::rc::Rc;
+
+use crate::analyzer::Lambda;
+use crate::debug;
+use crate::env::Env;
+use crate::error::{field, Exception};
+use crate::interrupt;
+use crate::printer;
+use crate::sandbox;
+use crate::symbol::Symbol;
+use crate::trace;
+use crate::types::{Args, Function, Ret, Type};
+
+/// Default maximum evaluation depth
+pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+
+/// Largest native stack returned by `stack_size_for`, that a thread
+/// can be spawned with on 64-bit targets
+pub const MAX_STACK_SIZE: usize = 1024 * 1024 * 1024;
+
+/// Native stack left free for the frames of a level and for raising
+/// the exception, at most a quarter of the stack of the thread
+const STACK_RED_ZONE: usize = 64 * 1024;
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    static MAX_DEPTH: Cell<usize> = const { Cell::new(DEFAULT_MAX_DEPTH) };
+    /// Lowest native stack address a level can start at, 0 if the
+    /// stack of the thread is unknown. Computed on the first level.
+    static STACK_LIMIT: Cell<Option<usize>> = const { Cell::new(None) };
+    /// Shadow call stack, innermost last
+    static CALLS: RefCell<Vec<Call>> = const { RefCell::new(Vec::new()) };
+    static STACK_TRACES: Cell<bool> = const { Cell::new(false) };
+    /// Calls and returns are reported to the debugger, the tracer or
+    /// the sandbox, checked on every call and return
+    static HOOKED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Function call being evaluated
+#[derive(Debug, Clone)]
+pub enum Call {
+    Builtin(Symbol),
+    /// Closure call and the environment of its parameters
+    Closure(Rc<Lambda>, Rc<Env>),
+    /// Bytecode frame running top level code or a macro expansion,
+    /// it's left out of stack traces
+    Form,
+}
+
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Call::Builtin(name) => write!(f, "{}", name),
+            Call::Closure(lambda, _) => match lambda.name() {
+                Some(name) => write!(f, "{}", name),
+                None => write!(f, "fn* {}", printer::pr_str(lambda.params.clone(), true)),
+            },
+            Call::Form => Ok(()),
+        }
+    }
+}
+
+impl Call {
+    /// Show a closure call with its arguments, `(name arg...)`
+    pub fn form(&self) -> String {
+        let mut form = vec![self.to_string()];
+        if let Call::Closure(_, env) = self {
+            for (_, value) in env.slot_bindings() {
+                form.push(printer::pr_str(value, true));
+            }
+        }
+        format!("({})", form.join(" "))
+    }
+}
+
+/// Set the maximum evaluation depth in this thread. Evaluating deeper
+/// nested calls raises a "stack depth exceeded" exception.
+///
+/// The tree-walking interpreter recurses on the native stack. The
+/// exception is raised earlier if the stack of the thread runs out,
+/// so the thread needs a stack big enough for the limit to be reached,
+/// see `stack_size_for`.
+pub fn set_max_depth(max_depth: usize) {
+    MAX_DEPTH.with(|max| max.set(max_depth));
+}
+
+/// Return the maximum evaluation depth in this thread
+pub fn max_depth() -> usize {
+    MAX_DEPTH.with(|max| max.get())
+}
+
+/// Return the native stack size a thread needs to evaluate code up to
+/// max_depth levels deep. It's at most `MAX_STACK_SIZE`, deeper limits
+/// are only reached by the code that doesn't recurse on the native
+/// stack, like the VM.
+pub fn stack_size_for(max_depth: usize) -> usize {
+    // generous for unoptimized builds, only touched pages are used
+    const BYTES_PER_LEVEL: usize = 32 * 1024;
+    max_depth
+        .saturating_mul(BYTES_PER_LEVEL)
+        .clamp(64 * 1024 * 1024, MAX_STACK_SIZE)
+}
+
+fn depth() -> usize {
+    DEPTH.with(|depth| depth.get())
+}
+
+/// Return true if the native stack is too deep to enter another level
+fn native_stack_exhausted() -> bool {
+    let marker = 0u8;
+    let limit = STACK_LIMIT.with(|limit| match limit.get() {
+        Some(limit) => limit,
+        None => {
+            let computed = match native_stack() {
+                Some((low, size)) => low + (size / 4).min(STACK_RED_ZONE),
+                None => 0,
+            };
+            limit.set(Some(computed));
+            computed
+        }
+    });
+    // the stack grows down on every supported target
+    (std::ptr::addr_of!(marker) as usize) < limit
+}
+
+/// Return the lowest address and the size of the native stack of the
+/// thread
+#[cfg(target_os = "linux")]
+fn native_stack() -> Option<(usize, usize)> {
+    // SAFETY: the attributes are initialized by pthread_getattr_np
+    // before being read, and destroyed once
+    unsafe {
+        let mut attr: libc::pthread_attr_t = std::mem::zeroed();
+        if libc::pthread_getattr_np(libc::pthread_self(), &mut attr) != 0 {
+            return None;
+        }
+        let mut low = std::ptr::null_mut();
+        let mut size = 0;
+        let found = libc::pthread_attr_getstack(&attr, &mut low, &mut size) == 0;
+        libc::pthread_attr_destroy(&mut attr);
+        found.then_some((low as usize, size))
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn native_stack() -> Option<(usize, usize)> {
+    // SAFETY: both functions only read the attributes of the thread
+    unsafe {
+        let thread = libc::pthread_self();
+        let high = libc::pthread_get_stackaddr_np(thread) as usize;
+        let size = libc::pthread_get_stacksize_np(thread);
+        Some((high - size, size))
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn native_stack() -> Option<(usize, usize)> {
+    None
+}
+
+/// Return the number of calls being evaluated
+pub fn level() -> usize {
+    CALLS.with(|calls| calls.borrow().len())
+}
+
+/// Return the calls being evaluated, innermost last
+pub fn calls() -> Vec<Call> {
+    CALLS.with(|calls| calls.borrow().clone())
+}
+
+/// Run f with the calls being evaluated, innermost last
+pub fn with_calls<R>(f: impl FnOnce(&[Call]) -> R) -> R {
+    CALLS.with(|calls| f(&calls.borrow()))
+}
+
+/// Return the innermost call being evaluated
+pub fn innermost() -> Option<Call> {
+    CALLS.with(|calls| calls.borrow().last().cloned())
+}
+
+/// Return true if a call was made at the level saved in base
+pub fn made_call(base: &Depth) -> bool {
+    base.called.get()
+}
+
+fn exceeded() -> Exception {
+    Exception::stack_depth_error("stack depth exceeded")
+}
+
+/// Evaluation depth and call stack to restore when dropped
+pub struct Depth {
+    depth: usize,
+    calls: usize,
+    /// Set by the tail calls made at this level
+    called: Cell<bool>,
+}
+
+impl Drop for Depth {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(self.depth));
+        CALLS.with(|calls| calls.borrow_mut().truncate(self.calls));
+    }
+}
+
+/// Enter a nested evaluation. The depth is restored when the returned
+/// guard is dropped.
+pub fn enter() -> Result<Depth, Exception> {
+    let current = depth();
+    if current >= max_depth() || native_stack_exhausted() {
+        return Err(exceeded());
+    }
+    DEPTH.with(|depth| depth.set(current + 1));
+    Ok(Depth {
+        depth: current,
+        calls: level(),
+        called: Cell::new(false),
+    })
+}
+
+/// Return a guard restoring the current depth and call stack when
+/// dropped
+pub fn save() -> Depth {
+    Depth {
+        depth: depth(),
+        calls: level(),
+        called: Cell::new(false),
+    }
+}
+
+/// Set the depth to the one saved in base plus levels, and drop the
+/// calls above as many levels
+pub fn set(base: &Depth, levels: usize) {
+    DEPTH.with(|depth| depth.set(base.depth + levels));
+    CALLS.with(|calls| calls.borrow_mut().truncate(base.calls + levels));
+}
+
+/// Remove the calls made above the level saved in base, by a
+/// coroutine being suspended, and return them
+pub fn suspend(base: &Depth) -> Vec<Call> {
+    DEPTH.with(|depth| depth.set(base.depth));
+    CALLS.with(|calls| calls.borrow_mut().split_off(base.calls))
+}
+
+/// Return the calls made above the level saved in base, by a VM whose
+/// continuation is captured
+pub fn calls_since(base: &Depth) -> Vec<Call> {
+    CALLS.with(|calls| calls.borrow()[base.calls..].to_vec())
+}
+
+/// Push back the calls of a coroutine being resumed, or of a
+/// continuation, one level each
+pub fn resume(suspended: Vec<Call>) {
+    DEPTH.with(|depth| depth.set(depth.get() + suspended.len()));
+    CALLS.with(|calls| calls.borrow_mut().extend(suspended));
+}
+
+/// Fail if the depth exceeds the maximum or the native stack runs out
+pub fn check() -> Result<(), Exception> {
+    if depth() > max_depth() || native_stack_exhausted() {
+        return Err(exceeded());
+    }
+    Ok(())
+}
+
+/// Push a call on the call stack. Forms run by the VM aren't calls
+/// for the hooks.
+pub fn push(call: Call) -> Result<(), Exception> {
+    let is_form = matches!(call, Call::Form);
+    CALLS.with(|calls| calls.borrow_mut().push(call));
+    if is_form {
+        return Ok(());
+    }
+    called()
+}
+
+/// Replace the innermost call, for a call in tail position
+pub fn replace(call: Call) -> Result<(), Exception> {
+    CALLS.with(|calls| match calls.borrow_mut().last_mut() {
+        Some(last) => *last = call,
+        None => unreachable!("tail calls replace a running call"),
+    });
+    called()
+}
+
+/// Report calls and returns while the debugger is stepping, calls are
+/// traced or evaluated in a sandbox
+pub fn update_hooks() {
+    let hooked = debug::stepping() || trace::tracing() || sandbox::sandboxed();
+    HOOKED.with(|current| current.set(hooked));
+}
+
+/// Return true if calls and returns are reported
+pub fn hooked() -> bool {
+    HOOKED.with(|hooked| hooked.get())
+}
+
+/// Raise a pending interrupt, count the call in the sandbox, then let
+/// the tracer print and the debugger stop at it
+fn called() -> Result<(), Exception> {
+    interrupt::check()?;
+    if hooked() {
+        hook_call()?;
+    }
+    Ok(())
+}
+
+/// Raise a pending interrupt and count an iteration of `recur` in the
+/// sandbox, like a call
+pub fn iterate() -> Result<(), Exception> {
+    interrupt::check()?;
+    if hooked() {
+        sandbox::step()?;
+    }
+    Ok(())
+}
+
+/// Set the environment of the innermost call, a closure run again by
+/// `recur`, and return its lambda
+pub fn recur_call(env: &Rc<Env>) -> Rc<Lambda> {
+    CALLS.with(|calls| match calls.borrow_mut().last_mut() {
+        Some(Call::Closure(lambda, call_env)) => {
+            *call_env = env.clone();
+            lambda.clone()
+        }
+        _ => unreachable!("recur runs the closure being called"),
+    })
+}
+
+#[cold]
+fn hook_call() -> Result<(), Exception> {
+    sandbox::step()?;
+    trace::called();
+    debug::called();
+    Ok(())
+}
+
+/// Let the tracer print and the debugger stop at the return of the
+/// innermost call
+#[cold]
+pub fn returned(value: &Type) {
+    trace::returned(value);
+    debug::returned(value);
+}
+
+/// Make call the only call made at the level saved in base. The
+/// first call is pushed, later ones are tail calls replacing it.
+pub fn tail_call(base: &Depth, call: Call) -> Result<(), Exception> {
+    CALLS.with(|calls| {
+        let mut calls = calls.borrow_mut();
+        calls.truncate(base.calls);
+        calls.push(call);
+    });
+    base.called.set(true);
+    called()
+}
+
+/// Call a builtin function with its name on the call stack
+pub fn call_builtin(fun: Function, name: Symbol, args: Args) -> Ret {
+    interrupt::check()?;
+    let base = CALLS.with(|calls| {
+        let mut calls = calls.borrow_mut();
+        calls.push(Call::Builtin(name));
+        calls.len() - 1
+    });
+    let result = if hooked() {
+        hooked_builtin(fun, args)
+    } else {
+        fun(args)
+    };
+    let result = result.map_err(traced);
+    CALLS.with(|calls| calls.borrow_mut().truncate(base));
+    result
+}
+
+/// Call a builtin function, counting the call and checking the size of
+/// its result in the sandbox
+#[cold]
+fn hooked_builtin(fun: Function, args: Args) -> Ret {
+    if !sandbox::sandboxed() {
+        return fun(args);
+    }
+    sandbox::step()?;
+    let value = fun(args)?;
+    sandbox::check_size(&value)?;
+    Ok(value)
+}
+
+/// Attach the current call stack to an exception, unless it already
+/// has the stack of the place it was raised. Escapes to continuations
+/// aren't errors, they're left as they are.
+#[cold]
+pub fn traced(mut exc: Exception) -> Exception {
+    if exc.stack.is_none() && !exc.is_escape() {
+        let stack = CALLS.with(|calls| {
+            calls
+                .borrow()
+                .iter()
+                .filter(|call| !matches!(call, Call::Form))
+                .cloned()
+                .collect()
+        });
+        exc.stack = Some(stack);
+        debug::raised(&exc);
+    }
+    exc
+}
+
+/// Convert an exception caught by `catch*` to the value it binds.
+/// Exception maps, like the ones of builtin errors and `ex-info`, get
+/// the call stack it was raised from as their `:stack`, innermost call
+/// first, for `ex-stack`. Rethrown ones keep the stack they had.
+pub fn catch(exc: Exception) -> Type {
+    let exc = traced(exc);
+    let stack = exc.stack.clone();
+    match exc.into_value() {
+        Type::HashMap(mut map) if map.contains_key(&field("message")) => {
+            map.entry(field("stack")).or_insert_with(|| {
+                let calls = stack.iter().flat_map(|stack| stack.iter().rev());
+                Box::new(Type::List(
+                    calls.map(|call| Type::String(call.to_string())).collect(),
+                ))
+            });
+            Type::HashMap(map)
+        }
+        value => value,
+    }
+}
+
+/// Print the call stack of uncaught exceptions after their message in
+/// this thread
+pub fn set_stack_traces(enabled: bool) {
+    STACK_TRACES.with(|traces| traces.set(enabled));
+}
+
+/// Return whether uncaught exceptions are printed with their stack
+pub fn stack_traces() -> bool {
+    STACK_TRACES.with(|traces| traces.get())
+}
+
+/// Format a call stack, one `at` line per call from the innermost.
+/// Repeated calls, like the ones of a deep recursion, are collapsed.
+pub fn format_stack(stack: &[Call]) -> String {
+    let mut lines = vec![];
+    let mut calls = stack.iter().rev().map(|call| call.to_string()).peekable();
+    while let Some(call) = calls.next() {
+        let mut times = 1;
+        while calls.next_if_eq(&call).is_some() {
+            times += 1;
+        }
+        if times == 1 {
+            lines.push(format!("  at {}", call));
+        } else {
+            lines.push(format!("  at {} ({} times)", call, times));
+        }
+    }
+    lines.join("\n")
+}
@@ -0,0 +1,170 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// Interned symbol. Symbols with the same name share the same integer
+/// id, so comparing and hashing them doesn't touch the name.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+/// Names of the symbols of a thread. They are owned by the interner
+/// and freed with it when the thread exits.
+struct Interner {
+    names: Vec<Rc<str>>,
+    ids: HashMap<Rc<str>, Symbol>,
+    /// Number of the last generated symbol
+    generated: u64,
+}
+
+macro_rules! predefined_symbols {
+    ($($name:ident = $string:expr),* $(,)?) => {
+        const PREDEFINED: &[&str] = &[$($string),*];
+
+        predefined_symbols!(@consts 0u32, $($name),*);
+    };
+    (@consts $idx:expr, $name:ident $(, $rest:ident)*) => {
+        pub const $name: Symbol = Symbol($idx);
+        predefined_symbols!(@consts $idx + 1u32 $(, $rest)*);
+    };
+    (@consts $idx:expr) => {};
+}
+
+// Symbols known by the interpreter. They have the same id in every
+// interner, so they can be used as constants and in patterns.
+predefined_symbols! {
+    DEF = "def!",
+    LET = "let*",
+    LETFN = "letfn",
+    LOOP = "loop",
+    RECUR = "recur",
+    DO = "do",
+    IF = "if",
+    FN = "fn*",
+    EVAL = "eval",
+    QUOTE = "quote",
+    QUASIQUOTE = "quasiquote",
+    QUASIQUOTEEXPAND = "quasiquoteexpand",
+    SYNTAX_QUOTE = "syntax-quote",
+    UNQUOTE = "unquote",
+    SPLICE_UNQUOTE = "splice-unquote",
+    DEFMACRO = "defmacro!",
+    DECLARE = "declare",
+    MACROEXPAND = "macroexpand",
+    MACROEXPAND_1 = "macroexpand-1",
+    MACROEXPAND_ALL = "macroexpand-all",
+    NS = "ns",
+    LOAD_FILE = "load-file",
+    SAVE_IMAGE = "save-image",
+    REQUIRE = "require",
+    BINDING = "binding",
+    TRY = "try*",
+    CATCH = "catch*",
+    FINALLY = "finally",
+    AMPERSAND = "&",
+    CONS = "cons",
+    CONCAT = "concat",
+    VEC = "vec",
+    DEREF = "deref",
+    WITH_META = "with-meta",
+    APPLY = "apply",
+    CALL_CC = "call/cc",
+    YIELD = "yield",
+    RESET = "reset",
+    SHIFT = "shift",
+    BREAK = "break",
+    TRACE = "trace",
+    UNTRACE = "untrace",
+    TRACE_VAR = "*trace*",
+}
+
+impl Interner {
+    fn new() -> Interner {
+        let mut interner = Interner {
+            names: vec![],
+            ids: HashMap::new(),
+            generated: 0,
+        };
+        for name in PREDEFINED {
+            interner.intern(name);
+        }
+        interner
+    }
+
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(symbol) = self.ids.get(name) {
+            return *symbol;
+        }
+
+        let name: Rc<str> = Rc::from(name);
+        let symbol = Symbol(self.names.len() as u32);
+        self.names.push(name.clone());
+        self.ids.insert(name, symbol);
+        symbol
+    }
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::new());
+}
+
+impl Symbol {
+    /// Return the symbol called name, interning it if needed
+    pub fn new(name: &str) -> Symbol {
+        INTERNER.with(|interner| interner.borrow_mut().intern(name))
+    }
+
+    pub fn name(&self) -> Rc<str> {
+        INTERNER.with(|interner| interner.borrow().names[self.0 as usize].clone())
+    }
+
+    /// Return a fresh symbol, whose name is a number between prefix and
+    /// suffix and was never interned before
+    pub fn generate(prefix: &str, suffix: &str) -> Symbol {
+        INTERNER.with(|interner| {
+            let mut interner = interner.borrow_mut();
+            loop {
+                interner.generated += 1;
+                let name = format!("{}{}{}", prefix, interner.generated, suffix);
+                if !interner.ids.contains_key(name.as_str()) {
+                    return interner.intern(&name);
+                }
+            }
+        })
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Symbol({})", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interning() {
+        assert_eq!(Symbol::new("abc"), Symbol::new("abc"));
+        assert_ne!(Symbol::new("abc"), Symbol::new("abd"));
+        assert_eq!(&*Symbol::new("abc").name(), "abc");
+        assert_eq!(Symbol::new("def!"), DEF);
+        assert_eq!(&*DEREF.name(), "deref");
+    }
+
+    #[test]
+    fn test_generated_symbols_are_fresh() {
+        let taken = Symbol::new("G__1");
+        let generated = Symbol::generate("G__", "");
+        assert_ne!(generated, taken);
+        assert!(generated.name().starts_with("G__"));
+        assert_ne!(Symbol::generate("G__", ""), generated);
+    }
+}
@@ -0,0 +1,170 @@
+use std::cell::{Cell, RefCell};
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::analyzer::Lambda;
+use crate::env::Env;
+use crate::error::Exception;
+use crate::ns;
+use crate::printer;
+use crate::stack::{self, Call};
+use crate::symbol::{self, Symbol};
+use crate::types::Type;
+
+/// Binding replaced by a traced closure
+struct Traced {
+    scope: Rc<Env>,
+    name: Symbol,
+    original: Type,
+    lambda: Rc<Lambda>,
+}
+
+thread_local! {
+    /// `*trace*` is true, every closure call is traced
+    static TRACE_ALL: Cell<bool> = const { Cell::new(false) };
+    static TRACED: RefCell<Vec<Traced>> = const { RefCell::new(Vec::new()) };
+    static OUTPUT: RefCell<Option<Box<dyn Write>>> = RefCell::new(None);
+}
+
+/// Send the trace of this thread to output instead of stderr
+pub fn set_trace_output(output: Option<Box<dyn Write>>) {
+    OUTPUT.with(|current| *current.borrow_mut() = output);
+}
+
+/// Return true if some calls are traced
+pub fn tracing() -> bool {
+    TRACE_ALL.with(|all| all.get()) || TRACED.with(|traced| !traced.borrow().is_empty())
+}
+
+/// Follow the value of `*trace*` when a var named name is set
+pub fn set_var(name: Symbol, value: &Type) {
+    if name == symbol::TRACE_VAR {
+        let enabled = value.is_truthy();
+        TRACE_ALL.with(|all| all.set(enabled));
+        stack::update_hooks();
+    }
+}
+
+/// Replace the closure bound to name by a traced copy
+pub fn trace(env: &Rc<Env>, name: Symbol) -> Result<(), Exception> {
+    let (scope, name) = ns::locate(env, name).ok_or_else(|| Exception::symbol_not_found(name))?;
+    if position(&scope, name).is_some() {
+        return Ok(());
+    }
+    let (closure_env, lambda, is_macro) = match scope.get_local(name) {
+        Some(Type::Closure {
+            env,
+            lambda,
+            is_macro,
+        }) => (env, lambda, is_macro),
+        _ => return Err(Exception::type_error("only closures can be traced")),
+    };
+
+    let traced = Rc::new(lambda.traced_copy());
+    let original = Type::Closure {
+        env: closure_env.clone(),
+        lambda,
+        is_macro,
+    };
+    scope.set(
+        name,
+        Type::Closure {
+            env: closure_env,
+            lambda: traced.clone(),
+            is_macro,
+        },
+    );
+    TRACED.with(|all| {
+        all.borrow_mut().push(Traced {
+            scope,
+            name,
+            original,
+            lambda: traced,
+        })
+    });
+    stack::update_hooks();
+    Ok(())
+}
+
+/// Restore the closure bound to name before it was traced, or all the
+/// traced closures without a name
+pub fn untrace(env: &Rc<Env>, name: Option<Symbol>) -> Result<(), Exception> {
+    let removed = match name {
+        Some(name) => {
+            let (scope, name) =
+                ns::locate(env, name).ok_or_else(|| Exception::symbol_not_found(name))?;
+            match position(&scope, name) {
+                Some(index) => TRACED.with(|all| vec![all.borrow_mut().remove(index)]),
+                None => vec![],
+            }
+        }
+        None => TRACED.with(|all| all.borrow_mut().drain(..).collect()),
+    };
+    for traced in removed {
+        // a binding redefined since it was traced is kept
+        if let Some(Type::Closure { lambda, .. }) = traced.scope.get_local(traced.name) {
+            if Rc::ptr_eq(&lambda, &traced.lambda) {
+                traced.scope.set(traced.name, traced.original);
+            }
+        }
+    }
+    stack::update_hooks();
+    Ok(())
+}
+
+fn position(scope: &Rc<Env>, name: Symbol) -> Option<usize> {
+    TRACED.with(|all| {
+        all.borrow()
+            .iter()
+            .position(|traced| traced.name == name && Rc::ptr_eq(&traced.scope, scope))
+    })
+}
+
+fn is_traced(call: &Call) -> bool {
+    match call {
+        Call::Closure(lambda, _) => lambda.is_traced() || TRACE_ALL.with(|all| all.get()),
+        _ => false,
+    }
+}
+
+/// Return the innermost call if it's traced, and the number of traced
+/// calls it's nested in
+fn traced_call() -> Option<(Call, usize)> {
+    stack::with_calls(|calls| {
+        let (innermost, outer) = calls.split_last()?;
+        if !is_traced(innermost) {
+            return None;
+        }
+        let nesting = outer.iter().filter(|call| is_traced(call)).count();
+        Some((innermost.clone(), nesting))
+    })
+}
+
+/// Print the innermost call with its arguments, if it's traced
+#[cold]
+pub fn called() {
+    if let Some((call, nesting)) = traced_call() {
+        print(nesting, &call.form());
+    }
+}
+
+/// Print the value returned by the innermost call, if it's traced
+#[cold]
+pub fn returned(value: &Type) {
+    if let Some((_, nesting)) = traced_call() {
+        print(
+            nesting,
+            &format!("=> {}", printer::pr_str(value.clone(), true)),
+        );
+    }
+}
+
+fn print(nesting: usize, line: &str) {
+    let line = format!("{}{}\n", "  ".repeat(nesting), line);
+    OUTPUT.with(|output| {
+        let _ = match *output.borrow_mut() {
+            Some(ref mut output) => output.write_all(line.as_bytes()),
+            None => io::stderr().write_all(line.as_bytes()),
+        };
+    });
+}
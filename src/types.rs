@@ -2,9 +2,16 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
+use crate::analyzer::Lambda;
+use crate::apply_closure;
+use crate::continuation::Continuation;
 use crate::env::Env;
 use crate::error::Exception;
-use crate::eval;
+use crate::event::Promise;
+use crate::generator::Generator;
+use crate::stack;
+use crate::symbol::Symbol;
+use crate::vm::{self, Backend};
 
 #[derive(Clone, Debug)]
 pub enum Type {
@@ -12,20 +19,23 @@ pub enum Type {
     Bool(bool),
     Int(i64),
     Float(f64),
-    Symbol(String),
+    Symbol(Symbol),
     Keyword(String),
     String(String),
     Atom(Rc<RefCell<Type>>),
     List(Vec<Type>),
     Vector(Vec<Type>),
     HashMap(HashMap<String, Box<Type>>),
-    Fun(Function),
+    /// Builtin function and the name it is registered with
+    Fun(Function, Symbol),
     Closure {
         env: Rc<Env>,
-        params: Box<Type>,
-        body: Box<Type>,
+        lambda: Rc<Lambda>,
         is_macro: bool,
     },
+    Generator(Generator),
+    Continuation(Rc<Continuation>),
+    Promise(Promise),
 }
 
 pub type Args = Vec<Type>;
@@ -37,37 +47,21 @@ impl Type {
     /// Fun or Closure.
     pub fn apply(&self, args: Vec<Type>) -> Ret {
         match self {
-            Type::Fun(fun) => fun(args),
-
-            Type::Closure {
-                ref params,
-                ref body,
-                ref env,
-                ..
-            } => {
-                let params = match **params {
-                    Type::List(ref l) | Type::Vector(ref l) => {
-                        let param_list: Vec<&str> = l
-                            .iter()
-                            .map(|elem| match *elem {
-                                Type::Symbol(ref sym) => sym.as_str(),
-                                _ => "",
-                            })
-                            .filter(|elem| !elem.is_empty())
-                            .collect();
-                        param_list
-                    }
-                    _ => return Err(Exception::interpreter_error("malformed closure")),
-                };
-
-                let fun_env = Env::new(Some(env.clone()), &params, args.as_slice());
-
-                eval(*body.to_owned(), &Rc::new(fun_env))
-            }
+            Type::Fun(fun, name) => stack::call_builtin(*fun, *name, args),
+
+            Type::Closure { .. } if vm::backend() == Backend::Vm => vm::apply(self, args),
+
+            Type::Closure { env, lambda, .. } => apply_closure(lambda, env, &args),
+            Type::Continuation(continuation) => continuation.invoke(args),
             _ => Err(Exception::type_error("first argument must be a function")),
         }
     }
 
+    /// Return true unless the value is nil or false
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Type::Nil | Type::Bool(false))
+    }
+
     /// Convert a Type instance to it's f64 representation. Only calls
     /// with types Int or Float will be successful
     pub fn convert_to_f64(&self) -> Result<f64, Exception> {
@@ -97,7 +91,7 @@ impl Type {
 
     pub fn convert_to_map(&self) -> Result<HashMap<String, Box<Type>>, String> {
         match self {
-            Type::HashMap(hm)=> Ok(hm.clone()),
+            Type::HashMap(hm) => Ok(hm.clone()),
             _ => Err("Type error: type must be a hash map".to_string()),
         }
     }
@@ -139,40 +133,70 @@ impl Type {
     }
 
     pub fn is_callable(&self) -> bool {
-        matches!(self, Type::Fun(_)) || matches!(self, Type::Closure { is_macro: false, .. })
+        matches!(
+            self,
+            Type::Fun(..)
+                | Type::Closure {
+                    is_macro: false,
+                    ..
+                }
+                | Type::Continuation(_)
+        )
     }
 
     pub fn is_macro(&self) -> bool {
-        match self {
-            Type::Closure { is_macro: true, .. } => true,
-            _ => false,
+        matches!(self, Type::Closure { is_macro: true, .. })
+    }
+
+    /// Give a closure being defined its name, shown in stack traces
+    pub fn name_closure(&self, name: Symbol) {
+        if let Type::Closure { lambda, .. } = self {
+            lambda.set_name(name);
         }
     }
 }
 
 impl PartialEq for Type {
+    /// Compare two values. Collections are compared with an explicit
+    /// stack instead of recursion, so they can be nested arbitrarily
+    /// deep.
     fn eq(&self, other: &Self) -> bool {
         use super::Type::*;
 
-        match (self, other) {
-            (Nil, Nil) => true,
-            (Bool(a), Bool(b)) => a == b,
-            (Int(_), Int(_)) | (Int(_), Float(_)) | (Float(_), Int(_)) | (Float(_), Float(_)) => {
-                self.convert_to_f64().unwrap() == other.convert_to_f64().unwrap()
-            }
-            (Symbol(a), Symbol(b)) => a == b,
-            (Keyword(a), Keyword(b)) => a == b,
-            (String(a), String(b)) => a == b,
-            (List(_), List(_))
-            | (List(_), Vector(_))
-            | (Vector(_), List(_))
-            | (Vector(_), Vector(_)) => self.convert_to_vec() == other.convert_to_vec(),
-            (HashMap(a), HashMap(b)) => {
-                a.len() == b.len() && a.iter().all(|(k,v)| {
-                    b.contains_key(k) && *b[k] == **v
-                })
+        let mut pending = vec![(self, other)];
+        while let Some((a, b)) = pending.pop() {
+            let equal = match (a, b) {
+                (Nil, Nil) => true,
+                (Bool(a), Bool(b)) => a == b,
+                (Int(_) | Float(_), Int(_) | Float(_)) => {
+                    a.convert_to_f64().unwrap() == b.convert_to_f64().unwrap()
+                }
+                (Symbol(a), Symbol(b)) => a == b,
+                (Keyword(a), Keyword(b)) => a == b,
+                (String(a), String(b)) => a == b,
+                (Generator(a), Generator(b)) => a.same(b),
+                (Continuation(a), Continuation(b)) => Rc::ptr_eq(a, b),
+                (Promise(a), Promise(b)) => a.same(b),
+                (List(a) | Vector(a), List(b) | Vector(b)) => {
+                    pending.extend(a.iter().zip(b.iter()));
+                    a.len() == b.len()
+                }
+                (HashMap(a), HashMap(b)) => {
+                    a.len() == b.len()
+                        && a.iter().all(|(k, v)| match b.get(k) {
+                            Some(w) => {
+                                pending.push((v, w));
+                                true
+                            }
+                            None => false,
+                        })
+                }
+                _ => false,
+            };
+            if !equal {
+                return false;
             }
-            _ => false,
         }
+        true
     }
 }
@@ -54,7 +54,6 @@ pub fn balanced_string(s: &str) -> bool {
     t.len() >= 2 && t[0] == t[t.len() - 1] && t[0] == '"'
 }
 
-
 #[cfg(test)]
 mod tests {
     use super::*;
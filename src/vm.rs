@@ -0,0 +1,1173 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use crate::analyzer::{self, Lambda, Node, Target};
+use crate::continuation::{self, Continuation};
+use crate::core;
+use crate::debug;
+use crate::env::Env;
+use crate::error::{nargs_check, Exception, Payload};
+use crate::ns;
+use crate::sandbox;
+use crate::stack::{self, Call, Depth};
+use crate::symbol::{self, Symbol};
+use crate::trace;
+use crate::types::{Ret, Type};
+use crate::{catching, eval_node, recur_env};
+
+/// Backends able to evaluate mal code
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// Tree-walking interpreter, evaluating the forms as they are read
+    TreeWalker,
+    /// Forms are compiled to bytecode and run by a stack machine
+    Vm,
+}
+
+thread_local! {
+    static BACKEND: Cell<Backend> = const { Cell::new(Backend::TreeWalker) };
+    /// Number of generators running in this thread, the ones resumed
+    /// by a running generator included
+    static GENERATORS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Select the backend evaluating code in this thread
+pub fn set_backend(backend: Backend) {
+    BACKEND.with(|current| current.set(backend));
+}
+
+/// Return the backend evaluating code in this thread
+pub fn backend() -> Backend {
+    BACKEND.with(|current| current.get())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    /// Push a constant
+    Constant(usize),
+    /// Push the value of a symbol, looked up by name
+    Global(Symbol),
+    /// Push the value of a local variable by its lexical address
+    Local(Symbol, usize, usize),
+    /// Pop n values and push a vector with them
+    Vector(usize),
+    /// Pop the values of the keys in a constant list and push a hash
+    /// map with them
+    HashMap(usize),
+    Pop,
+    Jump(usize),
+    /// Pop a value and jump if it's nil or false
+    JumpIfFalse(usize),
+    /// Define a symbol (and whether it's dynamic) with the value on
+    /// top of the stack
+    Def(Symbol, bool),
+    /// Pop a closure and define it as a macro
+    DefMacro(Symbol),
+    /// Enter a new local scope
+    PushScope,
+    /// Pop a value and bind it in the local scope
+    Bind(Symbol),
+    /// Leave the local scope
+    PopScope,
+    /// Enter a loop in tail position, its body starts at the next
+    /// instruction
+    Loop,
+    /// Run a loop in non tail position, compiled to a chunk of the
+    /// chunk, in a new frame
+    Enter(usize),
+    /// Pop the arguments of a recur node of the chunk and run the body
+    /// of its loop or closure again
+    Recur(usize),
+    /// Create a closure from a lambda of the chunk
+    Closure(usize),
+    /// Call the function below n arguments
+    Call(usize),
+    /// Call the function below n arguments and return its value
+    TailCall(usize),
+    Return,
+    /// Install an exception handler running the `catch*` clause of a
+    /// `try*` of the chunk catching the exception
+    Try(usize),
+    /// Install an exception handler running the finally block at the
+    /// address, then rethrowing the exception
+    Finally(usize),
+    /// Remove the innermost exception handler
+    EndTry,
+    /// Rethrow the exception caught by the innermost finally block
+    Rethrow,
+    /// Pop a form and evaluate it in the root environment. The flag
+    /// tells if it's in tail position.
+    Eval(bool),
+    /// Macroexpand a macro call node of the chunk and run its
+    /// expansion. The flag tells if it's in tail position.
+    Defer(usize, bool),
+    /// Pop a value and suspend the coroutine run by the VM, yielding
+    /// the value
+    Yield,
+    /// Run the body of a reset node of the chunk in a new VM, the
+    /// delimiter of the continuations captured by shift
+    Reset(usize),
+    /// Capture the continuation up to the reset for a shift node of the
+    /// chunk and run its body instead
+    Shift(usize),
+    /// Evaluate a node of the chunk with the tree-walking interpreter
+    Fallback(usize),
+}
+
+/// Compiled code of a form or a closure body
+#[derive(Debug, Default)]
+pub struct Chunk {
+    code: Vec<Op>,
+    constants: Vec<Type>,
+    /// Nodes of the deferred and fallback forms
+    nodes: Vec<Rc<Node>>,
+    lambdas: Vec<Rc<Lambda>>,
+    /// Loops in non tail position
+    chunks: Vec<Rc<Chunk>>,
+    catches: Vec<Catches>,
+}
+
+/// `try*` node with `catch*` clauses and the address of their handlers
+#[derive(Debug)]
+struct Catches {
+    node: Rc<Node>,
+    handlers: Vec<usize>,
+}
+
+/// Compile and run a form in env
+pub fn run(ast: Type, env: &Rc<Env>) -> Ret {
+    let env = if env.is_namespace() {
+        ns::current(env)
+    } else {
+        env.clone()
+    };
+    let chunk = Rc::new(compile(&analyzer::analyze(&ast, &env)));
+
+    let mut vm = Vm::new();
+    vm.root = stack::level() == 0;
+    vm.enter(chunk, env, Call::Form, false)?;
+    vm.execute()
+}
+
+/// Evaluate a reset node in env. Its body is run by a new VM, shift
+/// captures the continuation of the body from the state of the VM.
+pub fn reset(node: &Node, env: &Rc<Env>) -> Ret {
+    let chunk = match *node {
+        Node::Reset {
+            ref body,
+            ref compiled,
+        } => compiled.get_or_init(|| Rc::new(compile(body))).clone(),
+        _ => unreachable!("only reset nodes delimit continuations"),
+    };
+    let mut vm = Vm::new();
+    vm.delimited = true;
+    vm.enter(chunk, env.clone(), Call::Form, false)?;
+    vm.execute()
+}
+
+/// Call a continuation captured by shift with value, returning the
+/// value of its reset
+pub fn compose(snapshot: &Snapshot, value: Type) -> Ret {
+    let mut vm = Vm::new();
+    vm.delimited = true;
+    vm.restore(snapshot.clone())?;
+    vm.stack.push(value);
+    vm.execute()
+}
+
+/// Error of a shift evaluated out of the body of a reset
+pub fn shift_outside() -> Exception {
+    Exception::builtin("'shift' called outside of a reset")
+}
+
+/// Apply a function or closure to args
+pub fn apply(fun: &Type, args: Vec<Type>) -> Ret {
+    let mut vm = Vm::new();
+    vm.stack.push(fun.clone());
+    let argc = args.len();
+    vm.stack.extend(args);
+
+    vm.call(argc, false)?;
+    if vm.frames.is_empty() {
+        return Ok(vm.stack.pop().unwrap());
+    }
+    vm.execute()
+}
+
+/// Function run as a coroutine by its own VM, suspended when it
+/// yields a value
+pub enum Coroutine {
+    /// Function not called yet
+    Start(Type),
+    Suspended(Snapshot),
+    Running,
+    /// The function returned or raised an exception
+    Done,
+}
+
+/// State of a VM, with the calls of its frames: a coroutine suspended
+/// by `yield` or a captured continuation
+#[derive(Clone)]
+pub struct Snapshot {
+    stack: Vec<Type>,
+    frames: Vec<Frame>,
+    handlers: Vec<Handler>,
+    pending: Vec<Exception>,
+    calls: Vec<Call>,
+}
+
+/// Run a coroutine until it yields a value or returns. Returns the
+/// value yielded, or None if the coroutine returned.
+pub fn resume(coroutine: &RefCell<Coroutine>) -> Result<Option<Type>, Exception> {
+    // the coroutine is done if it raises an exception
+    let mut vm = match coroutine.replace(Coroutine::Done) {
+        Coroutine::Start(fun) => {
+            let mut vm = Vm::new();
+            vm.coroutine = true;
+            vm.stack.push(fun);
+            vm.call(0, false)?;
+            vm
+        }
+        Coroutine::Suspended(snapshot) => {
+            let mut vm = Vm::new();
+            vm.coroutine = true;
+            vm.restore(snapshot)?;
+            // yield returns nil
+            vm.stack.push(Type::Nil);
+            vm
+        }
+        Coroutine::Running => {
+            coroutine.replace(Coroutine::Running);
+            return Err(Exception::builtin("generator is already running"));
+        }
+        Coroutine::Done => return Ok(None),
+    };
+    if vm.frames.is_empty() {
+        return Ok(None);
+    }
+
+    coroutine.replace(Coroutine::Running);
+    GENERATORS.with(|running| running.set(running.get() + 1));
+    let result = vm.execute();
+    GENERATORS.with(|running| running.set(running.get() - 1));
+    if vm.frames.is_empty() || result.is_err() {
+        coroutine.replace(Coroutine::Done);
+        return result.map(|_| None);
+    }
+    coroutine.replace(Coroutine::Suspended(vm.suspend()));
+    result.map(Some)
+}
+
+/// Error of a `yield` evaluated out of the body of a generator. While
+/// a generator runs, it's a `yield` in a function called back by a
+/// builtin, which runs in a VM of its own the generator can't suspend.
+pub fn yield_outside() -> Exception {
+    if GENERATORS.with(|running| running.get()) > 0 {
+        Exception::builtin("'yield' called from a function called back by a builtin")
+    } else {
+        Exception::builtin("'yield' called outside of a generator")
+    }
+}
+
+/// Compile analyzed code
+fn compile(node: &Rc<Node>) -> Chunk {
+    let mut compiler = Compiler {
+        chunk: Chunk::default(),
+    };
+    compiler.compile(node, true);
+    compiler.emit(Op::Return);
+    compiler.chunk
+}
+
+struct Compiler {
+    chunk: Chunk,
+}
+
+impl Compiler {
+    fn emit(&mut self, op: Op) -> usize {
+        self.chunk.code.push(op);
+        self.chunk.code.len() - 1
+    }
+
+    fn constant(&mut self, value: Type) -> usize {
+        self.chunk.constants.push(value);
+        self.chunk.constants.len() - 1
+    }
+
+    fn node(&mut self, node: &Rc<Node>) -> usize {
+        self.chunk.nodes.push(node.clone());
+        self.chunk.nodes.len() - 1
+    }
+
+    /// Point the jump at address to the next instruction
+    fn patch(&mut self, address: usize) {
+        let target = self.chunk.code.len();
+        match self.chunk.code[address] {
+            Op::Jump(ref mut to) | Op::JumpIfFalse(ref mut to) | Op::Finally(ref mut to) => {
+                *to = target
+            }
+            _ => unreachable!("only jumps can be patched"),
+        }
+    }
+
+    /// Compile a node. Macro calls are deferred until they are run,
+    /// then their expansion is compiled and cached. The special forms
+    /// that are rarely used, or that failed to be analyzed, are left
+    /// to the tree-walking interpreter.
+    fn compile(&mut self, node: &Rc<Node>, tail: bool) {
+        match **node {
+            Node::Constant(ref value) => {
+                let constant = self.constant(value.clone());
+                self.emit(Op::Constant(constant));
+            }
+
+            Node::Global(symbol) => {
+                self.emit(Op::Global(symbol));
+            }
+
+            Node::Local {
+                symbol,
+                depth,
+                slot,
+            } => {
+                self.emit(Op::Local(symbol, depth, slot));
+            }
+
+            Node::Vector(ref elems) => {
+                for elem in elems {
+                    self.compile(elem, false);
+                }
+                self.emit(Op::Vector(elems.len()));
+            }
+
+            Node::HashMap(ref entries) => {
+                let mut keys = Vec::with_capacity(entries.len());
+                for (key, value) in entries {
+                    keys.push(Type::String(key.clone()));
+                    self.compile(value, false);
+                }
+                let keys = self.constant(Type::List(keys));
+                self.emit(Op::HashMap(keys));
+            }
+
+            Node::Def {
+                name,
+                dynamic,
+                ref value,
+            } => {
+                self.compile(value, false);
+                self.emit(Op::Def(name, dynamic));
+            }
+
+            Node::DefMacro { name, ref value } => {
+                self.compile(value, false);
+                self.emit(Op::DefMacro(name));
+            }
+
+            Node::Let {
+                ref bindings,
+                ref body,
+            } => {
+                self.emit(Op::PushScope);
+                for (name, value) in bindings {
+                    self.compile(value, false);
+                    self.emit(Op::Bind(*name));
+                }
+                self.compile(body, tail);
+                if !tail {
+                    self.emit(Op::PopScope);
+                }
+            }
+
+            // recur only runs the loop of its frame again, the body of
+            // the loop is kept in tail position
+            Node::Loop { .. } if !tail => {
+                self.chunk.chunks.push(Rc::new(compile(node)));
+                self.emit(Op::Enter(self.chunk.chunks.len() - 1));
+            }
+
+            Node::Loop {
+                ref bindings,
+                ref body,
+            } => {
+                self.emit(Op::PushScope);
+                for (name, value) in bindings {
+                    self.compile(value, false);
+                    self.emit(Op::Bind(*name));
+                }
+                self.emit(Op::Loop);
+                self.compile(body, true);
+            }
+
+            Node::Recur { ref args, .. } => {
+                for arg in args {
+                    self.compile(arg, false);
+                }
+                let node = self.node(node);
+                self.emit(Op::Recur(node));
+            }
+
+            Node::Do(ref forms) => {
+                let (last, forms) = forms.split_last().unwrap();
+                for form in forms {
+                    self.compile(form, false);
+                    self.emit(Op::Pop);
+                }
+                self.compile(last, tail);
+            }
+
+            Node::If {
+                ref cond,
+                ref then,
+                ref otherwise,
+            } => {
+                self.compile(cond, false);
+                let to_else = self.emit(Op::JumpIfFalse(0));
+                self.compile(then, tail);
+                let to_end = self.emit(Op::Jump(0));
+                self.patch(to_else);
+                match otherwise {
+                    Some(otherwise) => self.compile(otherwise, tail),
+                    None => {
+                        let nil = self.constant(Type::Nil);
+                        self.emit(Op::Constant(nil));
+                    }
+                }
+                self.patch(to_end);
+            }
+
+            Node::Fn(ref lambda) => {
+                self.chunk.lambdas.push(lambda.clone());
+                self.emit(Op::Closure(self.chunk.lambdas.len() - 1));
+            }
+
+            Node::Eval(ref form) => {
+                self.compile(form, false);
+                self.emit(Op::Eval(tail));
+            }
+
+            Node::Try {
+                ref body,
+                ref catches,
+                finally: None,
+            } if catches.is_empty() => self.compile(body, tail),
+
+            Node::Try {
+                ref body,
+                ref catches,
+                ref finally,
+            } => {
+                let to_finally = finally.as_ref().map(|_| self.emit(Op::Finally(0)));
+                // the finally block runs after the catch* handlers
+                let tail = tail && finally.is_none();
+                if catches.is_empty() {
+                    self.compile(body, false);
+                } else {
+                    self.chunk.catches.push(Catches {
+                        node: node.clone(),
+                        handlers: vec![],
+                    });
+                    let index = self.chunk.catches.len() - 1;
+                    self.emit(Op::Try(index));
+                    self.compile(body, false);
+                    self.emit(Op::EndTry);
+                    let mut to_end = vec![self.emit(Op::Jump(0))];
+                    for catch in catches {
+                        let handler = self.chunk.code.len();
+                        self.chunk.catches[index].handlers.push(handler);
+                        self.compile(&catch.handler, tail);
+                        if tail {
+                            self.emit(Op::Return);
+                        } else {
+                            self.emit(Op::PopScope);
+                            to_end.push(self.emit(Op::Jump(0)));
+                        }
+                    }
+                    for jump in to_end {
+                        self.patch(jump);
+                    }
+                }
+
+                if let (Some(to_finally), Some(finally)) = (to_finally, finally) {
+                    self.emit(Op::EndTry);
+                    self.compile(finally, false);
+                    self.emit(Op::Pop);
+                    let to_end = self.emit(Op::Jump(0));
+                    self.patch(to_finally);
+                    self.compile(finally, false);
+                    self.emit(Op::Pop);
+                    self.emit(Op::Rethrow);
+                    self.patch(to_end);
+                }
+            }
+
+            Node::Call(ref call) => {
+                for node in call {
+                    self.compile(node, false);
+                }
+                if tail {
+                    self.emit(Op::TailCall(call.len() - 1));
+                } else {
+                    self.emit(Op::Call(call.len() - 1));
+                }
+            }
+
+            Node::Yield(ref value) => {
+                self.compile(value, false);
+                self.emit(Op::Yield);
+            }
+
+            Node::Reset { .. } => {
+                let node = self.node(node);
+                self.emit(Op::Reset(node));
+            }
+
+            Node::Shift { .. } => {
+                let node = self.node(node);
+                self.emit(Op::Shift(node));
+            }
+
+            Node::MacroCall { .. } => {
+                let node = self.node(node);
+                self.emit(Op::Defer(node, tail));
+            }
+
+            _ => {
+                let node = self.node(node);
+                self.emit(Op::Fallback(node));
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Frame {
+    chunk: Rc<Chunk>,
+    ip: usize,
+    env: Rc<Env>,
+    /// Stack size when the frame was entered
+    base: usize,
+    /// Chunk and address of the body of the loop entered last
+    recur: Option<(Rc<Chunk>, usize)>,
+}
+
+#[derive(Clone)]
+struct Handler {
+    /// Number of frames when the handler was installed
+    frames: usize,
+    /// Stack size when the handler was installed
+    stack: usize,
+    /// Number of pending exceptions when the handler was installed
+    pending: usize,
+    env: Rc<Env>,
+    catching: Catching,
+}
+
+/// What an exception handler runs
+#[derive(Clone, Copy)]
+enum Catching {
+    /// `catch*` clauses of a `try*` of the chunk
+    Clauses(usize),
+    /// Finally block at the address
+    Finally(usize),
+}
+
+impl Handler {
+    /// Return true if the handler can catch the exception, instead of
+    /// always rethrowing it
+    fn catches(&self) -> bool {
+        matches!(self.catching, Catching::Clauses(_))
+    }
+}
+
+struct Vm {
+    stack: Vec<Type>,
+    frames: Vec<Frame>,
+    handlers: Vec<Handler>,
+    /// Exceptions rethrown at the end of the finally blocks running
+    pending: Vec<Exception>,
+    /// Evaluation depth when the VM was started, each frame is one
+    /// more level. It's restored when the VM is dropped.
+    depth: Depth,
+    /// The VM runs a coroutine, it can yield
+    coroutine: bool,
+    /// The VM runs the body of a `reset`, shift captures its state
+    delimited: bool,
+    /// The VM runs top level code, its continuation is the end of the
+    /// evaluation
+    root: bool,
+    /// Alive while the VM runs, for the continuations it captures
+    extent: Option<Rc<()>>,
+}
+
+impl Drop for Vm {
+    fn drop(&mut self) {
+        // handlers left by an exception unwinding through the VM
+        let catching = self.handlers.iter().filter(|handler| handler.catches());
+        debug::exit_try(catching.count());
+    }
+}
+
+impl Vm {
+    fn new() -> Vm {
+        Vm {
+            stack: Vec::with_capacity(64),
+            frames: vec![],
+            handlers: vec![],
+            pending: vec![],
+            depth: stack::save(),
+            coroutine: false,
+            delimited: false,
+            root: false,
+            extent: None,
+        }
+    }
+
+    /// Number of handlers of `try*` bodies being evaluated
+    fn trying(&self) -> usize {
+        let catching = self.handlers.iter().filter(|handler| handler.catches());
+        catching.count()
+    }
+
+    /// Suspend the coroutine run by the VM, leaving the VM empty
+    fn suspend(&mut self) -> Snapshot {
+        // the try* bodies are evaluated again when it's resumed
+        debug::exit_try(self.trying());
+        Snapshot {
+            stack: std::mem::take(&mut self.stack),
+            frames: std::mem::take(&mut self.frames),
+            handlers: std::mem::take(&mut self.handlers),
+            pending: std::mem::take(&mut self.pending),
+            calls: stack::suspend(&self.depth),
+        }
+    }
+
+    /// Return the state of the VM, to capture its continuation
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            stack: self.stack.clone(),
+            frames: self.frames.clone(),
+            handlers: self.handlers.clone(),
+            pending: self.pending.clone(),
+            calls: stack::calls_since(&self.depth),
+        }
+    }
+
+    /// Replace the state of the VM, running a suspended coroutine or a
+    /// continuation from the current depth
+    fn restore(&mut self, snapshot: Snapshot) -> Result<(), Exception> {
+        debug::exit_try(self.trying());
+        stack::set(&self.depth, 0);
+        self.stack = snapshot.stack;
+        self.frames = snapshot.frames;
+        self.handlers = snapshot.handlers;
+        self.pending = snapshot.pending;
+        for _ in 0..self.trying() {
+            debug::enter_try();
+        }
+        stack::resume(snapshot.calls);
+        stack::check()
+    }
+
+    /// Capture the continuation of the running code. The continuation
+    /// of a tail call returns from the frame making it.
+    fn capture(&mut self, delimited: bool, tail: bool) -> Rc<Continuation> {
+        let mut snapshot = self.snapshot();
+        if tail {
+            // chunks end with a Return
+            let frame = snapshot.frames.last_mut().unwrap();
+            frame.ip = frame.chunk.code.len() - 1;
+        }
+        let extent = self.extent.get_or_insert_with(|| Rc::new(()));
+        Rc::new(Continuation {
+            extent: Rc::downgrade(extent),
+            snapshot: Some(snapshot),
+            root: self.root,
+            delimited,
+        })
+    }
+
+    /// Call a continuation with value. The VM jumps to the ones it
+    /// captured, and to the ones of top level code if it runs top
+    /// level code too. Delimited continuations return a value, the
+    /// others unwind to the evaluation that captured them.
+    fn resume_continuation(
+        &mut self,
+        continuation: Rc<Continuation>,
+        value: Type,
+    ) -> Result<Option<Type>, Exception> {
+        let snapshot = match continuation.snapshot {
+            Some(ref snapshot) if continuation.delimited => {
+                return Ok(Some(compose(snapshot, value)?));
+            }
+            // the finally blocks on the way are run by unwinding to it
+            Some(ref snapshot)
+                if self.resumes(&continuation)
+                    && self.handlers.len() <= snapshot.handlers.len() =>
+            {
+                snapshot.clone()
+            }
+            Some(_) if self.resumes(&continuation) => {
+                return Err(Exception::escape(continuation, value))
+            }
+            _ => return continuation.escape(value).map(Some),
+        };
+        self.restore(snapshot)?;
+        self.stack.push(value);
+        Ok(None)
+    }
+
+    /// Return true if the VM can jump to continuation
+    fn resumes(&self, continuation: &Continuation) -> bool {
+        self.owns(continuation) || (self.root && continuation.root)
+    }
+
+    /// Return true if the VM captured continuation
+    fn owns(&self, continuation: &Continuation) -> bool {
+        self.extent
+            .as_ref()
+            .is_some_and(|extent| continuation.captured_by(extent))
+    }
+
+    /// Run until the first frame returns. Exceptions unwind to the
+    /// innermost handler, if any.
+    fn execute(&mut self) -> Ret {
+        loop {
+            match self.run() {
+                Ok(value) => return Ok(value),
+                Err(exc) => self.catch(stack::traced(exc))?,
+            }
+        }
+    }
+
+    fn catch(&mut self, exc: Exception) -> Result<(), Exception> {
+        // continuations jump once the finally blocks installed since
+        // they were captured have run, the catch* clauses don't catch
+        // them
+        if let Payload::Escape(ref continuation, ref value) = exc.payload {
+            match continuation.snapshot {
+                Some(ref snapshot)
+                    if self.resumes(continuation)
+                        && self.handlers.len() <= snapshot.handlers.len() =>
+                {
+                    let value = value.clone();
+                    self.restore(snapshot.clone())?;
+                    self.stack.push(value);
+                    return Ok(());
+                }
+                _ => (),
+            }
+        }
+
+        let handler = match self.handlers.pop() {
+            Some(handler) => handler,
+            None => return Err(exc),
+        };
+        self.frames.truncate(handler.frames);
+        stack::set(&self.depth, self.frames.len());
+        self.stack.truncate(handler.stack);
+        self.pending.truncate(handler.pending);
+
+        let chunk = self.frames.last().unwrap().chunk.clone();
+        let (ip, env) = match handler.catching {
+            Catching::Clauses(index) => {
+                debug::exit_try(1);
+                let catches = &chunk.catches[index];
+                let clauses = match *catches.node {
+                    Node::Try { ref catches, .. } => catches,
+                    _ => unreachable!("only try* nodes have catch* clauses"),
+                };
+                // exceptions no clause catches unwind to the next handler
+                let (index, exception) = match catching(clauses, exc, &handler.env) {
+                    Ok(caught) => caught,
+                    Err(exc) => return self.catch(exc),
+                };
+                let var = clauses[index].var;
+                let env = Env::new(Some(handler.env), &[var], &[exception]);
+                (catches.handlers[index], Rc::new(env))
+            }
+            Catching::Finally(address) => {
+                self.pending.push(exc);
+                (address, handler.env)
+            }
+        };
+        let frame = self.frames.last_mut().unwrap();
+        frame.ip = ip;
+        frame.env = env;
+        Ok(())
+    }
+
+    /// Environment of the running frame. Top level code is run in the
+    /// current namespace.
+    fn env(&self) -> Rc<Env> {
+        let env = &self.frames.last().unwrap().env;
+        if env.is_namespace() {
+            ns::current(env)
+        } else {
+            env.clone()
+        }
+    }
+
+    fn run(&mut self) -> Ret {
+        loop {
+            let frame = self.frames.last_mut().unwrap();
+            let op = frame.chunk.code[frame.ip];
+            frame.ip += 1;
+
+            match op {
+                Op::Constant(constant) => {
+                    let value = frame.chunk.constants[constant].clone();
+                    self.stack.push(value);
+                }
+
+                Op::Global(symbol) => {
+                    let value = if frame.env.is_namespace() {
+                        ns::current(&frame.env).get(symbol)?
+                    } else {
+                        frame.env.get(symbol)?
+                    };
+                    self.stack.push(value);
+                }
+
+                Op::Local(symbol, depth, slot) => {
+                    let value = frame.env.get_address(depth, slot, symbol)?;
+                    self.stack.push(value);
+                }
+
+                Op::Vector(len) => {
+                    let vector = Type::Vector(self.stack.split_off(self.stack.len() - len));
+                    sandbox::check_size(&vector)?;
+                    self.stack.push(vector);
+                }
+
+                Op::HashMap(constant) => {
+                    let keys = match frame.chunk.constants[constant] {
+                        Type::List(ref keys) => keys,
+                        _ => unreachable!("hash map keys are compiled to a list"),
+                    };
+                    let values = self.stack.split_off(self.stack.len() - keys.len());
+                    let hash_map = keys
+                        .iter()
+                        .zip(values)
+                        .filter_map(|(key, value)| match key {
+                            Type::String(key) => Some((key.clone(), Box::new(value))),
+                            _ => None,
+                        })
+                        .collect();
+                    let hash_map = Type::HashMap(hash_map);
+                    sandbox::check_size(&hash_map)?;
+                    self.stack.push(hash_map);
+                }
+
+                Op::Pop => {
+                    self.stack.pop();
+                }
+
+                Op::Jump(address) => frame.ip = address,
+
+                Op::JumpIfFalse(address) => {
+                    if !self.stack.pop().unwrap().is_truthy() {
+                        frame.ip = address;
+                    }
+                }
+
+                Op::Def(name, dynamic) => {
+                    let env = self.env();
+                    let value = self.stack.last().unwrap().clone();
+                    value.name_closure(name);
+                    env.set(name, value);
+                    if env.is_namespace() {
+                        ns::set_dynamic(&env, name, dynamic);
+                        trace::set_var(name, self.stack.last().unwrap());
+                    }
+                }
+
+                Op::DefMacro(name) => {
+                    let value = match self.stack.pop() {
+                        Some(Type::Closure { env, lambda, .. }) => Type::Closure {
+                            env,
+                            lambda,
+                            is_macro: true,
+                        },
+                        _ => {
+                            return Err(Exception::type_error(
+                                "Type error defmacro! must be called with a function",
+                            ))
+                        }
+                    };
+                    value.name_closure(name);
+                    self.env().set(name, value.clone());
+                    self.stack.push(value);
+                }
+
+                Op::PushScope => {
+                    let env = Env::new(Some(self.env()), &[], &[]);
+                    self.frames.last_mut().unwrap().env = Rc::new(env);
+                }
+
+                Op::Bind(name) => {
+                    let value = self.stack.pop().unwrap();
+                    frame.env.bind(name, value);
+                }
+
+                Op::PopScope => {
+                    let outer = frame.env.outer().unwrap().clone();
+                    frame.env = outer;
+                }
+
+                Op::Loop => frame.recur = Some((frame.chunk.clone(), frame.ip)),
+
+                Op::Enter(chunk) => {
+                    let chunk = frame.chunk.chunks[chunk].clone();
+                    let env = frame.env.clone();
+                    self.enter(chunk, env, Call::Form, false)?;
+                }
+
+                Op::Recur(node) => {
+                    let (depth, slots, target) = match *frame.chunk.nodes[node] {
+                        Node::Recur {
+                            depth,
+                            ref slots,
+                            target,
+                            ..
+                        } => (depth, slots.clone(), target),
+                        _ => unreachable!("recur nodes are compiled to Recur"),
+                    };
+                    let values = self.stack.split_off(self.stack.len() - slots.len());
+                    let env = recur_env(&frame.env, depth, &slots, values)?;
+                    let (chunk, ip) = match target {
+                        Target::Loop => frame.recur.clone().expect("recur is in a loop"),
+                        Target::Fn => {
+                            frame.recur = None;
+                            let lambda = stack::recur_call(&env);
+                            (lambda.compiled.get().unwrap().clone(), 0)
+                        }
+                    };
+                    frame.chunk = chunk;
+                    frame.ip = ip;
+                    frame.env = env;
+                }
+
+                Op::Closure(lambda) => {
+                    let lambda = frame.chunk.lambdas[lambda].clone();
+                    self.stack.push(Type::Closure {
+                        env: self.env(),
+                        lambda,
+                        is_macro: false,
+                    });
+                }
+
+                Op::Call(argc) => {
+                    self.call(argc, false)?;
+                }
+
+                Op::TailCall(argc) => {
+                    if let Some(value) = self.call(argc, true)? {
+                        return Ok(value);
+                    }
+                }
+
+                Op::Return => {
+                    let value = self.stack.pop().unwrap();
+                    if let Some(value) = self.ret(value) {
+                        return Ok(value);
+                    }
+                }
+
+                Op::Try(catches) => {
+                    self.install(Catching::Clauses(catches));
+                    debug::enter_try();
+                }
+
+                Op::Finally(address) => self.install(Catching::Finally(address)),
+
+                Op::EndTry => {
+                    if self.handlers.pop().is_some_and(|handler| handler.catches()) {
+                        debug::exit_try(1);
+                    }
+                }
+
+                Op::Rethrow => return Err(self.pending.pop().unwrap()),
+
+                Op::Eval(tail) => {
+                    let ast = self.stack.pop().unwrap();
+                    let root = ns::current(&frame.env.outermost());
+                    let chunk = Rc::new(compile(&analyzer::analyze(&ast, &root)));
+                    self.enter(chunk, root, Call::Form, tail)?;
+                }
+
+                Op::Defer(node, tail) => {
+                    let node = frame.chunk.nodes[node].clone();
+                    let env = self.env();
+                    let expansion = analyzer::expansion(&node, &env)?;
+                    let chunk = expansion
+                        .compiled
+                        .get_or_init(|| Rc::new(compile(&expansion.node)))
+                        .clone();
+                    self.enter(chunk, env, Call::Form, tail)?;
+                }
+
+                Op::Yield if self.coroutine => return Ok(self.stack.pop().unwrap()),
+
+                Op::Yield => return Err(yield_outside()),
+
+                Op::Reset(node) => {
+                    let node = frame.chunk.nodes[node].clone();
+                    let value = reset(&node, &self.env())?;
+                    self.stack.push(value);
+                }
+
+                Op::Shift(_) if !self.delimited => return Err(shift_outside()),
+
+                Op::Shift(node) => {
+                    let node = frame.chunk.nodes[node].clone();
+                    let (var, chunk) = match *node {
+                        Node::Shift {
+                            var,
+                            ref body,
+                            ref compiled,
+                        } => (var, compiled.get_or_init(|| Rc::new(compile(body))).clone()),
+                        _ => unreachable!("shift nodes are compiled to Shift"),
+                    };
+                    let env = self.env();
+                    let continuation = Type::Continuation(self.capture(true, false));
+                    let env = Rc::new(Env::new(Some(env), &[var], &[continuation]));
+
+                    // the body is run in place of the body of the reset
+                    debug::exit_try(self.trying());
+                    self.handlers.clear();
+                    self.pending.clear();
+                    self.frames.clear();
+                    self.stack.clear();
+                    stack::set(&self.depth, 0);
+                    self.enter(chunk, env, Call::Form, false)?;
+                }
+
+                Op::Fallback(node) => {
+                    let node = frame.chunk.nodes[node].clone();
+                    let value = eval_node(&node, &self.env())?;
+                    self.stack.push(value);
+                }
+            }
+        }
+    }
+
+    fn install(&mut self, catching: Catching) {
+        let handler = Handler {
+            frames: self.frames.len(),
+            stack: self.stack.len(),
+            pending: self.pending.len(),
+            env: self.env(),
+            catching,
+        };
+        self.handlers.push(handler);
+    }
+
+    /// Run chunk in env for call, in a new frame or replacing the
+    /// running one if it's in tail position. Forms run in tail position
+    /// keep the call of the frame they replace.
+    fn enter(
+        &mut self,
+        chunk: Rc<Chunk>,
+        env: Rc<Env>,
+        call: Call,
+        tail: bool,
+    ) -> Result<(), Exception> {
+        if tail {
+            let frame = self.frames.last_mut().unwrap();
+            self.stack.truncate(frame.base);
+            frame.chunk = chunk;
+            frame.ip = 0;
+            frame.env = env;
+            if !matches!(call, Call::Form) {
+                frame.recur = None;
+                stack::replace(call)?;
+            }
+        } else {
+            stack::push(call)?;
+            self.frames.push(Frame {
+                chunk,
+                ip: 0,
+                env,
+                base: self.stack.len(),
+                recur: None,
+            });
+            stack::set(&self.depth, self.frames.len());
+            stack::check()?;
+        }
+        Ok(())
+    }
+
+    /// Call the function below argc arguments on the stack. Closures
+    /// are run in a new frame, or in the running one if the call is in
+    /// tail position. Returns the value of the first frame if a tail
+    /// call returned from it.
+    fn call(&mut self, argc: usize, tail: bool) -> Result<Option<Type>, Exception> {
+        let position = self.stack.len() - argc - 1;
+        let mut args = self.stack.split_off(position + 1);
+        let mut fun = self.stack.pop().unwrap();
+        while let Type::Fun(_, symbol::APPLY) = fun {
+            (fun, args) = core::apply_args(args)?;
+        }
+
+        match fun {
+            Type::Fun(_, symbol::CALL_CC) => {
+                nargs_check("call/cc", 1, args.len())?;
+                let continuation = self.capture(false, tail);
+                self.stack.push(args.pop().unwrap());
+                self.stack.push(Type::Continuation(continuation));
+                return self.call(1, tail);
+            }
+
+            Type::Continuation(continuation) => {
+                let value = continuation::argument(args)?;
+                return match self.resume_continuation(continuation, value)? {
+                    Some(value) if tail => Ok(self.ret(value)),
+                    Some(value) => {
+                        self.stack.push(value);
+                        Ok(None)
+                    }
+                    None => Ok(None),
+                };
+            }
+
+            Type::Fun(fun, name) => {
+                let value = stack::call_builtin(fun, name, args)?;
+                if tail {
+                    return Ok(self.ret(value));
+                }
+                self.stack.push(value);
+            }
+
+            Type::Closure { env, lambda, .. } => {
+                lambda.check_nargs(args.len())?;
+                let chunk = lambda
+                    .compiled
+                    .get_or_init(|| Rc::new(compile(&lambda.body_node(&env))))
+                    .clone();
+                let fun_env = Rc::new(Env::new(Some(env), &lambda.binds, &args));
+                self.enter(chunk, fun_env.clone(), Call::Closure(lambda, fun_env), tail)?;
+            }
+
+            _ => return Err(Exception::type_error("first argument must be a function")),
+        }
+
+        Ok(None)
+    }
+
+    /// Return value from the running frame. Returns the value back if
+    /// it was the first frame.
+    fn ret(&mut self, value: Type) -> Option<Type> {
+        if stack::hooked() {
+            stack::returned(&value);
+        }
+        let frame = self.frames.pop().unwrap();
+        stack::set(&self.depth, self.frames.len());
+        self.stack.truncate(frame.base);
+        if self.frames.is_empty() {
+            return Some(value);
+        }
+        self.stack.push(value);
+        None
+    }
+}
@@ -1,10 +1,12 @@
+#![allow(dead_code)]
+
 use std::error::Error;
 use std::fs::File;
 use std::io::Read;
 use std::rc::Rc;
 
-use mal_rust;
 use mal_rust::env::Env;
+use mal_rust::Backend;
 
 pub fn load_core(env: &Rc<Env>) -> Result<(), Box<dyn Error>> {
     load_mal_file(env, "src/core.mal")
@@ -16,8 +18,23 @@ pub fn load_mal_file(env: &Rc<Env>, name: &str) -> Result<(), Box<dyn Error>> {
     file.read_to_string(&mut contents)?;
 
     for line in contents.lines() {
-        mal_rust::rep(line, &env);
+        mal_rust::rep(line, env);
     }
 
     Ok(())
 }
+
+/// Return a new environment with the core loaded, evaluated with backend
+pub fn new_env(backend: Backend) -> Rc<Env> {
+    mal_rust::set_backend(backend);
+    let env = Rc::new(Env::new_default());
+    mal_rust::load_core(&env);
+    env
+}
+
+/// Run test with a new environment for each backend
+pub fn for_each_backend(mut test: impl FnMut(Rc<Env>)) {
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        test(new_env(backend));
+    }
+}
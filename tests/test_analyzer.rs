@@ -0,0 +1,87 @@
+mod common;
+
+#[test]
+fn testing_macro_calls_are_expanded_once() {
+    common::for_each_backend(|env| {
+        mal_rust::rep("(def! expansions (atom 0))", &env);
+        mal_rust::rep(
+            "(defmacro! twice (fn* (x) (do (swap! expansions + 1) `(* 2 ~x))))",
+            &env,
+        );
+        mal_rust::rep("(def! f (fn* (n) (twice n)))", &env);
+        assert_eq!(mal_rust::rep("(list (f 1) (f 2) (f 3))", &env), "(2 4 6)");
+        assert_eq!(mal_rust::rep("@expansions", &env), "1");
+    });
+}
+
+#[test]
+fn testing_macro_calls_are_expanded_again_when_redefined() {
+    common::for_each_backend(|env| {
+        mal_rust::rep("(defmacro! m (fn* (x) `(+ ~x 1)))", &env);
+        mal_rust::rep("(defmacro! outer (fn* (x) `(m ~x)))", &env);
+        mal_rust::rep("(def! f (fn* (n) (m n)))", &env);
+        mal_rust::rep("(def! g (fn* (n) (outer n)))", &env);
+        assert_eq!(mal_rust::rep("(list (f 5) (g 5))", &env), "(6 6)");
+
+        mal_rust::rep("(defmacro! m (fn* (x) `(+ ~x 2)))", &env);
+        assert_eq!(mal_rust::rep("(list (f 5) (g 5))", &env), "(7 7)");
+
+        // a function defined with the name of the macro
+        mal_rust::rep("(def! m (fn* (x) (* x 10)))", &env);
+        assert_eq!(mal_rust::rep("(list (f 5) (g 5))", &env), "(50 50)");
+    });
+}
+
+#[test]
+fn testing_functions_defined_later() {
+    common::for_each_backend(|env| {
+        mal_rust::rep(
+            "(def! is-even? (fn* (n) (if (= n 0) true (is-odd? (- n 1)))))",
+            &env,
+        );
+        mal_rust::rep(
+            "(def! is-odd? (fn* (n) (if (= n 0) false (is-even? (- n 1)))))",
+            &env,
+        );
+        assert_eq!(mal_rust::rep("(is-even? 10)", &env), "true");
+        assert_eq!(mal_rust::rep("(is-odd? 10)", &env), "false");
+    });
+}
+
+#[test]
+fn testing_malformed_forms_fail_when_evaluated() {
+    common::for_each_backend(|env| {
+        assert_eq!(
+            mal_rust::rep("(def! f (fn* (x) (if x)))", &env),
+            "(fn* (x) (if x))"
+        );
+        assert_eq!(
+            mal_rust::rep("(f 1)", &env),
+            "Error: Malformed if expression"
+        );
+        assert_eq!(
+            mal_rust::rep("(fn* (x) (let* (1 x) x))", &env),
+            "(fn* (x) (let* (1 x) x))"
+        );
+        assert_eq!(
+            mal_rust::rep("((fn* (x) (let* (1 x) x)) 1)", &env),
+            "Error: let* variable names must be symbols"
+        );
+    });
+}
+
+#[test]
+fn testing_recursive_functions() {
+    common::for_each_backend(|env| {
+        mal_rust::rep(
+            "(def! fib (fn* (n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2))))))",
+            &env,
+        );
+        assert_eq!(mal_rust::rep("(fib 20)", &env), "6765");
+        mal_rust::rep(
+            "(def! sum-to (fn* (n acc) (if (= n 0) acc (sum-to (- n 1) (+ n acc)))))",
+            &env,
+        );
+        assert_eq!(mal_rust::rep("(sum-to 10000 0)", &env), "50005000");
+    });
+}
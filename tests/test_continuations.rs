@@ -0,0 +1,174 @@
+use mal_rust::Backend;
+
+mod common;
+
+#[test]
+fn testing_escaping_continuations() {
+    common::for_each_backend(|env| {
+        assert_eq!(
+            mal_rust::rep("(+ 1 (call/cc (fn* (k) (+ 10 (k 2)))))", &env),
+            "3"
+        );
+        assert_eq!(mal_rust::rep("(call/cc (fn* (k) 5))", &env), "5");
+        assert_eq!(mal_rust::rep("(call/cc (fn* (k) (k)))", &env), "nil");
+        assert_eq!(
+            mal_rust::rep("(call/cc (fn* (k) k))", &env),
+            "#<continuation>"
+        );
+
+        mal_rust::rep(
+            "(def! sign (fn* (x) (call/cc (fn* (k) (do (if (< x 0) (k :neg) nil) :pos)))))",
+            &env,
+        );
+        assert_eq!(mal_rust::rep("[(sign 1) (sign -1)]", &env), "[:pos :neg]");
+
+        // builtins calling back are unwound too
+        assert_eq!(
+            mal_rust::rep("(call/cc (fn* (k) (map (fn* (x) (k x)) [1 2])))", &env),
+            "1"
+        );
+    });
+}
+
+#[test]
+fn testing_escapes_skip_catch_clauses() {
+    common::for_each_backend(|env| {
+        assert_eq!(
+            mal_rust::rep("(call/cc (fn* (k) (try* (k 7) (catch* e :caught))))", &env),
+            "7"
+        );
+
+        // finally blocks are run on the way out
+        mal_rust::rep("(def! log (atom []))", &env);
+        assert_eq!(
+            mal_rust::rep(
+                "(call/cc (fn* (k) (try* (k 7) (finally (swap! log conj :finally)))))",
+                &env
+            ),
+            "7"
+        );
+        assert_eq!(mal_rust::rep("@log", &env), "[:finally]");
+        assert_eq!(
+            mal_rust::rep(
+                "(call/cc (fn* (k) (try* (try* (k 8) (catch* e :caught) (finally (swap! log conj :inner))) (finally (swap! log conj :outer)))))",
+                &env
+            ),
+            "8"
+        );
+        assert_eq!(mal_rust::rep("@log", &env), "[:finally :inner :outer]");
+
+        // from builtins calling back too
+        mal_rust::rep("(def! log (atom []))", &env);
+        assert_eq!(
+            mal_rust::rep(
+                "(call/cc (fn* (k) (map (fn* (x) (try* (k x) (finally (swap! log conj x)))) [1 2])))",
+                &env
+            ),
+            "1"
+        );
+        assert_eq!(mal_rust::rep("@log", &env), "[1]");
+    });
+}
+
+#[test]
+fn testing_reentrant_continuations() {
+    let env = common::new_env(Backend::Vm);
+    mal_rust::rep(
+        "(def! count-to (fn* (n) (let* [i (atom 0) k (call/cc (fn* (c) c))] (do (swap! i + 1) (if (< @i n) (k k) @i)))))",
+        &env,
+    );
+    assert_eq!(mal_rust::rep("(count-to 5)", &env), "5");
+
+    // the continuation of top level code is resumed by later top level
+    // code
+    mal_rust::rep("(def! saved (atom nil))", &env);
+    mal_rust::rep("(def! log (atom []))", &env);
+    assert_eq!(
+        mal_rust::rep(
+            "(swap! log conj (call/cc (fn* (k) (do (reset! saved k) 1))))",
+            &env
+        ),
+        "[1]"
+    );
+    assert_eq!(mal_rust::rep("(@saved 2)", &env), "[1 2]");
+    assert_eq!(mal_rust::rep("@log", &env), "[1 2]");
+}
+
+#[test]
+fn testing_reset_shift() {
+    common::for_each_backend(|env| {
+        assert_eq!(mal_rust::rep("(reset (+ 1 2))", &env), "3");
+        assert_eq!(
+            mal_rust::rep("(reset (+ 1 (shift k (k (k 10)))))", &env),
+            "12"
+        );
+        assert_eq!(mal_rust::rep("(reset (+ 1 (shift k 42)))", &env), "42");
+        assert_eq!(
+            mal_rust::rep("(reset (* 2 (shift k (+ (k 1) (k 10)))))", &env),
+            "22"
+        );
+
+        // continuations are values, called after their reset returned
+        mal_rust::rep("(def! k (reset (* 2 (shift k k))))", &env);
+        assert_eq!(mal_rust::rep("(k 21)", &env), "42");
+        assert_eq!(mal_rust::rep("(map k [1 2])", &env), "(2 4)");
+
+        // shift captures up to the innermost reset
+        assert_eq!(
+            mal_rust::rep("(reset (+ 1 (reset (* 2 (shift k (k 5))))))", &env),
+            "11"
+        );
+    });
+}
+
+#[test]
+fn testing_continuation_errors() {
+    common::for_each_backend(|env| {
+        assert_eq!(
+            mal_rust::rep("(shift k 1)", &env),
+            "Error: 'shift' called outside of a reset"
+        );
+        assert_eq!(
+            mal_rust::rep("(shift 1 1)", &env),
+            "Error: Type error: shift continuation name must be a symbol"
+        );
+        assert_eq!(
+            mal_rust::rep("(call/cc 1)", &env),
+            "Error: Type error: first argument must be a function"
+        );
+        assert_eq!(
+            mal_rust::rep("((call/cc (fn* (k) k)) 1 2)", &env),
+            "Error: Type error: a continuation takes zero or one argument"
+        );
+        mal_rust::rep("(def! saved (atom nil))", &env);
+        mal_rust::rep(
+            "(map (fn* (x) (call/cc (fn* (k) (reset! saved k)))) [1])",
+            &env,
+        );
+        assert_eq!(
+            mal_rust::rep("(@saved 1)", &env),
+            "Error: continuation called after its evaluation returned"
+        );
+        assert_eq!(mal_rust::rep("(+ 1 2)", &env), "3");
+    });
+
+    // the tree-walking backend doesn't resume continuations
+    let env = common::new_env(Backend::TreeWalker);
+    mal_rust::rep("(def! saved (atom nil))", &env);
+    assert_eq!(
+        mal_rust::rep("(+ 1 (call/cc (fn* (k) (do (reset! saved k) 1))))", &env),
+        "2"
+    );
+    assert_eq!(
+        mal_rust::rep("(@saved 2)", &env),
+        "Error: continuation called after its evaluation returned"
+    );
+    mal_rust::rep(
+        "(def! count-to (fn* (n) (let* [i (atom 0) k (call/cc (fn* (c) c))] (do (swap! i + 1) (if (< @i n) (k k) @i)))))",
+        &env,
+    );
+    assert_eq!(
+        mal_rust::rep("(count-to 5)", &env),
+        "Error: continuation called after its evaluation returned"
+    );
+}
@@ -0,0 +1,169 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use mal_rust::Debugger;
+
+mod common;
+
+/// Debugger running a script of commands and recording the prompts
+/// and the output
+struct Script {
+    commands: VecDeque<String>,
+    transcript: Rc<RefCell<Vec<String>>>,
+}
+
+impl Debugger for Script {
+    fn read_line(&mut self, prompt: &str) -> Option<String> {
+        let command = self.commands.pop_front()?;
+        self.transcript
+            .borrow_mut()
+            .push(format!("{}{}", prompt, command));
+        Some(command)
+    }
+
+    fn print(&mut self, line: &str) {
+        self.transcript.borrow_mut().push(line.to_string());
+    }
+}
+
+/// Install a debugger running commands and return its transcript
+fn debug_with(commands: &[&str]) -> Rc<RefCell<Vec<String>>> {
+    let transcript = Rc::new(RefCell::new(vec![]));
+    mal_rust::set_debugger(Some(Box::new(Script {
+        commands: commands.iter().map(|command| command.to_string()).collect(),
+        transcript: transcript.clone(),
+    })));
+    transcript
+}
+
+const PROGRAM: &[&str] = &[
+    "(def! square (fn* (x) (* x x)))",
+    "(def! f (fn* (a b) (let* [c (+ a b)] (do (break) (+ 1 (square c))))))",
+];
+
+#[test]
+fn testing_break_without_debugger() {
+    common::for_each_backend(|env| {
+        mal_rust::set_debugger(None);
+        assert_eq!(mal_rust::rep("(break)", &env), "nil");
+    });
+}
+
+#[test]
+fn testing_break_inspects_and_sets_locals() {
+    common::for_each_backend(|env| {
+        for form in PROGRAM {
+            mal_rust::rep(form, &env);
+        }
+
+        let transcript = debug_with(&[":locals", "(* c 10)", ":set c 4", ":continue"]);
+        assert_eq!(mal_rust::rep("(f 1 2)", &env), "17");
+        assert_eq!(
+            *transcript.borrow(),
+            [
+                "break in f",
+                "debug[f]> :locals",
+                "c = 3",
+                "a = 1",
+                "b = 2",
+                "debug[f]> (* c 10)",
+                "30",
+                "debug[f]> :set c 4",
+                "debug[f]> :continue",
+            ]
+        );
+    });
+}
+
+#[test]
+fn testing_stepping() {
+    common::for_each_backend(|env| {
+        for form in PROGRAM {
+            mal_rust::rep(form, &env);
+        }
+
+        let transcript = debug_with(&[":step", ":step", ":step", ":continue"]);
+        assert_eq!(mal_rust::rep("(f 1 2)", &env), "10");
+        assert_eq!(
+            *transcript.borrow(),
+            [
+                "break in f",
+                "debug[f]> :step",
+                "call (square 3)",
+                "debug[square]> :step",
+                "square returned 9",
+                "debug[square]> :step",
+                "f returned 10",
+                "debug[f]> :continue",
+            ]
+        );
+
+        // over the calls made by f, and out of it
+        mal_rust::rep("(def! g (fn* () (do (break) (f 1 1) (f 2 2))))", &env);
+        let transcript = debug_with(&[":next", ":next", ":c", ":out", ":c"]);
+        assert_eq!(mal_rust::rep("(g)", &env), "17");
+        assert_eq!(
+            *transcript.borrow(),
+            [
+                "break in g",
+                "debug[g]> :next",
+                // breaks stop even in the calls stepped over
+                "break in f",
+                "debug[f]> :next",
+                "f returned 5",
+                "debug[f]> :c",
+                "break in f",
+                "debug[f]> :out",
+                "f returned 17",
+                "debug[f]> :c",
+            ]
+        );
+    });
+}
+
+#[test]
+fn testing_debug_on_exception() {
+    common::for_each_backend(|env| {
+        mal_rust::rep("(def! inner (fn* (x) (+ x \"a\")))", &env);
+        mal_rust::rep("(def! outer (fn* (y) (+ 1 (inner y))))", &env);
+        mal_rust::set_debug_on_exception(true);
+
+        let transcript = debug_with(&[":where", "x", ":up", "y", ":down", ":c"]);
+        assert_eq!(
+            mal_rust::rep("(outer 2)", &env),
+            "Error: Type error: + only accepts numeric types"
+        );
+        assert_eq!(
+            *transcript.borrow(),
+            [
+                "exception in inner: Type error: + only accepts numeric types",
+                "debug[inner]> :where",
+                "> inner",
+                "  outer",
+                "debug[inner]> x",
+                "2",
+                "debug[inner]> :up",
+                "debug[outer]> y",
+                "2",
+                "debug[outer]> :down",
+                "debug[inner]> :c",
+            ]
+        );
+
+        // caught exceptions aren't debugged
+        let transcript = debug_with(&[]);
+        assert_eq!(
+            mal_rust::rep("(try* (outer 2) (catch* e :caught))", &env),
+            ":caught"
+        );
+        assert!(transcript.borrow().is_empty());
+
+        mal_rust::set_debug_on_exception(false);
+        assert_eq!(
+            mal_rust::rep("(outer 2)", &env),
+            "Error: Type error: + only accepts numeric types"
+        );
+        assert!(transcript.borrow().is_empty());
+    });
+}
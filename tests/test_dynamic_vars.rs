@@ -0,0 +1,111 @@
+use std::rc::Rc;
+
+use mal_rust::env::Env;
+
+#[test]
+fn testing_binding_earmuffed_vars() {
+    let env = Rc::new(Env::new_default());
+
+    mal_rust::rep("(def! *level* 1)", &env);
+    mal_rust::rep("(def! get-level (fn* () *level*))", &env);
+
+    assert_eq!(
+        mal_rust::rep("(binding [*level* 2] (get-level))", &env),
+        "2"
+    );
+    assert_eq!(mal_rust::rep("(get-level)", &env), "1");
+    assert_eq!(
+        mal_rust::rep(
+            "(binding [*level* 2] (list (binding [*level* 3] (get-level)) (get-level)))",
+            &env
+        ),
+        "(3 2)"
+    );
+    assert_eq!(mal_rust::rep("*level*", &env), "1");
+
+    // the body is an implicit do
+    mal_rust::rep("(def! levels (atom []))", &env);
+    assert_eq!(
+        mal_rust::rep(
+            "(binding [*level* 2] (swap! levels conj (get-level)) (get-level))",
+            &env
+        ),
+        "2"
+    );
+    assert_eq!(mal_rust::rep("@levels", &env), "[2]");
+    assert_eq!(
+        mal_rust::rep("(binding [*level* 2])", &env),
+        "Error: Type error: 'binding' takes a binding vector and a body"
+    );
+}
+
+#[test]
+fn testing_dynamic_metadata() {
+    let env = Rc::new(Env::new_default());
+
+    mal_rust::rep("(def! ^:dynamic depth 0)", &env);
+    mal_rust::rep("(def! ^{:dynamic true} width 0)", &env);
+    mal_rust::rep("(def! height 0)", &env);
+
+    assert_eq!(
+        mal_rust::rep("(binding [depth 1 width 2] (+ depth width))", &env),
+        "3"
+    );
+    assert_eq!(mal_rust::rep("(list depth width)", &env), "(0 0)");
+    assert_eq!(
+        mal_rust::rep("(binding [height 1] height)", &env),
+        "Error: can't dynamically bind non-dynamic var 'height'"
+    );
+    assert_eq!(
+        mal_rust::rep("(let* (x 1) (binding [x 2] x))", &env),
+        "Error: can't dynamically bind local 'x'"
+    );
+    assert_eq!(
+        mal_rust::rep("(binding [*missing* 1] 1)", &env),
+        "Error: symbol '*missing*' not found"
+    );
+
+    // redefining a var without metadata makes it static again
+    mal_rust::rep("(def! depth 0)", &env);
+    assert_eq!(
+        mal_rust::rep("(binding [depth 1] depth)", &env),
+        "Error: can't dynamically bind non-dynamic var 'depth'"
+    );
+}
+
+#[test]
+fn testing_binding_is_restored_on_exceptions() {
+    let env = Rc::new(Env::new_default());
+
+    mal_rust::rep("(def! *mode* :normal)", &env);
+    mal_rust::rep("(def! fail (fn* () (throw *mode*)))", &env);
+
+    assert_eq!(
+        mal_rust::rep(
+            "(try* (binding [*mode* :testing] (fail)) (catch* e (list e *mode*)))",
+            &env
+        ),
+        "(:testing :normal)"
+    );
+    assert_eq!(
+        mal_rust::rep("(binding [*mode* :testing] (nth [] 1))", &env),
+        "Error: Index error: index 1 is out of bounds for sequence of length 0"
+    );
+    assert_eq!(mal_rust::rep("*mode*", &env), ":normal");
+}
+
+#[test]
+fn testing_binding_vars_of_other_namespaces() {
+    let env = Rc::new(Env::new_default());
+
+    mal_rust::rep("(ns config)", &env);
+    mal_rust::rep("(def! *verbose* false)", &env);
+    mal_rust::rep("(def! verbose? (fn* () *verbose*))", &env);
+    mal_rust::rep("(ns app (:require [config :as c]))", &env);
+
+    assert_eq!(
+        mal_rust::rep("(binding [c/*verbose* true] (c/verbose?))", &env),
+        "true"
+    );
+    assert_eq!(mal_rust::rep("(c/verbose?)", &env), "false");
+}
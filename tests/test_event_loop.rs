@@ -0,0 +1,123 @@
+use std::rc::Rc;
+
+use mal_rust::env::Env;
+
+mod common;
+
+/// Define the functions the tests use
+fn setup(env: &Rc<Env>) {
+    mal_rust::rep("(def! log (atom []))", env);
+}
+
+#[test]
+fn testing_timers() {
+    common::for_each_backend(|env| {
+        setup(&env);
+        mal_rust::rep("(set-timeout (fn* () (swap! log conj :late)) 30)", &env);
+        mal_rust::rep("(set-timeout (fn* () (swap! log conj :early)) 10)", &env);
+        mal_rust::rep(
+            "(def! id (set-interval (fn* () (do (swap! log conj :tick) (if (= (count @log) 4) (clear-timer id) nil))) 5))",
+            &env,
+        );
+        let cancelled = mal_rust::rep("(set-timeout (fn* () (swap! log conj :never)) 1)", &env);
+        assert_eq!(
+            mal_rust::rep(&format!("(clear-timer {})", cancelled), &env),
+            "true"
+        );
+        assert_eq!(
+            mal_rust::rep(&format!("(clear-timer {})", cancelled), &env),
+            "false"
+        );
+
+        // nothing runs until the loop does
+        assert_eq!(mal_rust::rep("@log", &env), "[]");
+        assert_eq!(mal_rust::run_event_loop(), None);
+        let log = mal_rust::rep("@log", &env);
+        assert!(log.starts_with("[:tick"), "{}", log);
+        assert!(log.ends_with(":late]"), "{}", log);
+        assert_eq!(mal_rust::rep("(count @log)", &env), "5");
+    });
+}
+
+#[test]
+fn testing_promises() {
+    common::for_each_backend(|env| {
+        setup(&env);
+        mal_rust::rep("(def! p (promise))", &env);
+        assert_eq!(mal_rust::rep("(promise? p)", &env), "true");
+        assert_eq!(mal_rust::rep("p", &env), "#<promise>");
+        assert_eq!(mal_rust::rep("(realized? p)", &env), "false");
+        mal_rust::rep("(set-timeout (fn* () (deliver p 42)) 5)", &env);
+        // deref waits for the timer
+        assert_eq!(mal_rust::rep("@p", &env), "42");
+        assert_eq!(mal_rust::rep("(realized? p)", &env), "true");
+        assert_eq!(mal_rust::rep("(deliver p 43)", &env), "false");
+        assert_eq!(mal_rust::rep("(deref p)", &env), "42");
+
+        assert_eq!(
+            mal_rust::rep("@(then (future (fn* () 1)) (fn* (x) (+ x 1)))", &env),
+            "2"
+        );
+        // a promise returned by a callback is waited for
+        assert_eq!(
+            mal_rust::rep(
+                "@(then (future (fn* () 2)) (fn* (x) (future (fn* () (* x 10)))))",
+                &env
+            ),
+            "20"
+        );
+        mal_rust::rep("(def! q (promise))", &env);
+        mal_rust::rep("(def! r (promise))", &env);
+        mal_rust::rep("(deliver q r)", &env);
+        mal_rust::rep("(deliver r :follows)", &env);
+        assert_eq!(mal_rust::rep("@q", &env), ":follows");
+    });
+}
+
+#[test]
+fn testing_promise_exceptions() {
+    common::for_each_backend(|env| {
+        setup(&env);
+        // exceptions of the callbacks are raised by deref
+        mal_rust::rep("(def! p (future (fn* () (throw \"bad\"))))", &env);
+        mal_rust::rep("(def! q (then p (fn* (x) (swap! log conj x))))", &env);
+        assert_eq!(mal_rust::rep("(try* @q (catch* e e))", &env), "\"bad\"");
+        assert_eq!(mal_rust::rep("(try* @p (catch* e e))", &env), "\"bad\"");
+        assert_eq!(mal_rust::rep("@log", &env), "[]");
+
+        assert_eq!(
+            mal_rust::rep("@(promise)", &env),
+            "Error: deref of a promise no task can deliver"
+        );
+        assert_eq!(
+            mal_rust::rep("(set-timeout 1 10)", &env),
+            "Error: Type error: first argument to 'set-timeout' must be a function"
+        );
+        assert_eq!(
+            mal_rust::rep("(set-interval (fn* () nil) -1)", &env),
+            "Error: Type error: second argument to 'set-interval' must be a non-negative integer"
+        );
+        assert_eq!(
+            mal_rust::rep("(then 1 (fn* (x) x))", &env),
+            "Error: Type error: first argument to 'then' must be a promise"
+        );
+    });
+}
+
+#[test]
+fn testing_event_loop_errors() {
+    common::for_each_backend(|env| {
+        setup(&env);
+        mal_rust::rep("(set-timeout (fn* () (throw \"in timer\")) 1)", &env);
+        mal_rust::rep("(set-timeout (fn* () (swap! log conj :after)) 5)", &env);
+
+        // the loop stops at an exception, the other tasks are run next
+        assert_eq!(
+            mal_rust::run_event_loop(),
+            Some(String::from("Error: in timer"))
+        );
+        assert_eq!(mal_rust::rep("@log", &env), "[]");
+        assert_eq!(mal_rust::run_event_loop(), None);
+        assert_eq!(mal_rust::rep("@log", &env), "[:after]");
+    });
+}
@@ -0,0 +1,74 @@
+mod common;
+
+#[test]
+fn testing_builtin_exception_kinds() {
+    common::for_each_backend(|env| {
+        mal_rust::rep(
+            "(def! kind (fn* (f) (try* (f) (catch* e (get e :kind)))))",
+            &env,
+        );
+        let cases = [
+            ("(+ 1 \"a\")", ":type-error"),
+            ("(nth [1] 3)", ":index-error"),
+            ("(count 1 2)", ":arity-error"),
+            ("(slurp \"no/such/file\")", ":io-error"),
+            ("undefined-symbol", ":symbol-error"),
+            ("(read-string \"(1 2\")", ":reader-error"),
+            ("(throw {:kind :io-error})", ":io-error"),
+        ];
+        for (code, kind) in cases {
+            assert_eq!(
+                mal_rust::rep(&format!("(kind (fn* () {}))", code), &env),
+                kind,
+                "{}",
+                code
+            );
+        }
+        assert_eq!(
+            mal_rust::rep("(try* (nth [] 0) (catch* e (ex-message e)))", &env),
+            "\"Index error: index 0 is out of bounds for sequence of length 0\""
+        );
+
+        // a rethrown builtin exception keeps its kind
+        assert_eq!(
+            mal_rust::rep(
+                "(try* (try* (count 1 2) (catch* e (throw e))) (catch* :arity-error e :arity))",
+                &env
+            ),
+            ":arity"
+        );
+    });
+}
+
+#[test]
+fn testing_ex_info() {
+    common::for_each_backend(|env| {
+        mal_rust::rep(
+            "(def! fetch (fn* (id) (try* (nth [] id)
+               (catch* e (throw (ex-info \"fetch failed\" {:id id} e))))))",
+            &env,
+        );
+        mal_rust::rep("(def! e (try* (fetch 3) (catch* e e)))", &env);
+        assert_eq!(mal_rust::rep("(ex-message e)", &env), "\"fetch failed\"");
+        assert_eq!(mal_rust::rep("(ex-data e)", &env), "{:id 3}");
+        assert_eq!(
+            mal_rust::rep("(get (ex-cause e) :kind)", &env),
+            ":index-error"
+        );
+        assert_eq!(mal_rust::rep("(ex-cause (ex-cause e))", &env), "nil");
+
+        assert_eq!(
+            mal_rust::rep("(ex-data (ex-info \"no cause\" {}))", &env),
+            "{}"
+        );
+        assert_eq!(mal_rust::rep("(ex-message \"text\")", &env), "nil");
+        assert_eq!(
+            mal_rust::rep("(ex-info \"bad\" 1)", &env),
+            "Error: Type error: 'ex-info' data must be a hash map"
+        );
+        assert_eq!(
+            mal_rust::rep("(ex-info \"bad\")", &env),
+            "Error: Type error: 'ex-info' takes two or three arguments"
+        );
+    });
+}
@@ -0,0 +1,174 @@
+use std::rc::Rc;
+
+use mal_rust::env::Env;
+
+mod common;
+
+/// Define the functions the tests use
+fn setup(env: &Rc<Env>) {
+    mal_rust::rep(
+        "(def! naturals (fn* () (loop [n 0] (do (yield n) (recur (+ n 1))))))",
+        env,
+    );
+    mal_rust::rep(
+        "(def! abc (fn* () (do (yield :a) (yield :b) (yield :c))))",
+        env,
+    );
+}
+
+#[test]
+fn testing_next() {
+    common::for_each_backend(|env| {
+        setup(&env);
+        mal_rust::rep("(def! g (generator abc))", &env);
+        assert_eq!(mal_rust::rep("(generator? g)", &env), "true");
+        assert_eq!(mal_rust::rep("g", &env), "#<generator>");
+        assert_eq!(mal_rust::rep("(next! g)", &env), ":a");
+        assert_eq!(mal_rust::rep("(next! g)", &env), ":b");
+        assert_eq!(mal_rust::rep("(next! g)", &env), ":c");
+        assert_eq!(mal_rust::rep("(next! g)", &env), "nil");
+        assert_eq!(mal_rust::rep("(next! g)", &env), "nil");
+
+        // the body runs only when values are needed
+        mal_rust::rep("(def! log (atom []))", &env);
+        mal_rust::rep(
+            "(def! g (generator (fn* () (do (swap! log conj 1) (yield 1) (swap! log conj 2)))))",
+            &env,
+        );
+        assert_eq!(mal_rust::rep("@log", &env), "[]");
+        assert_eq!(mal_rust::rep("(next! g)", &env), "1");
+        assert_eq!(mal_rust::rep("@log", &env), "[1]");
+        assert_eq!(mal_rust::rep("(next! g)", &env), "nil");
+        assert_eq!(mal_rust::rep("@log", &env), "[1 2]");
+    });
+}
+
+#[test]
+fn testing_first_rest_seq() {
+    common::for_each_backend(|env| {
+        setup(&env);
+        mal_rust::rep("(def! g (generator naturals))", &env);
+        assert_eq!(mal_rust::rep("(first g)", &env), "0");
+        assert_eq!(mal_rust::rep("(first (rest (rest g)))", &env), "2");
+        // first and rest don't move the generator
+        assert_eq!(mal_rust::rep("(first g)", &env), "0");
+        assert_eq!(mal_rust::rep("(next! g)", &env), "0");
+        assert_eq!(mal_rust::rep("(first g)", &env), "1");
+        assert_eq!(mal_rust::rep("(nth g 10000)", &env), "10001");
+
+        mal_rust::rep(
+            "(def! collect (fn* (s) (loop [s (seq s) acc []] (if s (recur (seq (rest s)) (conj acc (first s))) acc))))",
+            &env,
+        );
+        assert_eq!(
+            mal_rust::rep("(collect (generator abc))", &env),
+            "[:a :b :c]"
+        );
+        assert_eq!(mal_rust::rep("(seq (generator (fn* () nil)))", &env), "nil");
+        assert_eq!(
+            mal_rust::rep("(empty? (generator (fn* () nil)))", &env),
+            "true"
+        );
+    });
+}
+
+#[test]
+fn testing_sequence_functions() {
+    common::for_each_backend(|env| {
+        setup(&env);
+        mal_rust::rep("(def! g (generator abc))", &env);
+        assert_eq!(mal_rust::rep("(count g)", &env), "3");
+        assert_eq!(mal_rust::rep("(vec g)", &env), "[:a :b :c]");
+        assert_eq!(mal_rust::rep("(cons :z g)", &env), "(:z :a :b :c)");
+        assert_eq!(mal_rust::rep("(concat g [:d])", &env), "(:a :b :c :d)");
+        assert_eq!(mal_rust::rep("(map keyword? g)", &env), "(true true true)");
+        assert_eq!(mal_rust::rep("(apply list :z g)", &env), "(:z :a :b :c)");
+        assert_eq!(mal_rust::rep("(nth g 1)", &env), ":b");
+        assert_eq!(
+            mal_rust::rep("(nth g 3)", &env),
+            "Error: Index error: index 3 is out of bounds for sequence of length 3"
+        );
+        assert_eq!(mal_rust::rep("(sequential? g)", &env), "true");
+        assert_eq!(mal_rust::rep("(= g g)", &env), "true");
+    });
+}
+
+#[test]
+fn testing_yield_in_called_functions() {
+    common::for_each_backend(|env| {
+        setup(&env);
+        mal_rust::rep("(def! twice (fn* (x) (do (yield x) (yield x))))", &env);
+        assert_eq!(
+            mal_rust::rep("(vec (generator (fn* () (do (twice 1) (twice 2)))))", &env),
+            "[1 1 2 2]"
+        );
+
+        // exceptions are caught across yields
+        assert_eq!(
+            mal_rust::rep(
+                "(vec (generator (fn* () (try* (do (yield 1) (throw \"boom\")) (catch* e (yield e))))))",
+                &env
+            ),
+            "[1 \"boom\"]"
+        );
+    });
+}
+
+#[test]
+fn testing_generator_errors() {
+    common::for_each_backend(|env| {
+        setup(&env);
+        assert_eq!(
+            mal_rust::rep("(yield 1)", &env),
+            "Error: 'yield' called outside of a generator"
+        );
+        // builtins calling back can't be suspended
+        assert_eq!(
+            mal_rust::rep(
+                "(first (generator (fn* () (map (fn* (x) (yield x)) [1]))))",
+                &env
+            ),
+            "Error: 'yield' called from a function called back by a builtin"
+        );
+        mal_rust::rep(
+            "(def! g (generator (fn* [] (map (fn* [x] (yield x)) [7 8 9]))))",
+            &env,
+        );
+        assert_eq!(
+            mal_rust::rep("(next! g)", &env),
+            "Error: 'yield' called from a function called back by a builtin"
+        );
+        assert_eq!(mal_rust::rep("(next! g)", &env), "nil");
+        assert_eq!(
+            mal_rust::rep("(map (fn* (x) (yield x)) [1])", &env),
+            "Error: 'yield' called outside of a generator"
+        );
+        assert_eq!(
+            mal_rust::rep("(generator 1)", &env),
+            "Error: Type error: 'generator' argument must be a function"
+        );
+        assert_eq!(
+            mal_rust::rep("(next! [1])", &env),
+            "Error: Type error: 'next!' argument must be a generator"
+        );
+
+        mal_rust::rep("(def! g (generator (fn* () (yield (next! g)))))", &env);
+        assert_eq!(
+            mal_rust::rep("(next! g)", &env),
+            "Error: generator is already running"
+        );
+
+        // a generator raising an exception has no more values
+        mal_rust::rep(
+            "(def! g (generator (fn* () (do (yield 1) (throw \"failed\")))))",
+            &env,
+        );
+        assert_eq!(mal_rust::rep("(next! g)", &env), "1");
+        assert_eq!(
+            mal_rust::rep("(try* (next! g) (catch* e e))", &env),
+            "\"failed\""
+        );
+        assert_eq!(mal_rust::rep("(next! g)", &env), "nil");
+        assert_eq!(mal_rust::rep("(+ 1 2)", &env), "3");
+    });
+}
@@ -0,0 +1,38 @@
+mod common;
+
+#[test]
+fn testing_gensym() {
+    common::for_each_backend(|env| {
+        assert_eq!(mal_rust::rep("(symbol? (gensym))", &env), "true");
+        assert_eq!(mal_rust::rep("(= (gensym) (gensym))", &env), "false");
+        mal_rust::rep("(def! s (gensym \"tmp\"))", &env);
+        assert!(mal_rust::rep("s", &env).starts_with("tmp"));
+        assert_eq!(mal_rust::rep("(not (= s 'tmp))", &env), "true");
+        assert_eq!(
+            mal_rust::rep("(gensym 1)", &env),
+            "Error: Type error: gensym only accepts string type"
+        );
+    });
+}
+
+#[test]
+fn testing_auto_gensym() {
+    common::for_each_backend(|env| {
+        // the same symbol everywhere in a template, a new one in the
+        // next template
+        mal_rust::rep("(def! t `(x# x# y#))", &env);
+        assert_eq!(mal_rust::rep("(= (nth t 0) (nth t 1))", &env), "true");
+        assert_eq!(mal_rust::rep("(= (nth t 0) (nth t 2))", &env), "false");
+        assert_eq!(mal_rust::rep("(= (nth t 0) 'x)", &env), "false");
+        assert_eq!(mal_rust::rep("(= (nth t 0) (nth `(x#) 0))", &env), "false");
+        assert_eq!(mal_rust::rep("`#", &env), "#");
+
+        // the locals of a macro don't capture the ones of the caller
+        mal_rust::rep(
+            "(defmacro! my-or (fn* (a b) `(let* [v# ~a] (if v# v# ~b))))",
+            &env,
+        );
+        assert_eq!(mal_rust::rep("(let* [v 5] (my-or false v))", &env), "5");
+        assert_eq!(mal_rust::rep("(my-or 1 2)", &env), "1");
+    });
+}
@@ -0,0 +1,145 @@
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use mal_rust::env::Env;
+
+fn image_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("mal-rust-{}-{}.img", name, std::process::id()))
+}
+
+fn new_env() -> Rc<Env> {
+    let env = Rc::new(Env::new_default());
+    mal_rust::load_core(&env);
+    env
+}
+
+#[test]
+fn testing_image_round_trip() {
+    let env = new_env();
+    let path = image_path("round-trip");
+    let path = path.to_str().unwrap();
+
+    mal_rust::rep("(def! answer 42)", &env);
+    mal_rust::rep("(def! counter (atom 0))", &env);
+    mal_rust::rep("(def! inc! (fn* () (swap! counter + 1)))", &env);
+    mal_rust::rep("(defmacro! unless (fn* (c a b) `(if ~c ~b ~a)))", &env);
+    mal_rust::rep("(inc!)", &env);
+    mal_rust::save_image(path, &env).unwrap();
+
+    let restored = mal_rust::load_image(path).unwrap();
+    fs::remove_file(path).unwrap();
+
+    assert_eq!(mal_rust::rep("answer", &restored), "42");
+    assert_eq!(mal_rust::rep("(inc!)", &restored), "2");
+    assert_eq!(mal_rust::rep("@counter", &restored), "2");
+    assert_eq!(mal_rust::rep("(unless false :yes :no)", &restored), ":yes");
+    // core.mal definitions are restored without reloading them
+    assert_eq!(mal_rust::rep("(cond false 1 :else 2)", &restored), "2");
+
+    // the original interpreter isn't affected
+    assert_eq!(mal_rust::rep("@counter", &env), "1");
+}
+
+#[test]
+fn testing_image_keeps_shared_state() {
+    let env = new_env();
+    let path = image_path("shared");
+    let path = path.to_str().unwrap();
+
+    mal_rust::rep(
+        "(def! make-counter (fn* () (let* (n (atom 0)) [(fn* () (swap! n + 1)) (fn* () @n)])))",
+        &env,
+    );
+    mal_rust::rep("(def! c (make-counter))", &env);
+    mal_rust::rep(
+        "(def! fact (fn* (n) (if (< n 2) 1 (* n (fact (- n 1))))))",
+        &env,
+    );
+    mal_rust::save_image(path, &env).unwrap();
+
+    let restored = mal_rust::load_image(path).unwrap();
+    fs::remove_file(path).unwrap();
+
+    mal_rust::rep("((nth c 0))", &restored);
+    mal_rust::rep("((nth c 0))", &restored);
+    assert_eq!(mal_rust::rep("((nth c 1))", &restored), "2");
+    assert_eq!(mal_rust::rep("(fact 5)", &restored), "120");
+}
+
+#[test]
+fn testing_image_keeps_namespaces() {
+    let env = new_env();
+    let path = image_path("namespaces");
+
+    mal_rust::rep("(ns lib)", &env);
+    mal_rust::rep("(def! ^:dynamic depth 1)", &env);
+    mal_rust::rep("(def! greet (fn* (name) (str \"hi \" name)))", &env);
+    mal_rust::rep("(ns app (:require [lib :as l :refer [greet]]))", &env);
+    mal_rust::rep(&format!("(save-image {:?})", path.to_str().unwrap()), &env);
+
+    let restored = mal_rust::load_image(path.to_str().unwrap()).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(mal_rust::rep("(greet \"you\")", &restored), "\"hi you\"");
+    assert_eq!(
+        mal_rust::rep("(binding [l/depth 2] l/depth)", &restored),
+        "2"
+    );
+    assert_eq!(mal_rust::rep("(resolve 'greet)", &restored), "lib/greet");
+}
+
+#[test]
+fn testing_invalid_images_are_rejected() {
+    let path = image_path("invalid");
+    let path_str = path.to_str().unwrap();
+
+    fs::write(&path, "(def! a 1)").unwrap();
+    assert_eq!(
+        mal_rust::load_image(path_str).unwrap_err(),
+        format!("can't load image '{}': not a mal-rust image", path_str)
+    );
+
+    let mut stale = b"MALIMAGE".to_vec();
+    stale.extend(0u32.to_le_bytes());
+    fs::write(&path, stale).unwrap();
+    assert!(mal_rust::load_image(path_str)
+        .unwrap_err()
+        .contains("stale image saved by mal-rust unknown (image format 0)"));
+
+    let env = new_env();
+    mal_rust::save_image(path_str, &env).unwrap();
+    let image = fs::read(&path).unwrap();
+    fs::write(&path, &image[..image.len() / 2]).unwrap();
+    assert_eq!(
+        mal_rust::load_image(path_str).unwrap_err(),
+        format!("can't load image '{}': corrupted image", path_str)
+    );
+
+    fs::remove_file(&path).unwrap();
+    assert!(mal_rust::load_image(path_str)
+        .unwrap_err()
+        .starts_with(&format!("can't load image '{}': ", path_str)));
+}
+
+#[test]
+fn testing_unsaved_values_are_rejected() {
+    let path = image_path("unsaved");
+    let path_str = path.to_str().unwrap();
+
+    for (definition, printed) in [
+        ("(def! p (promise))", "#<promise>"),
+        ("(def! p (atom [(generator (fn* () 1))]))", "#<generator>"),
+        ("(def! p {:k (call/cc (fn* (k) k))})", "#<continuation>"),
+    ] {
+        let env = new_env();
+        mal_rust::rep(definition, &env);
+        let message = format!("can't save {} in an image (found in 'p')", printed);
+        assert_eq!(mal_rust::save_image(path_str, &env).unwrap_err(), message);
+        assert_eq!(
+            mal_rust::rep(&format!("(save-image {:?})", path_str), &env),
+            format!("Error: {}", message)
+        );
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,81 @@
+use std::thread;
+use std::time::Duration;
+
+mod common;
+
+// the interrupt flag is shared by the threads, so a single test uses it
+#[test]
+fn testing_interrupt() {
+    common::for_each_backend(|env| {
+        mal_rust::rep("(def! f (fn* (n) (f (+ n 1))))", &env);
+
+        let interrupter = thread::spawn(|| {
+            thread::sleep(Duration::from_millis(50));
+            mal_rust::interrupt();
+        });
+        assert_eq!(mal_rust::rep("(f 0)", &env), "Error: Interrupted");
+        interrupter.join().unwrap();
+
+        // catch-all clauses don't catch it, loops catching every
+        // exception are interrupted too
+        mal_rust::rep("(def! finally-runs (atom 0))", &env);
+        for clause in [
+            "(catch* e nil)",
+            "(catch* :default e nil)",
+            "(catch* :error e nil)",
+            "(catch* map? e nil)",
+        ] {
+            let interrupter = thread::spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                mal_rust::interrupt();
+            });
+            let code = format!(
+                "(loop [] (do (try* (f 0) {} (finally (swap! finally-runs + 1))) (recur)))",
+                clause
+            );
+            assert_eq!(
+                mal_rust::rep(&code, &env),
+                "Error: Interrupted",
+                "{}",
+                clause
+            );
+            interrupter.join().unwrap();
+        }
+        assert_eq!(mal_rust::rep("@finally-runs", &env), "4");
+
+        // clauses selecting its kind catch it
+        let interrupter = thread::spawn(|| {
+            thread::sleep(Duration::from_millis(50));
+            mal_rust::interrupt();
+        });
+        assert_eq!(
+            mal_rust::rep("(try* (f 0) (catch* :interrupt e (ex-message e)))", &env),
+            "\"Interrupted\""
+        );
+        interrupter.join().unwrap();
+
+        // and so are builtins calling builtins
+        mal_rust::rep(
+            "(do (def! big (loop [v [1] n 20] (if (= n 0) v (recur (concat v v) (- n 1))))) nil)",
+            &env,
+        );
+        let interrupter = thread::spawn(|| {
+            thread::sleep(Duration::from_millis(50));
+            mal_rust::interrupt();
+        });
+        assert_eq!(
+            mal_rust::rep("(count (map list big))", &env),
+            "Error: Interrupted"
+        );
+        interrupter.join().unwrap();
+
+        // an interrupt left pending by the previous input, like a
+        // Ctrl-C after its last call, doesn't abort the next one
+        mal_rust::interrupt();
+        assert_eq!(mal_rust::rep("((fn* () 1))", &env), "1");
+        mal_rust::rep("(def! app-loads (atom 0))", &env);
+        mal_rust::interrupt();
+        assert_eq!(mal_rust::run_file("tests/modules/main.mal", &env), "nil");
+        assert_eq!(mal_rust::rep("@app-loads", &env), "1");
+    });
+}
@@ -0,0 +1,89 @@
+use std::rc::Rc;
+
+use mal_rust::env::Env;
+use mal_rust::symbol::Symbol;
+
+#[test]
+fn testing_ns_publics() {
+    let env = Rc::new(Env::new_default());
+
+    mal_rust::rep("(ns lib)", &env);
+    mal_rust::rep("(def! a 1)", &env);
+    mal_rust::rep("(def! b 2)", &env);
+    mal_rust::rep("(ns user)", &env);
+
+    assert_eq!(mal_rust::rep("(get (ns-publics 'lib) \"a\")", &env), "1");
+    assert_eq!(
+        mal_rust::rep("(count (keys (ns-publics \"lib\")))", &env),
+        "2"
+    );
+    assert_eq!(
+        mal_rust::rep("(ns-publics 'missing)", &env),
+        "Error: namespace 'missing' not found"
+    );
+}
+
+#[test]
+fn testing_resolve_and_bound() {
+    let env = Rc::new(Env::new_default());
+
+    mal_rust::rep("(ns lib)", &env);
+    mal_rust::rep("(def! a 1)", &env);
+    mal_rust::rep("(ns app (:require [lib :as l :refer [a]]))", &env);
+
+    assert_eq!(mal_rust::rep("(resolve 'a)", &env), "lib/a");
+    assert_eq!(mal_rust::rep("(resolve 'l/a)", &env), "lib/a");
+    assert_eq!(mal_rust::rep("(resolve '+)", &env), "mal.core/+");
+    assert_eq!(mal_rust::rep("(resolve 'nothing)", &env), "nil");
+    // local variables aren't namespace definitions
+    assert_eq!(mal_rust::rep("(let* (x 1) (resolve 'x))", &env), "nil");
+    assert_eq!(mal_rust::rep("(apply resolve ['l/a])", &env), "lib/a");
+
+    assert_eq!(mal_rust::rep("(bound? 'a)", &env), "true");
+    assert_eq!(mal_rust::rep("(bound? 'nothing)", &env), "false");
+    assert_eq!(mal_rust::rep("((fn* (y) (bound? 'y)) nil)", &env), "false");
+    assert_eq!(
+        mal_rust::rep("(map bound? '[a nothing])", &env),
+        "(true false)"
+    );
+}
+
+#[test]
+fn testing_ns_unmap() {
+    let env = Rc::new(Env::new_default());
+
+    mal_rust::rep("(ns lib)", &env);
+    mal_rust::rep("(def! a 1)", &env);
+    mal_rust::rep("(ns app (:require [lib :refer [a]]))", &env);
+
+    assert_eq!(mal_rust::rep("(ns-unmap 'app 'a)", &env), "nil");
+    assert_eq!(mal_rust::rep("(bound? 'a)", &env), "false");
+    assert_eq!(mal_rust::rep("(bound? 'lib/a)", &env), "true");
+    assert_eq!(mal_rust::rep("(ns-unmap 'lib 'a)", &env), "nil");
+    assert_eq!(mal_rust::rep("(bound? 'lib/a)", &env), "false");
+}
+
+#[test]
+fn testing_env_bindings_api() {
+    let env = Rc::new(Env::new_default());
+
+    mal_rust::rep("(ns lib)", &env);
+    mal_rust::rep("(def! b 2)", &env);
+    mal_rust::rep("(def! a 1)", &env);
+
+    let namespaces: Vec<String> = env.namespaces().into_iter().map(|(name, _)| name).collect();
+    assert_eq!(namespaces, vec!["lib", "mal.core", "user"]);
+
+    let (_, lib) = &env.namespaces()[0];
+    let bindings: Vec<String> = lib
+        .bindings()
+        .into_iter()
+        .map(|(symbol, value)| format!("{} {:?}", symbol, value))
+        .collect();
+    assert_eq!(bindings, vec!["a Int(1)", "b Int(2)"]);
+
+    let scopes = lib.scopes();
+    assert_eq!(scopes.len(), 2);
+    assert_eq!(scopes[1].namespace_name(), Some("mal.core".to_string()));
+    assert!(scopes[1].get_local(Symbol::new("+")).is_some());
+}
@@ -0,0 +1,66 @@
+mod common;
+
+#[test]
+fn testing_letfn() {
+    common::for_each_backend(|env| {
+        assert_eq!(
+            mal_rust::rep(
+                "(letfn [(even? (n) (if (= n 0) true (odd? (- n 1))))
+                         (odd? (n) (if (= n 0) false (even? (- n 1))))]
+                   [(even? 10) (odd? 7) (even? 3)])",
+                &env
+            ),
+            "[true true false]"
+        );
+        // the closures see the locals around the letfn and outlive it
+        mal_rust::rep(
+            "(def! counter (let* [start 10] (letfn [(f (n) (if (= n 0) start (g (- n 1)))) (g (n) (f n))] f)))",
+            &env,
+        );
+        assert_eq!(mal_rust::rep("(counter 3)", &env), "10");
+        assert_eq!(mal_rust::rep("(letfn [] 1)", &env), "1");
+    });
+}
+
+#[test]
+fn testing_malformed_letfn() {
+    common::for_each_backend(|env| {
+        assert_eq!(
+            mal_rust::rep("(letfn (f (x) x) 1)", &env),
+            "Error: letfn functions must be (name params body) lists"
+        );
+        assert_eq!(
+            mal_rust::rep("(letfn [(\"f\" (x) x)] 1)", &env),
+            "Error: letfn function names must be symbols"
+        );
+        assert_eq!(
+            mal_rust::rep("(letfn [(f x x)] 1)", &env),
+            "Error: fn* must be defined with a sequence as parameter"
+        );
+    });
+}
+
+#[test]
+fn testing_declare() {
+    common::for_each_backend(|env| {
+        assert_eq!(mal_rust::rep("(declare ping pong)", &env), "nil");
+        assert_eq!(mal_rust::rep("ping", &env), "nil");
+        mal_rust::rep(
+            "(def! ping (fn* (n) (if (= n 0) :ping (pong (- n 1)))))",
+            &env,
+        );
+        mal_rust::rep(
+            "(def! pong (fn* (n) (if (= n 0) :pong (ping (- n 1)))))",
+            &env,
+        );
+        assert_eq!(mal_rust::rep("(ping 3)", &env), ":pong");
+
+        // defined names keep their value
+        mal_rust::rep("(declare ping)", &env);
+        assert_eq!(mal_rust::rep("(ping 4)", &env), ":ping");
+        assert_eq!(
+            mal_rust::rep("(declare 1)", &env),
+            "Error: Type error: 'declare' arguments must be symbols"
+        );
+    });
+}
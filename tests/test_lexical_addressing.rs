@@ -0,0 +1,50 @@
+use std::rc::Rc;
+
+use mal_rust::env::Env;
+
+#[test]
+fn testing_closures_capture_local_variables() {
+    let env = Rc::new(Env::new_default());
+
+    mal_rust::rep(
+        "(def! adder (fn* (a) (let* (b 10) (fn* (c) (+ a (+ b c))))))",
+        &env,
+    );
+    assert_eq!(mal_rust::rep("((adder 1) 100)", &env), "111");
+    assert_eq!(
+        mal_rust::rep("(let* (x 1 y x x 2) (list x y))", &env),
+        "(2 1)"
+    );
+}
+
+#[test]
+fn testing_def_inside_a_function_shadows_captured_variables() {
+    let env = Rc::new(Env::new_default());
+
+    mal_rust::rep(
+        "(def! f (fn* (x) (let* (y 1) (do (def! x 5) (def! y 7) (list x y)))))",
+        &env,
+    );
+    assert_eq!(mal_rust::rep("(f 1)", &env), "(5 7)");
+    mal_rust::rep("(def! g (fn* (x) ((fn* () (do (def! x 2) x)))))", &env);
+    assert_eq!(mal_rust::rep("(g 1)", &env), "2");
+}
+
+#[test]
+fn testing_macros_defined_after_the_function_see_symbols() {
+    let env = Rc::new(Env::new_default());
+
+    mal_rust::rep("(def! f (fn* (x) (quoted x)))", &env);
+    mal_rust::rep("(defmacro! quoted (fn* (a) (list 'quote a)))", &env);
+    assert_eq!(mal_rust::rep("(f 1)", &env), "x");
+}
+
+#[test]
+fn testing_closures_print_their_source() {
+    let env = Rc::new(Env::new_default());
+
+    assert_eq!(
+        mal_rust::rep("(fn* (a & more) (let* (b a) (list b more)))", &env),
+        "(fn* (a & more) (let* (b a) (list b more)))"
+    );
+}
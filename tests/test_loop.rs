@@ -0,0 +1,86 @@
+mod common;
+
+#[test]
+fn testing_loop_and_recur() {
+    common::for_each_backend(|env| {
+        assert_eq!(
+            mal_rust::rep(
+                "(loop [i 0 acc 0] (if (< i 100000) (recur (+ i 1) (+ acc i)) acc))",
+                &env
+            ),
+            "4999950000"
+        );
+        // in non tail position, and through macro expansions
+        assert_eq!(
+            mal_rust::rep(
+                "(+ 1 (loop [i 0] (cond (< i 5) (recur (+ i 1)) \"else\" i)))",
+                &env
+            ),
+            "6"
+        );
+        assert_eq!(
+            mal_rust::rep(
+                "(loop [x 1] (loop [y 2] (if (< y 4) (recur (+ y 1)) [x y])))",
+                &env
+            ),
+            "[1 4]"
+        );
+        // each iteration has its own locals
+        assert_eq!(
+            mal_rust::rep(
+                "(loop [i 0 fs []] (if (< i 3) (recur (+ i 1) (conj fs (fn* () i))) (map (fn* (f) (f)) fs)))",
+                &env
+            ),
+            "(0 1 2)"
+        );
+        // an exception leaving an inner loop
+        assert_eq!(
+            mal_rust::rep(
+                "(loop [i 0] (do (try* (loop [j 0] (throw j)) (catch* e nil)) (if (< i 3) (recur (+ i 1)) i)))",
+                &env
+            ),
+            "3"
+        );
+    });
+}
+
+#[test]
+fn testing_recur_in_fn() {
+    common::for_each_backend(|env| {
+        mal_rust::rep(
+            "(def! sum (fn* (n acc) (if (= n 0) acc (recur (- n 1) (+ acc n)))))",
+            &env,
+        );
+        assert_eq!(mal_rust::rep("(sum 100000 0)", &env), "5000050000");
+
+        // the rest parameter is rebound to a list
+        mal_rust::rep(
+            "(def! pad (fn* (& xs) (if (< (count xs) 3) (recur (cons 0 xs)) xs)))",
+            &env,
+        );
+        assert_eq!(mal_rust::rep("(pad 1)", &env), "(0 0 1)");
+    });
+}
+
+#[test]
+fn testing_misplaced_recur() {
+    common::for_each_backend(|env| {
+        assert_eq!(
+            mal_rust::rep("(loop [i 0] (+ 1 (recur i)))", &env),
+            "Error: recur must be in tail position"
+        );
+        // rejected when the closure is defined, not when it's called
+        assert_eq!(
+            mal_rust::rep("(def! f (fn* () (try* (recur) (catch* e 1))))", &env),
+            "Error: recur must be in tail position"
+        );
+        assert_eq!(
+            mal_rust::rep("(recur 1)", &env),
+            "Error: recur must be in a loop or fn* body"
+        );
+        assert_eq!(
+            mal_rust::rep("(loop [i 1] (recur))", &env),
+            "Error: Type error: 'recur' takes exactly 1 argument (0 given)"
+        );
+    });
+}
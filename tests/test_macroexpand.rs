@@ -0,0 +1,74 @@
+use std::rc::Rc;
+
+use mal_rust::env::Env;
+
+mod common;
+
+/// Define the functions the tests use
+fn setup(env: &Rc<Env>) {
+    mal_rust::rep("(defmacro! unless (fn* (p a b) `(if ~p ~b ~a)))", env);
+    mal_rust::rep("(defmacro! my-when (fn* (p a) `(unless ~p nil ~a)))", env);
+}
+
+#[test]
+fn testing_macroexpand_1() {
+    common::for_each_backend(|env| {
+        setup(&env);
+        assert_eq!(
+            mal_rust::rep("(macroexpand-1 (my-when c x))", &env),
+            "(unless c nil x)"
+        );
+        assert_eq!(
+            mal_rust::rep("(macroexpand (my-when c x))", &env),
+            "(if c x nil)"
+        );
+        assert_eq!(mal_rust::rep("(macroexpand-1 (+ 1 2))", &env), "(+ 1 2)");
+        assert_eq!(
+            mal_rust::rep("(macroexpand-1)", &env),
+            "Error: Type error: 'macroexpand-1' takes exactly 1 argument (0 given)"
+        );
+    });
+}
+
+#[test]
+fn testing_macroexpand_all() {
+    common::for_each_backend(|env| {
+        setup(&env);
+        assert_eq!(
+            mal_rust::rep("(macroexpand-all (my-when a (my-when b c)))", &env),
+            "(if a (if b c nil) nil)"
+        );
+        // quoted forms and parameters are left as is
+        assert_eq!(
+            mal_rust::rep(
+                "(macroexpand-all (do '(my-when d e) (fn* (my-when) (my-when 1 2))))",
+                &env
+            ),
+            "(do (quote (my-when d e)) (fn* (my-when) (if 1 2 nil)))"
+        );
+        assert_eq!(
+            mal_rust::rep("(macroexpand-all [(my-when a b) {:k (my-when c d)}])", &env),
+            "[(if a b nil) {:k (if c d nil)}]"
+        );
+    });
+}
+
+#[test]
+fn testing_expansions_are_cached() {
+    common::for_each_backend(|env| {
+        setup(&env);
+        mal_rust::rep("(def! expansions (atom 0))", &env);
+        mal_rust::rep(
+            "(defmacro! counted (fn* (x) (do (swap! expansions + 1) x)))",
+            &env,
+        );
+        mal_rust::rep("(def! f (fn* (n) (counted n)))", &env);
+        assert_eq!(
+            mal_rust::rep(
+                "(loop [i 0] (if (< i 100) (do (f i) (recur (+ i 1))) @expansions))",
+                &env
+            ),
+            "1"
+        );
+    });
+}
@@ -0,0 +1,104 @@
+use std::rc::Rc;
+
+use mal_rust::env::Env;
+
+fn new_env() -> Rc<Env> {
+    let env = Rc::new(Env::new_default());
+    mal_rust::load_core(&env);
+    mal_rust::rep("(def! app-loads (atom 0))", &env);
+    env
+}
+
+#[test]
+fn testing_require_from_the_requiring_file_directory() {
+    let env = new_env();
+
+    assert_eq!(
+        mal_rust::rep("(load-file \"tests/modules/main.mal\")", &env),
+        "nil"
+    );
+    assert_eq!(mal_rust::rep("result", &env), "\"hello mal!\"");
+    assert_eq!(mal_rust::rep("(util.strings/shout 1)", &env), "\"1!\"");
+}
+
+#[test]
+fn testing_load_file_is_a_function_too() {
+    let env = new_env();
+
+    mal_rust::rep("(def! f load-file)", &env);
+    assert_eq!(mal_rust::rep("(f \"tests/modules/main.mal\")", &env), "nil");
+    assert_eq!(mal_rust::rep("result", &env), "\"hello mal!\"");
+    assert_eq!(
+        mal_rust::rep("(map load-file [\"tests/modules/main.mal\"])", &env),
+        "(nil)"
+    );
+    assert_eq!(
+        mal_rust::rep("(apply load-file [1])", &env),
+        "Error: Type error: type must be a string"
+    );
+}
+
+#[test]
+fn testing_modules_are_loaded_once() {
+    let env = new_env();
+
+    mal_rust::rep("(load-file \"tests/modules/main.mal\")", &env);
+    mal_rust::rep("(load-file \"tests/modules/main.mal\")", &env);
+    assert_eq!(mal_rust::rep("@app-loads", &env), "1");
+}
+
+#[test]
+fn testing_loading_restores_the_current_namespace() {
+    let env = new_env();
+
+    mal_rust::rep("(ns here)", &env);
+    mal_rust::rep("(load-file \"tests/modules/main.mal\")", &env);
+    assert_eq!(mal_rust::rep("::kw", &env), ":here/kw");
+}
+
+#[test]
+fn testing_circular_requires_are_detected() {
+    let env = new_env();
+
+    assert_eq!(
+        mal_rust::rep("(load-file \"tests/modules/cycle.mal\")", &env),
+        "Error: circular require of 'cycle.ping' (require chain: \
+         tests/modules/cycle.mal -> cycle.ping -> cycle.pong -> cycle.ping)"
+    );
+}
+
+#[test]
+fn testing_require_errors_report_the_require_chain() {
+    // only the directory of the requiring file is searched
+    std::env::remove_var("MAL_PATH");
+    let env = new_env();
+
+    assert_eq!(
+        mal_rust::rep("(load-file \"tests/modules/broken.mal\")", &env),
+        "Error: module 'util.missing' not found in: tests/modules \
+         (require chain: tests/modules/broken.mal -> util.missing)"
+    );
+    assert_eq!(
+        mal_rust::rep("(require 'missing)", &env),
+        "Error: module 'missing' not found in: . (require chain: missing)"
+    );
+    assert_eq!(
+        mal_rust::rep("(load-file \"tests/modules/requires_unbound.mal\")", &env),
+        "Error: symbol 'abc' not found \
+         (require chain: tests/modules/requires_unbound.mal -> util.unbound)"
+    );
+}
+
+#[test]
+fn testing_other_errors_have_no_require_chain() {
+    let env = new_env();
+
+    assert_eq!(
+        mal_rust::rep("(load-file \"tests/modules/unbound.mal\")", &env),
+        "Error: symbol 'abc' not found"
+    );
+    assert_eq!(
+        mal_rust::run_file("tests/modules/unbound.mal", &env),
+        "Error: symbol 'abc' not found"
+    );
+}
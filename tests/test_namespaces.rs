@@ -0,0 +1,116 @@
+use std::rc::Rc;
+
+use mal_rust::env::Env;
+
+#[test]
+fn testing_namespaces_do_not_clobber_each_other() {
+    let env = Rc::new(Env::new_default());
+
+    mal_rust::rep("(def! parse (fn* (x) (str \"user \" x)))", &env);
+    mal_rust::rep("(ns reader)", &env);
+    mal_rust::rep("(def! parse (fn* (x) (str \"reader \" x)))", &env);
+    assert_eq!(mal_rust::rep("(parse 1)", &env), "\"reader 1\"");
+
+    mal_rust::rep("(ns user)", &env);
+    assert_eq!(mal_rust::rep("(parse 1)", &env), "\"user 1\"");
+    assert_eq!(mal_rust::rep("(reader/parse 2)", &env), "\"reader 2\"");
+}
+
+#[test]
+fn testing_user_definitions_are_not_seen_by_other_namespaces() {
+    let env = Rc::new(Env::new_default());
+    mal_rust::load_core(&env);
+
+    mal_rust::rep("(def! helper (fn* () :user))", &env);
+    mal_rust::rep("(ns lib)", &env);
+    assert_eq!(
+        mal_rust::rep("(helper)", &env),
+        "Error: symbol 'helper' not found"
+    );
+    assert_eq!(mal_rust::rep("(user/helper)", &env), ":user");
+
+    // builtins and core.mal definitions are seen from every namespace
+    assert_eq!(mal_rust::rep("(+ 1 2)", &env), "3");
+    assert_eq!(mal_rust::rep("(not false)", &env), "true");
+    assert_eq!(mal_rust::rep("(resolve 'not)", &env), "mal.core/not");
+
+    mal_rust::rep("(ns user)", &env);
+    assert_eq!(mal_rust::rep("(helper)", &env), ":user");
+}
+
+#[test]
+fn testing_require_with_alias_and_refer() {
+    let env = Rc::new(Env::new_default());
+
+    mal_rust::rep("(ns str)", &env);
+    mal_rust::rep("(def! join (fn* (a b) (str a \",\" b)))", &env);
+    mal_rust::rep("(def! upper (fn* (a) a))", &env);
+
+    assert_eq!(
+        mal_rust::rep("(ns app (:require [str :as s :refer [join]]))", &env),
+        "nil"
+    );
+    assert_eq!(mal_rust::rep("(join 1 2)", &env), "\"1,2\"");
+    assert_eq!(mal_rust::rep("(s/join 3 4)", &env), "\"3,4\"");
+    assert_eq!(mal_rust::rep("(str/join 5 6)", &env), "\"5,6\"");
+    assert_eq!(
+        mal_rust::rep("(upper 1)", &env),
+        "Error: symbol 'upper' not found"
+    );
+
+    assert_eq!(
+        mal_rust::rep("(ns app2 (:require [str :refer :all]))", &env),
+        "nil"
+    );
+    assert_eq!(mal_rust::rep("(upper 1)", &env), "1");
+}
+
+#[test]
+fn testing_require_errors() {
+    let env = Rc::new(Env::new_default());
+
+    assert_eq!(
+        mal_rust::rep("(ns app (:require [missing]))", &env),
+        "Error: module 'missing' not found in: . (require chain: missing)"
+    );
+    mal_rust::rep("(ns lib)", &env);
+    assert_eq!(
+        mal_rust::rep("(ns app (:require [lib :refer [nothing]]))", &env),
+        "Error: symbol 'nothing' not found in namespace 'lib'"
+    );
+}
+
+#[test]
+fn testing_ns_inside_a_do_block() {
+    let env = Rc::new(Env::new_default());
+
+    assert_eq!(mal_rust::rep("(do (ns lib) (def! x 1) x)", &env), "1");
+    mal_rust::rep("(ns user)", &env);
+    assert_eq!(mal_rust::rep("lib/x", &env), "1");
+    assert_eq!(mal_rust::rep("x", &env), "Error: symbol 'x' not found");
+}
+
+#[test]
+fn testing_auto_resolved_keywords() {
+    let env = Rc::new(Env::new_default());
+
+    assert_eq!(mal_rust::rep("::local", &env), ":user/local");
+    mal_rust::rep("(ns lib)", &env);
+    assert_eq!(mal_rust::rep("::local", &env), ":lib/local");
+    assert_eq!(mal_rust::rep("[::a :b]", &env), "[:lib/a :b]");
+
+    // quoted data and map keys are qualified too
+    assert_eq!(mal_rust::rep("'::a", &env), ":lib/a");
+    assert_eq!(mal_rust::rep("(= '::a ::a)", &env), "true");
+    assert_eq!(
+        mal_rust::rep("'(1 [::a {::b ::c}])", &env),
+        "(1 [:lib/a {:lib/b :lib/c}])"
+    );
+    assert_eq!(mal_rust::rep("(get {::a 1} :lib/a)", &env), "1");
+    assert_eq!(mal_rust::rep("(get (quote {::a 1}) ::a)", &env), "1");
+
+    // in macro templates, with the namespace of the macro
+    mal_rust::rep("(defmacro! tagged (fn* (x) `[::tag ~x]))", &env);
+    mal_rust::rep("(ns app)", &env);
+    assert_eq!(mal_rust::rep("(lib/tagged ::x)", &env), "[:lib/tag :app/x]");
+}
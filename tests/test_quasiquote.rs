@@ -0,0 +1,83 @@
+mod common;
+
+#[test]
+fn testing_nested_quasiquote() {
+    common::for_each_backend(|env| {
+        mal_rust::rep("(def! x 5)", &env);
+        mal_rust::rep("(def! xs (list 1 2))", &env);
+
+        // the inner unquotes are kept, only the doubly unquoted form is
+        // evaluated
+        assert_eq!(
+            mal_rust::rep("``(a ~b)", &env),
+            "(quasiquote (a (unquote b)))"
+        );
+        assert_eq!(
+            mal_rust::rep("``(a ~(b ~x))", &env),
+            "(quasiquote (a (unquote (b 5))))"
+        );
+        assert_eq!(
+            mal_rust::rep("``(a ~@(b ~@xs))", &env),
+            "(quasiquote (a (splice-unquote (b 1 2))))"
+        );
+        assert_eq!(mal_rust::rep("(eval ``(a ~~x))", &env), "(a 5)");
+    });
+}
+
+#[test]
+fn testing_macro_generating_macro() {
+    common::for_each_backend(|env| {
+        mal_rust::rep(
+            "(defmacro! def-adder (fn* (name n) `(defmacro! ~name (fn* (y) `(+ ~y ~~n)))))",
+            &env,
+        );
+        mal_rust::rep("(def-adder add3 3)", &env);
+        assert_eq!(mal_rust::rep("(macroexpand (add3 4))", &env), "(+ 4 3)");
+        assert_eq!(mal_rust::rep("(add3 4)", &env), "7");
+    });
+}
+
+#[test]
+fn testing_syntax_quote() {
+    common::for_each_backend(|env| {
+        mal_rust::rep("(def! x 5)", &env);
+        assert_eq!(
+            mal_rust::rep("(syntax-quote (+ x ~x missing user/x))", &env),
+            "(mal.core/+ user/x 5 user/missing user/x)"
+        );
+        // special forms and auto-gensyms aren't qualified
+        let expanded = mal_rust::rep("(syntax-quote (let* [a# 1] (if a# a# nil)))", &env);
+        assert!(expanded.starts_with("(let* [a__"), "{}", expanded);
+        assert!(expanded.contains(" 1] (if a__"), "{}", expanded);
+        assert_eq!(
+            mal_rust::rep("(eval (syntax-quote (let* [a# 1] (if a# a# nil))))", &env),
+            "1"
+        );
+        assert_eq!(
+            mal_rust::rep("(syntax-quote)", &env),
+            "Error: Type error: 'syntax-quote' takes exactly 1 argument (0 given)"
+        );
+    });
+}
+
+#[test]
+fn testing_syntax_quote_across_namespaces() {
+    common::for_each_backend(|env| {
+        mal_rust::rep("(ns lib)", &env);
+        mal_rust::rep("(def! helper (fn* (a) (* a 10)))", &env);
+        mal_rust::rep(
+            "(defmacro! scaled (fn* (a) (syntax-quote (helper ~a))))",
+            &env,
+        );
+        assert_eq!(
+            mal_rust::rep("(macroexpand (scaled 2))", &env),
+            "(lib/helper 2)"
+        );
+
+        // the expansion refers to lib's helper wherever it's used
+        mal_rust::rep("(ns app (:require [lib :refer [scaled]]))", &env);
+        mal_rust::rep("(def! helper (fn* (a) a))", &env);
+        assert_eq!(mal_rust::rep("(scaled 2)", &env), "20");
+        assert_eq!(mal_rust::rep("(helper 2)", &env), "2");
+    });
+}
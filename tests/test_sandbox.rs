@@ -0,0 +1,239 @@
+use std::rc::Rc;
+use std::time::Duration;
+
+use mal_rust::env::Env;
+use mal_rust::{Backend, Sandbox};
+
+/// Create an environment evaluating code in sandbox
+fn sandboxed_env(backend: Backend, sandbox: Sandbox) -> Rc<Env> {
+    mal_rust::set_backend(backend);
+    mal_rust::set_sandbox(None);
+    let env = Rc::new(Env::new_default());
+    mal_rust::load_core(&env);
+    mal_rust::set_sandbox(Some(sandbox));
+    env
+}
+
+#[test]
+fn testing_step_quota() {
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let sandbox = Sandbox {
+            max_steps: Some(10_000),
+            ..Sandbox::default()
+        };
+        let env = sandboxed_env(backend, sandbox.clone());
+        mal_rust::rep("(def! f (fn* () (f)))", &env);
+        assert_eq!(mal_rust::rep("(f)", &env), "Error: step quota exceeded");
+
+        // the exception is caught by kind, but the handler can't make
+        // any call and later steps fail too
+        assert_eq!(
+            mal_rust::rep("(try* (f) (catch* :step-quota-error e :caught))", &env),
+            ":caught"
+        );
+        assert_eq!(
+            mal_rust::rep("(try* (f) (catch* e (str \"caught \" e)))", &env),
+            "Error: step quota exceeded"
+        );
+        assert_eq!(mal_rust::rep("(+ 1 2)", &env), "Error: step quota exceeded");
+
+        mal_rust::set_sandbox(Some(sandbox));
+        mal_rust::rep(
+            "(def! sum (fn* (n) (if (= n 0) 0 (+ n (sum (- n 1))))))",
+            &env,
+        );
+        assert_eq!(mal_rust::rep("(sum 100)", &env), "5050");
+        mal_rust::set_sandbox(None);
+    }
+}
+
+#[test]
+fn testing_time_quota() {
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let sandbox = Sandbox {
+            timeout: Some(Duration::from_millis(50)),
+            ..Sandbox::default()
+        };
+        let env = sandboxed_env(backend, sandbox);
+        mal_rust::rep("(def! f (fn* (n) (f (+ n 1))))", &env);
+        assert_eq!(
+            mal_rust::rep("(try* (f 0) (catch* :time-quota-error e :caught))", &env),
+            ":caught"
+        );
+        assert_eq!(mal_rust::rep("(+ 1 2)", &env), "Error: time quota exceeded");
+        mal_rust::set_sandbox(None);
+    }
+}
+
+#[test]
+fn testing_size_quota() {
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let sandbox = Sandbox {
+            max_size: Some(100),
+            ..Sandbox::default()
+        };
+        let env = sandboxed_env(backend, sandbox);
+        mal_rust::rep("(def! grow (fn* (xs) (grow (concat xs xs))))", &env);
+        assert_eq!(
+            mal_rust::rep("(grow (list 1))", &env),
+            "Error: size quota exceeded"
+        );
+        assert_eq!(mal_rust::rep("(count (concat (list 1 2) [3]))", &env), "3");
+        mal_rust::rep("(def! long (fn* (_) \"abcdefghijk\"))", &env);
+        assert_eq!(
+            mal_rust::rep(
+                "(try* (apply str (map long [1 2 3 4 5 6 7 8 9 10])) (catch* :size-quota-error e (ex-message e)))",
+                &env
+            ),
+            "\"size quota exceeded\""
+        );
+
+        // finally blocks are run too
+        mal_rust::rep("(def! log (atom []))", &env);
+        assert_eq!(
+            mal_rust::rep(
+                "(try* (apply str (map long [1 2 3 4 5 6 7 8 9 10])) (finally (swap! log conj :finally)))",
+                &env
+            ),
+            "Error: size quota exceeded"
+        );
+        assert_eq!(mal_rust::rep("@log", &env), "[:finally]");
+
+        // nested elements are counted, in literals too
+        mal_rust::rep(
+            "(def! dbl (fn* (v n) (if (= n 0) 0 (dbl [v v] (- n 1)))))",
+            &env,
+        );
+        assert_eq!(
+            mal_rust::rep("(dbl 1 40)", &env),
+            "Error: size quota exceeded"
+        );
+        mal_rust::rep(
+            "(def! wrap (fn* (m n) (if (= n 0) 0 (wrap {:a m :b m} (- n 1)))))",
+            &env,
+        );
+        assert_eq!(
+            mal_rust::rep("(wrap 1 40)", &env),
+            "Error: size quota exceeded"
+        );
+        assert_eq!(mal_rust::rep("(count (list [1 2] [3 4]))", &env), "2");
+        mal_rust::rep(&format!("(def! s \"{}\")", "x".repeat(60)), &env);
+        assert_eq!(
+            mal_rust::rep("(list s s)", &env),
+            "Error: size quota exceeded"
+        );
+        mal_rust::set_sandbox(None);
+    }
+}
+
+#[test]
+fn testing_io_denied() {
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let sandbox = Sandbox {
+            deny_io: true,
+            ..Sandbox::default()
+        };
+        mal_rust::set_backend(backend);
+        mal_rust::set_sandbox(Some(sandbox));
+        let env = Rc::new(Env::new_default());
+        mal_rust::load_core(&env);
+
+        assert_eq!(
+            mal_rust::rep("(slurp \"Cargo.toml\")", &env),
+            "Error: symbol 'slurp' not found"
+        );
+        assert_eq!(
+            mal_rust::rep("(readline \"> \")", &env),
+            "Error: symbol 'readline' not found"
+        );
+        assert_eq!(
+            mal_rust::rep(
+                "(try* (load-file \"Cargo.toml\") (catch* e (ex-message e)))",
+                &env
+            ),
+            "\"IO denied: can't read files in the sandbox\""
+        );
+        assert_eq!(
+            mal_rust::rep("(save-image \"sandbox.img\")", &env),
+            "Error: IO denied: can't write files in the sandbox"
+        );
+        assert_eq!(
+            mal_rust::rep("(require 'sandbox)", &env),
+            "Error: IO denied: can't read files in the sandbox"
+        );
+        assert_eq!(mal_rust::rep("(+ 1 2)", &env), "3");
+        mal_rust::set_sandbox(None);
+    }
+}
+
+#[test]
+fn testing_io_denied_in_existing_environments() {
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let sandbox = Sandbox {
+            deny_io: true,
+            ..Sandbox::default()
+        };
+        // the IO functions are defined before the sandbox is set
+        let env = sandboxed_env(backend, sandbox);
+
+        assert_eq!(
+            mal_rust::rep("(slurp \"/etc/passwd\")", &env),
+            "Error: IO denied: can't slurp in the sandbox"
+        );
+        assert_eq!(
+            mal_rust::rep("(readline \"> \")", &env),
+            "Error: IO denied: can't readline in the sandbox"
+        );
+        assert_eq!(
+            mal_rust::rep(
+                "(try* (slurp \"Cargo.toml\") (catch* :io-error e :denied))",
+                &env
+            ),
+            ":denied"
+        );
+        mal_rust::set_sandbox(None);
+        assert_eq!(
+            mal_rust::rep("(string? (slurp \"Cargo.toml\"))", &env),
+            "true"
+        );
+    }
+}
+
+#[test]
+fn testing_closure_arity_errors() {
+    // untrusted code can't make the interpreter panic
+    for backend in [Backend::TreeWalker, Backend::Vm] {
+        let env = sandboxed_env(backend, Sandbox::default());
+        assert_eq!(
+            mal_rust::rep("((fn* (a b) a) 1)", &env),
+            "Error: Type error: 'fn*' takes exactly 2 argument (1 given)"
+        );
+        assert_eq!(
+            mal_rust::rep("((fn* (a) a) 1 2)", &env),
+            "Error: Type error: 'fn*' takes exactly 1 argument (2 given)"
+        );
+        mal_rust::rep("(def! f (fn* (a b & more) more))", &env);
+        assert_eq!(
+            mal_rust::rep("(f 1)", &env),
+            "Error: Type error: 'f' takes at least 2 argument (1 given)"
+        );
+        assert_eq!(mal_rust::rep("(f 1 2)", &env), "()");
+        assert_eq!(
+            mal_rust::rep("(try* (map f [1]) (catch* :arity-error e :arity))", &env),
+            ":arity"
+        );
+        assert_eq!(
+            mal_rust::rep("((fn* (a &) a) 1)", &env),
+            "Error: fn* must have exactly one parameter name after an &"
+        );
+        assert_eq!(
+            mal_rust::rep("((fn* (a & b c) a) 1)", &env),
+            "Error: fn* must have exactly one parameter name after an &"
+        );
+        assert_eq!(
+            mal_rust::rep("(try* (fn* (&) 1) (catch* e (ex-message e)))", &env),
+            "\"fn* must have exactly one parameter name after an &\""
+        );
+        mal_rust::set_sandbox(None);
+    }
+}
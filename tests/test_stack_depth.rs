@@ -0,0 +1,170 @@
+use std::thread;
+
+use mal_rust::Backend;
+
+mod common;
+
+/// Run f in a thread with a stack big enough for the default maximum
+/// depth, like the interpreter does
+fn with_stack<F: FnOnce() + Send + 'static>(f: F) {
+    thread::Builder::new()
+        .stack_size(mal_rust::stack_size_for(mal_rust::DEFAULT_MAX_DEPTH))
+        .spawn(f)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn testing_deep_recursion_raises_an_exception() {
+    with_stack(|| {
+        common::for_each_backend(|env| {
+            mal_rust::rep(
+                "(def! depth (fn* (n) (if (= n 0) 0 (+ 1 (depth (- n 1))))))",
+                &env,
+            );
+            assert_eq!(mal_rust::rep("(depth 1000)", &env), "1000");
+            assert_eq!(
+                mal_rust::rep("(depth 100000)", &env),
+                "Error: stack depth exceeded"
+            );
+            assert_eq!(
+                mal_rust::rep("(try* (depth 100000) (catch* e (ex-message e)))", &env),
+                "\"stack depth exceeded\""
+            );
+            assert_eq!(
+                mal_rust::rep(
+                    "(try* (depth 100000) (catch* :stack-depth-error e :too-deep))",
+                    &env
+                ),
+                ":too-deep"
+            );
+            // the depth is restored after the exception
+            assert_eq!(mal_rust::rep("(depth 1000)", &env), "1000");
+
+            // through builtins calling functions
+            mal_rust::rep(
+                "(def! nested (fn* (n) (if (= n 0) 0 (first (map nested [(- n 1)])))))",
+                &env,
+            );
+            assert_eq!(
+                mal_rust::rep("(nested 100000)", &env),
+                "Error: stack depth exceeded"
+            );
+        });
+    });
+}
+
+#[test]
+fn testing_default_stack_is_not_overflowed() {
+    // the stack of a spawned thread and of the main thread, too small
+    // for the default maximum depth in unoptimized builds
+    for stack_size in [2 * 1024 * 1024, 8 * 1024 * 1024] {
+        thread::Builder::new()
+            .stack_size(stack_size)
+            .spawn(|| {
+                let env = common::new_env(Backend::TreeWalker);
+                assert_eq!(mal_rust::max_depth(), mal_rust::DEFAULT_MAX_DEPTH);
+
+                mal_rust::rep(
+                    "(def! depth (fn* (n) (if (= n 0) 0 (+ 1 (depth (- n 1))))))",
+                    &env,
+                );
+                mal_rust::rep(
+                    "(def! nested (fn* (n) (if (= n 0) 0 (first (map nested [(- n 1)])))))",
+                    &env,
+                );
+                for form in ["(depth 9000)", "(nested 9000)"] {
+                    let result = mal_rust::rep(form, &env);
+                    assert!(
+                        result == "9000" || result == "Error: stack depth exceeded",
+                        "{}",
+                        result
+                    );
+                }
+                assert_eq!(mal_rust::rep("(depth 100)", &env), "100");
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
+
+#[test]
+fn testing_max_depth_is_configurable() {
+    common::for_each_backend(|env| {
+        mal_rust::set_max_depth(100);
+
+        mal_rust::rep(
+            "(def! depth (fn* (n) (if (= n 0) 0 (+ 1 (depth (- n 1))))))",
+            &env,
+        );
+        assert_eq!(mal_rust::rep("(depth 50)", &env), "50");
+        assert_eq!(
+            mal_rust::rep("(depth 200)", &env),
+            "Error: stack depth exceeded"
+        );
+
+        // deeper than 100 but shallow enough for the stack of the test
+        mal_rust::set_max_depth(mal_rust::DEFAULT_MAX_DEPTH);
+        assert_eq!(mal_rust::rep("(depth 150)", &env), "150");
+    });
+}
+
+#[test]
+fn testing_stack_size_is_capped() {
+    assert_eq!(
+        mal_rust::stack_size_for(1_000_000),
+        mal_rust::MAX_STACK_SIZE
+    );
+    assert_eq!(
+        mal_rust::stack_size_for(usize::MAX),
+        mal_rust::MAX_STACK_SIZE
+    );
+
+    // a thread can be spawned for a very high limit, the VM reaches it
+    thread::Builder::new()
+        .stack_size(mal_rust::stack_size_for(1_000_000))
+        .spawn(|| {
+            let env = common::new_env(Backend::Vm);
+            mal_rust::set_max_depth(1_000_000);
+            mal_rust::rep(
+                "(def! depth (fn* (n) (if (= n 0) 0 (+ 1 (depth (- n 1))))))",
+                &env,
+            );
+            assert_eq!(mal_rust::rep("(depth 200000)", &env), "200000");
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn testing_deeply_nested_data() {
+    with_stack(|| {
+        let env = common::new_env(Backend::TreeWalker);
+        let depth = 200_000;
+        let nested = format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        let nested_vectors = format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+
+        // read and printed
+        mal_rust::rep(&format!("(def! a (read-string \"{}\"))", nested), &env);
+        assert_eq!(mal_rust::rep("a", &env), nested);
+        mal_rust::rep(
+            &format!("(def! b (read-string \"{}\"))", nested_vectors),
+            &env,
+        );
+        assert_eq!(mal_rust::rep("b", &env), nested_vectors);
+
+        // compared
+        mal_rust::rep(&format!("(def! c (read-string \"{}\"))", nested), &env);
+        assert_eq!(mal_rust::rep("(= a c)", &env), "true");
+        assert_eq!(mal_rust::rep("(= a b)", &env), "false");
+
+        // evaluated
+        assert_eq!(
+            mal_rust::rep(&nested_vectors, &env),
+            "Error: stack depth exceeded"
+        );
+    });
+}
@@ -0,0 +1,140 @@
+mod common;
+
+#[test]
+fn testing_ex_stack() {
+    common::for_each_backend(|env| {
+        mal_rust::rep("(def! inner (fn* (x) (+ x \"a\")))", &env);
+        mal_rust::rep("(def! outer (fn* (x) (let* [y (inner x)] y)))", &env);
+        assert_eq!(
+            mal_rust::rep("(try* (outer 1) (catch* e (ex-stack e)))", &env),
+            "(\"+\" \"inner\" \"outer\")"
+        );
+
+        // thrown exception maps, anonymous functions and builtins
+        // calling them
+        assert_eq!(
+            mal_rust::rep(
+                "(try* (map (fn* [a] (throw (ex-info \"a\" {}))) [1]) (catch* e (ex-stack e)))",
+                &env
+            ),
+            "(\"throw\" \"fn* [a]\" \"map\")"
+        );
+
+        // tail calls replace the call they're made from
+        mal_rust::rep("(def! tail (fn* (x) (inner x)))", &env);
+        assert_eq!(
+            mal_rust::rep("(try* (tail 1) (catch* e (ex-stack e)))", &env),
+            "(\"+\" \"inner\")"
+        );
+
+        // errors raised by the evaluator
+        mal_rust::rep("(def! missing (fn* () (+ 1 undefined-symbol)))", &env);
+        assert_eq!(
+            mal_rust::rep("(try* (missing) (catch* e (ex-stack e)))", &env),
+            "(\"missing\")"
+        );
+
+        // the stack is unwound after the exception is caught
+        assert_eq!(
+            mal_rust::rep("(outer (try* (outer 1) (catch* e 2)))", &env),
+            "Error: Type error: + only accepts numeric types"
+        );
+        assert_eq!(mal_rust::rep("(ex-stack \"not caught\")", &env), "nil");
+    });
+}
+
+#[test]
+fn testing_ex_stack_is_carried_by_the_exception() {
+    common::for_each_backend(|env| {
+        // exception maps thrown from different places keep their stack
+        mal_rust::rep("(def! one (fn* () (throw (ex-info \"x\" {}))))", &env);
+        mal_rust::rep("(def! two (fn* () (throw (ex-info \"x\" {}))))", &env);
+        mal_rust::rep("(def! e1 (try* (one) (catch* e e)))", &env);
+        mal_rust::rep("(def! e2 (try* (two) (catch* e e)))", &env);
+        assert_eq!(mal_rust::rep("(ex-stack e1)", &env), "(\"throw\" \"one\")");
+        assert_eq!(mal_rust::rep("(ex-stack e2)", &env), "(\"throw\" \"two\")");
+        assert_eq!(
+            mal_rust::rep("(get e1 :stack)", &env),
+            "(\"throw\" \"one\")"
+        );
+        assert_eq!(
+            mal_rust::rep("(= (dissoc e1 :stack) (dissoc e2 :stack))", &env),
+            "true"
+        );
+
+        // stored exceptions keep it however many are caught since
+        for _ in 0..20 {
+            mal_rust::rep("(try* (two) (catch* e e))", &env);
+        }
+        assert_eq!(mal_rust::rep("(ex-stack e1)", &env), "(\"throw\" \"one\")");
+
+        // rethrown ones keep the stack they had
+        assert_eq!(
+            mal_rust::rep("(ex-stack (try* (throw e1) (catch* e e)))", &env),
+            "(\"throw\" \"one\")"
+        );
+        assert_eq!(
+            mal_rust::rep("(try* (nth [] 1) (catch* e (ex-stack e)))", &env),
+            "(\"nth\")"
+        );
+
+        // other values are caught as they were thrown, without a stack
+        assert_eq!(
+            mal_rust::rep("(try* (throw :x) (catch* e [e (ex-stack e)]))", &env),
+            "[:x nil]"
+        );
+        assert_eq!(
+            mal_rust::rep("(try* (throw {:a 1}) (catch* e e))", &env),
+            "{:a 1}"
+        );
+    });
+}
+
+#[test]
+fn testing_closures_keep_their_first_name() {
+    common::for_each_backend(|env| {
+        mal_rust::rep("(def! fail (fn* () (throw (ex-info \"fail\" {}))))", &env);
+        mal_rust::rep("(def! alias fail)", &env);
+        assert_eq!(
+            mal_rust::rep("(try* (alias) (catch* e (ex-stack e)))", &env),
+            "(\"throw\" \"fail\")"
+        );
+    });
+}
+
+#[test]
+fn testing_uncaught_errors_print_their_stack() {
+    common::for_each_backend(|env| {
+        mal_rust::set_stack_traces(true);
+
+        mal_rust::rep("(def! half (fn* (n) (/ n \"2\")))", &env);
+        assert_eq!(
+            mal_rust::rep("(map (fn* (n) (+ 1 (half n))) [1])", &env),
+            "Error: Type error: / only accepts numeric types\n  \
+             at /\n  at half\n  at fn* (n)\n  at map"
+        );
+
+        // repeated calls are collapsed
+        mal_rust::rep(
+            "(def! down (fn* (n) (if (= n 0) (half n) (+ 1 (down (- n 1))))))",
+            &env,
+        );
+        assert_eq!(
+            mal_rust::rep("(down 3)", &env),
+            "Error: Type error: / only accepts numeric types\n  \
+             at /\n  at half\n  at down (3 times)"
+        );
+
+        // errors at top level have no stack
+        assert_eq!(
+            mal_rust::rep("(/ 1 \"2\")", &env),
+            "Error: Type error: / only accepts numeric types\n  at /"
+        );
+
+        mal_rust::set_stack_traces(false);
+        assert_eq!(
+            mal_rust::rep("(down 3)", &env),
+            "Error: Type error: / only accepts numeric types"
+        );
+    });
+}
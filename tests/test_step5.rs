@@ -1,6 +1,5 @@
 use std::rc::Rc;
 
-use mal_rust;
 use mal_rust::env::Env;
 
 #[test]
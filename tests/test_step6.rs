@@ -1,7 +1,6 @@
 use std::rc::Rc;
 use std::error::Error;
 
-use mal_rust;
 use mal_rust::env::Env;
 
 mod common;
@@ -0,0 +1,100 @@
+use std::thread;
+
+mod common;
+
+/// Depth of the recursions. Recursing this deep through calls that
+/// aren't tail calls overflows the native stack of the tests.
+const DEPTH: &str = "100000";
+
+/// Depth of the slow test, run with `cargo test -- --ignored`
+const DEEP: &str = "1000000";
+
+/// Size of the native stack the tests run with
+const STACK_SIZE: usize = 256 * 1024;
+
+/// Run a test in a thread with a small native stack
+fn with_small_stack(test: impl FnOnce() + Send + 'static) {
+    thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(test)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+/// Define f with body, a function of n recursing through a tail
+/// call, and check with each backend that it returns 0 when called
+/// depth deep
+fn assert_tail_calls(body: &str, depth: &str) {
+    common::for_each_backend(|env| {
+        mal_rust::rep(&format!("(def! f (fn* (n) {}))", body), &env);
+        assert_eq!(
+            mal_rust::rep(&format!("(f {})", depth), &env),
+            "0",
+            "{}",
+            body
+        );
+    });
+}
+
+#[test]
+fn testing_tail_calls_in_special_forms() {
+    with_small_stack(|| tail_calls_in_special_forms(DEPTH));
+}
+
+#[test]
+#[ignore = "slow in debug builds"]
+fn testing_tail_calls_a_million_deep() {
+    with_small_stack(|| tail_calls_in_special_forms(DEEP));
+}
+
+fn tail_calls_in_special_forms(depth: &str) {
+    let bodies = [
+        // then and else branches of if
+        "(if (> n 0) (f (- n 1)) n)",
+        "(if (= n 0) n (f (- n 1)))",
+        "(let* (m (- n 1)) (if (< m 0) 0 (f m)))",
+        "(do (if (= n 0) 0 (f (- n 1))))",
+        "(cond (= n 0) 0 :else (f (- n 1)))",
+        "(if (= n 0) 0 (try* (throw (- n 1)) (catch* m (f m))))",
+        "(if (= n 0) 0 (eval (list 'f (- n 1))))",
+    ];
+    for body in bodies {
+        assert_tail_calls(body, depth);
+    }
+}
+
+#[test]
+fn testing_apply_in_tail_position() {
+    with_small_stack(apply_in_tail_position);
+}
+
+fn apply_in_tail_position() {
+    assert_tail_calls("(if (= n 0) 0 (apply f [(- n 1)]))", DEPTH);
+    assert_tail_calls("(if (= n 0) 0 (apply apply f [[(- n 1)]]))", DEPTH);
+}
+
+#[test]
+fn testing_tail_calls_in_functions_called_by_builtins() {
+    with_small_stack(tail_calls_in_functions_called_by_builtins);
+}
+
+fn tail_calls_in_functions_called_by_builtins() {
+    common::for_each_backend(|env| {
+        mal_rust::rep(
+            "(def! count-down (fn* (n) (if (= n 0) 0 (count-down (- n 1)))))",
+            &env,
+        );
+
+        mal_rust::rep(&format!("(def! a (atom {}))", DEPTH), &env);
+        assert_eq!(mal_rust::rep("(swap! a count-down)", &env), "0");
+        assert_eq!(
+            mal_rust::rep(&format!("(map count-down [{} 10])", DEPTH), &env),
+            "(0 0)"
+        );
+        assert_eq!(
+            mal_rust::rep(&format!("(apply count-down [{}])", DEPTH), &env),
+            "0"
+        );
+    });
+}
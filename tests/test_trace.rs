@@ -0,0 +1,134 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+mod common;
+
+/// Trace output shared with the test
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Output {
+    /// Return the lines traced since the last call
+    fn take(&self) -> Vec<String> {
+        let bytes = std::mem::take(&mut *self.0.borrow_mut());
+        String::from_utf8(bytes)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+}
+
+fn capture() -> Output {
+    let output = Output::default();
+    mal_rust::set_trace_output(Some(Box::new(output.clone())));
+    output
+}
+
+const FACT: &str = "(def! fact (fn* (n) (if (< n 2) 1 (* n (fact (- n 1))))))";
+
+#[test]
+fn testing_trace_and_untrace() {
+    common::for_each_backend(|env| {
+        let output = capture();
+        mal_rust::rep(FACT, &env);
+
+        assert_eq!(mal_rust::rep("(trace 'fact)", &env), "nil");
+        assert_eq!(mal_rust::rep("(fact 3)", &env), "6");
+        assert_eq!(
+            output.take(),
+            [
+                "(fact 3)",
+                "  (fact 2)",
+                "    (fact 1)",
+                "    => 1",
+                "  => 2",
+                "=> 6"
+            ]
+        );
+
+        assert_eq!(mal_rust::rep("(untrace 'fact)", &env), "nil");
+        assert_eq!(mal_rust::rep("(fact 3)", &env), "6");
+        assert!(output.take().is_empty());
+
+        // untrace without arguments restores every traced closure
+        mal_rust::rep("(def! inc (fn* (x) (+ x 1)))", &env);
+        mal_rust::rep("(trace 'fact 'inc)", &env);
+        assert_eq!(mal_rust::rep("(inc (fact 2))", &env), "3");
+        assert_eq!(
+            output.take(),
+            [
+                "(fact 2)",
+                "  (fact 1)",
+                "  => 1",
+                "=> 2",
+                "(inc 2)",
+                "=> 3"
+            ]
+        );
+        mal_rust::rep("(untrace)", &env);
+        assert_eq!(mal_rust::rep("(inc (fact 2))", &env), "3");
+        assert!(output.take().is_empty());
+    });
+}
+
+#[test]
+fn testing_trace_errors() {
+    common::for_each_backend(|env| {
+        assert_eq!(
+            mal_rust::rep("(trace '+)", &env),
+            "Error: Type error: only closures can be traced"
+        );
+        assert_eq!(
+            mal_rust::rep("(trace 1)", &env),
+            "Error: Type error: 'trace' arguments must be symbols"
+        );
+        assert_eq!(
+            mal_rust::rep("(untrace 'nope)", &env),
+            "Error: symbol 'nope' not found"
+        );
+    });
+}
+
+#[test]
+fn testing_trace_var() {
+    common::for_each_backend(|env| {
+        let output = capture();
+        mal_rust::rep("(def! double (fn* (x) (* 2 x)))", &env);
+        mal_rust::rep("(def! quad (fn* (x) (+ 0 (double (double x)))))", &env);
+
+        assert_eq!(
+            mal_rust::rep("(binding [*trace* true] (quad 1))", &env),
+            "4"
+        );
+        assert_eq!(
+            output.take(),
+            [
+                "(quad 1)",
+                "  (double 1)",
+                "  => 2",
+                "  (double 2)",
+                "  => 4",
+                "=> 4"
+            ]
+        );
+        assert_eq!(mal_rust::rep("(quad 1)", &env), "4");
+        assert!(output.take().is_empty());
+
+        mal_rust::rep("(def! *trace* true)", &env);
+        assert_eq!(mal_rust::rep("(double 3)", &env), "6");
+        mal_rust::rep("(def! *trace* false)", &env);
+        assert_eq!(output.take(), ["(double 3)", "=> 6"]);
+    });
+}
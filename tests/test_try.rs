@@ -0,0 +1,132 @@
+mod common;
+
+#[test]
+fn testing_typed_catch_clauses() {
+    common::for_each_backend(|env| {
+        mal_rust::rep(
+            "(def! classify (fn* (f) (try* (f)
+               (catch* :type-error e (str \"type: \" (ex-message e)))
+               (catch* :index-error e (str \"index: \" (ex-message e)))
+               (catch* string? e (str \"string: \" e))
+               (catch* :default e (str \"other: \" e)))))",
+            &env,
+        );
+        assert_eq!(
+            mal_rust::rep("(classify (fn* () (+ 1 \"a\")))", &env),
+            "\"type: Type error: + only accepts numeric types\""
+        );
+        assert_eq!(
+            mal_rust::rep("(classify (fn* () (nth [1] 5)))", &env),
+            "\"index: Index error: index 5 is out of bounds for sequence of length 1\""
+        );
+        assert_eq!(
+            mal_rust::rep("(classify (fn* () (throw \"oops\")))", &env),
+            "\"string: oops\""
+        );
+        assert_eq!(
+            mal_rust::rep("(classify (fn* () (throw 42)))", &env),
+            "\"other: 42\""
+        );
+        assert_eq!(mal_rust::rep("(classify (fn* () 1))", &env), "1");
+
+        // handlers in tail position returning without a call
+        mal_rust::rep(
+            "(def! kind (fn* (x) (try* (throw x) (catch* string? e :string) (catch* e :other))))",
+            &env,
+        );
+        assert_eq!(mal_rust::rep("(kind \"a\")", &env), ":string");
+        assert_eq!(mal_rust::rep("(kind 1)", &env), ":other");
+
+        // exceptions no clause catches are rethrown
+        assert_eq!(
+            mal_rust::rep(
+                "(try* (try* (throw 1) (catch* :type-error e :inner)) (catch* e [:outer e]))",
+                &env
+            ),
+            "[:outer 1]"
+        );
+        assert_eq!(
+            mal_rust::rep("(try* (throw 1) (catch* string? e e))", &env),
+            "Error: 1"
+        );
+    });
+}
+
+#[test]
+fn testing_finally() {
+    common::for_each_backend(|env| {
+        mal_rust::rep("(def! log (atom []))", &env);
+        mal_rust::rep("(def! note (fn* (x) (swap! log conj x)))", &env);
+
+        assert_eq!(
+            mal_rust::rep("(try* (do (note :body) 1) (finally (note :finally)))", &env),
+            "1"
+        );
+        assert_eq!(
+            mal_rust::rep(
+                "(try* (throw 2) (catch* e (do (note :catch) e)) (finally (note :finally)))",
+                &env
+            ),
+            "2"
+        );
+        // finally runs when nothing catches the exception, and when the
+        // catch clause throws
+        assert_eq!(
+            mal_rust::rep("(try* (throw 3) (finally (note :uncaught)))", &env),
+            "Error: 3"
+        );
+        assert_eq!(
+            mal_rust::rep(
+                "(try* (try* (throw 4) (catch* e (throw (+ e 1))) (finally (note :rethrown))) (catch* e e))",
+                &env
+            ),
+            "5"
+        );
+        assert_eq!(
+            mal_rust::rep("@log", &env),
+            "[:body :finally :catch :finally :uncaught :rethrown]"
+        );
+
+        // an exception of the finally block replaces the exception
+        assert_eq!(
+            mal_rust::rep(
+                "(try* (try* (throw 6) (finally (throw 7))) (catch* e e))",
+                &env
+            ),
+            "7"
+        );
+        // exceptions of finally blocks unwinding through closures calls
+        mal_rust::rep(
+            "(def! countdown (fn* (n) (try* (if (= n 0) (throw :done) (countdown (- n 1))) (finally (note n)))))",
+            &env,
+        );
+        mal_rust::rep("(reset! log [])", &env);
+        assert_eq!(
+            mal_rust::rep("(try* (countdown 2) (catch* e e))", &env),
+            ":done"
+        );
+        assert_eq!(mal_rust::rep("@log", &env), "[0 1 2]");
+    });
+}
+
+#[test]
+fn testing_malformed_try() {
+    common::for_each_backend(|env| {
+        assert_eq!(
+            mal_rust::rep("(try* 1 (catch* :nope e e))", &env),
+            "Error: unknown exception kind :nope"
+        );
+        assert_eq!(
+            mal_rust::rep("(try* 1 (finally 2) (catch* e e))", &env),
+            "Error: finally must be the last try* clause"
+        );
+        assert_eq!(
+            mal_rust::rep("(try* 1 (cat e e))", &env),
+            "Error: try* clauses must be catch* or finally blocks"
+        );
+        assert_eq!(
+            mal_rust::rep("(try* 1 (catch* :default 2 e))", &env),
+            "Error: Type error: catch* exception name must be a symbol"
+        );
+    });
+}
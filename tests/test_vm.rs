@@ -0,0 +1,154 @@
+use mal_rust::Backend;
+
+mod common;
+
+#[test]
+fn testing_backends_agree() {
+    let programs = [
+        "(def! fib (fn* (n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2))))))",
+        "(fib 15)",
+        "(let* (x 1 y [x (+ x 1)]) {:y y})",
+        "(do (def! a 1) (def! b (+ a 1)) [a b])",
+        "(if nil 1)",
+        "(cond false 1 (= 1 2) 2 :else 3)",
+        "`(1 ~(+ 1 1) ~@(list 3 4))",
+        "(eval (list + 1 2))",
+        "(map (fn* (x) (* x x)) [1 2 3])",
+        "(apply + [1 2])",
+        "(def! counter (atom 0))",
+        "(swap! counter (fn* (n) (+ n 10)))",
+        "(try* (throw {:code 1}) (catch* e (get e :code)))",
+        "(try* (nth [] 1) (catch* e [(get e :kind) (ex-message e)]))",
+        "(try* undefined-symbol (catch* e [(get e :kind) (ex-message e)]))",
+        "(let* (1 2) 3)",
+        "(undefined-function 1 2)",
+        "::local",
+    ];
+
+    let tree_walker: Vec<String> = {
+        let env = common::new_env(Backend::TreeWalker);
+        programs
+            .iter()
+            .map(|code| mal_rust::rep(code, &env))
+            .collect()
+    };
+    let vm: Vec<String> = {
+        let env = common::new_env(Backend::Vm);
+        programs
+            .iter()
+            .map(|code| mal_rust::rep(code, &env))
+            .collect()
+    };
+
+    assert_eq!(tree_walker, vm);
+}
+
+#[test]
+fn testing_vm_tail_calls() {
+    let env = common::new_env(Backend::Vm);
+
+    mal_rust::rep(
+        "(def! sum-to (fn* (n acc) (if (= n 0) acc (sum-to (- n 1) (+ n acc)))))",
+        &env,
+    );
+    assert_eq!(mal_rust::rep("(sum-to 100000 0)", &env), "5000050000");
+
+    // tail calls through let*, do and macros
+    mal_rust::rep(
+        "(def! count-down (fn* (n) (let* (m (- n 1)) (do (cond (= n 0) :done :else (count-down m))))))",
+        &env,
+    );
+    assert_eq!(mal_rust::rep("(count-down 100000)", &env), ":done");
+
+    // non tail calls don't grow the native stack either, only the
+    // evaluation depth
+    mal_rust::set_max_depth(200_000);
+    mal_rust::rep(
+        "(def! depth (fn* (n) (if (= n 0) 0 (+ 1 (depth (- n 1))))))",
+        &env,
+    );
+    assert_eq!(mal_rust::rep("(depth 100000)", &env), "100000");
+}
+
+#[test]
+fn testing_vm_exceptions() {
+    let env = common::new_env(Backend::Vm);
+
+    assert_eq!(
+        mal_rust::rep(
+            "(try* (do (try* \"t1\" (catch* e \"c1\")) (throw \"e1\")) (catch* e \"c2\"))",
+            &env
+        ),
+        "\"c2\""
+    );
+    assert_eq!(
+        mal_rust::rep(
+            "(try* (try* (throw \"e1\") (catch* e (throw \"e2\"))) (catch* e e))",
+            &env
+        ),
+        "\"e2\""
+    );
+    assert_eq!(
+        mal_rust::rep(
+            "(try* (map throw (list \"my err\")) (catch* exc exc))",
+            &env
+        ),
+        "\"my err\""
+    );
+
+    // handlers of returned frames are gone
+    mal_rust::rep(
+        "(def! safe (fn* (f) (try* (f) (catch* e (str \"caught \" e)))))",
+        &env,
+    );
+    assert_eq!(
+        mal_rust::rep("(safe (fn* () (throw \"inner\")))", &env),
+        "\"caught inner\""
+    );
+    assert_eq!(
+        mal_rust::rep("(do (safe (fn* () 1)) (throw \"outer\"))", &env),
+        "Error: outer"
+    );
+}
+
+#[test]
+fn testing_vm_macros_defined_later() {
+    let env = common::new_env(Backend::Vm);
+
+    // the macro is defined after the call is compiled
+    assert_eq!(
+        mal_rust::rep(
+            "(do (defmacro! twice (fn* (x) `(do ~x ~x))) (def! n (atom 0)) (twice (swap! n + 1)) @n)",
+            &env
+        ),
+        "2"
+    );
+
+    mal_rust::rep("(def! use-later (fn* () (later 1)))", &env);
+    mal_rust::rep("(defmacro! later (fn* (x) `(+ ~x 1)))", &env);
+    assert_eq!(mal_rust::rep("(use-later)", &env), "2");
+
+    // locals shadowing macros are called
+    assert_eq!(
+        mal_rust::rep("(let* (twice (fn* (x) (* 2 x))) (twice 4))", &env),
+        "8"
+    );
+}
+
+#[test]
+fn testing_vm_namespaces_and_dynamic_vars() {
+    let env = common::new_env(Backend::Vm);
+
+    mal_rust::rep("(do (ns lib) (def! x 1) (def! *level* 0))", &env);
+    mal_rust::rep("(def! level (fn* () *level*))", &env);
+    mal_rust::rep("(ns user (:require [lib :as l]))", &env);
+
+    assert_eq!(mal_rust::rep("l/x", &env), "1");
+    assert_eq!(
+        mal_rust::rep(
+            "(try* (binding [l/*level* 2] (throw (l/level))) (catch* e [e (l/level)]))",
+            &env
+        ),
+        "[2 0]"
+    );
+}